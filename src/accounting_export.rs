@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::NaiveDate;
+use serde_derive::Deserialize;
+
+use crate::data_structures::LoveAdmin;
+use crate::dates::parse_date;
+use crate::matching::{normalise_name, team_from_product};
+
+pub enum ExportFormat {
+    Xero,
+    QuickBooks,
+    Qif,
+}
+
+// One line of the account code CSV (columns: product, team, account_code).
+// A product row wins over a team row, and a row with neither is the default.
+#[derive(Deserialize)]
+struct AccountCodeRow {
+    product: Option<String>,
+    team: Option<String>,
+    account_code: String,
+}
+
+pub struct AccountCodes {
+    by_product: HashMap<String, String>,
+    by_team: HashMap<String, String>,
+    default: Option<String>,
+}
+
+// A LoveAdmin payment as a bank-style transaction
+pub struct Transaction {
+    pub date: NaiveDate,
+    pub amount: f64,
+    pub payee: String,
+    pub player: String,
+    pub team: String,
+    pub product: String,
+    pub account_code: Option<String>,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<ExportFormat> {
+        match value.to_lowercase().as_str() {
+            "xero" => Some(ExportFormat::Xero),
+            "quickbooks" | "qb" => Some(ExportFormat::QuickBooks),
+            "qif" => Some(ExportFormat::Qif),
+            _ => None,
+        }
+    }
+}
+
+impl AccountCodes {
+    pub fn new() -> Self {
+        AccountCodes { by_product: HashMap::new(), by_team: HashMap::new(), default: None }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("Cannot read account codes {}: {}", path.display(), e))?;
+        let mut codes = AccountCodes::new();
+        for (index, row) in reader.deserialize::<AccountCodeRow>().enumerate() {
+            let row = row.map_err(|e| format!("Account codes line {}: {}", index + 2, e))?;
+            match (row.product, row.team) {
+                (Some(product), _) => {
+                    codes.by_product.insert(normalise_name(&product), row.account_code);
+                }
+                (None, Some(team)) => {
+                    codes.by_team.insert(normalise_name(&team), row.account_code);
+                }
+                (None, None) => codes.default = Some(row.account_code),
+            }
+        }
+        Ok(codes)
+    }
+
+    pub fn set_default(&mut self, account_code: &str) {
+        self.default = Some(account_code.to_string());
+    }
+
+    pub fn code_for(&self, product: &str, team: &str) -> Option<String> {
+        self.by_product
+            .get(&normalise_name(product))
+            .or_else(|| self.by_team.get(&normalise_name(team)))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+impl Transaction {
+    // Player and team go in the description so every line can be traced back to LoveAdmin
+    pub fn description(&self) -> String {
+        if self.team.is_empty() {
+            format!("{} - {}", self.player, self.product)
+        } else {
+            format!("{} - {} - {}", self.player, self.team, self.product)
+        }
+    }
+}
+
+// Paid LoveAdmin rows between the two dates (inclusive) as transactions, oldest first
+pub fn transactions(invoices: &[LoveAdmin], codes: &AccountCodes, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Vec<Transaction> {
+    let mut transactions: Vec<Transaction> = invoices
+        .iter()
+        .filter(|invoice| invoice.get_paid() > 0.0)
+        .filter_map(|invoice| parse_date(invoice.get_date()).map(|date| (date, invoice)))
+        .filter(|(date, _)| from.is_none_or(|from| *date >= from) && to.is_none_or(|to| *date <= to))
+        .map(|(date, invoice)| {
+            let team = team_from_product(invoice.get_product()).unwrap_or_default();
+            Transaction {
+                date,
+                amount: invoice.get_paid(),
+                payee: invoice.get_account_owner().trim().to_string(),
+                player: invoice.get_name().trim().to_string(),
+                account_code: codes.code_for(invoice.get_product(), &team),
+                team,
+                product: invoice.get_product().trim().to_string(),
+            }
+        })
+        .collect();
+    transactions.sort_by_key(|transaction| transaction.date);
+    transactions
+}
+
+fn csv_error(e: csv::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+// Xero's precoded bank statement import
+pub fn write_xero_csv<W: Write>(writer: W, transactions: &[Transaction]) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(["*Date", "*Amount", "Payee", "Description", "Reference", "Account Code"]).map_err(csv_error)?;
+    for transaction in transactions {
+        csv.write_record([
+            transaction.date.format("%d/%m/%Y").to_string(),
+            format!("{:.2}", transaction.amount),
+            transaction.payee.clone(),
+            transaction.description(),
+            transaction.player.clone(),
+            transaction.account_code.clone().unwrap_or_default(),
+        ])
+        .map_err(csv_error)?;
+    }
+    csv.flush()
+}
+
+// QuickBooks' four column bank upload (money in is a credit)
+pub fn write_quickbooks_csv<W: Write>(writer: W, transactions: &[Transaction]) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(["Date", "Description", "Credit", "Debit"]).map_err(csv_error)?;
+    for transaction in transactions {
+        let mut description = format!("{}: {}", transaction.payee, transaction.description());
+        if let Some(code) = &transaction.account_code {
+            description.push_str(&format!(" [{}]", code));
+        }
+        csv.write_record([transaction.date.format("%d/%m/%Y").to_string(), description, format!("{:.2}", transaction.amount), String::new()])
+            .map_err(csv_error)?;
+    }
+    csv.flush()
+}
+
+pub fn write_qif<W: Write>(mut writer: W, transactions: &[Transaction]) -> io::Result<()> {
+    writeln!(writer, "!Type:Bank")?;
+    for transaction in transactions {
+        writeln!(writer, "D{}", transaction.date.format("%d/%m/%Y"))?;
+        writeln!(writer, "T{:.2}", transaction.amount)?;
+        writeln!(writer, "P{}", transaction.payee)?;
+        writeln!(writer, "M{}", transaction.description())?;
+        if let Some(code) = &transaction.account_code {
+            writeln!(writer, "L{}", code)?;
+        }
+        writeln!(writer, "^")?;
+    }
+    writer.flush()
+}
+
+pub fn write_export<W: Write>(format: &ExportFormat, writer: W, transactions: &[Transaction]) -> io::Result<()> {
+    match format {
+        ExportFormat::Xero => write_xero_csv(writer, transactions),
+        ExportFormat::QuickBooks => write_quickbooks_csv(writer, transactions),
+        ExportFormat::Qif => write_qif(writer, transactions),
+    }
+}
+
+#[cfg(test)]
+mod accounting_export_tests {
+    use super::*;
+    use crate::fixtures;
+    use std::fs;
+
+    fn invoice(name: &str, product: &str, date: &str, paid: f64) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, product, date, 20.0);
+        invoice.set_account_owner("Jo Doe".to_string());
+        invoice.set_paid(paid);
+        invoice
+    }
+
+    fn sample() -> Vec<Transaction> {
+        let path = std::env::temp_dir().join(format!("codes-{}.csv", uuid::Uuid::new_v4()));
+        fs::write(&path, "product,team,account_code\nSummer Camp,,210\n,U12 Reds,201\n,,200\n").unwrap();
+        let codes = AccountCodes::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let invoices = vec![
+            invoice("Amy Doe", "U12 Reds (*)", "2023-10-01", 20.0),
+            invoice("Amy Doe", "Summer Camp", "2023-09-15", 50.0),
+            invoice("Ben Doe", "U14 Blues (*)", "2023-10-01", 20.0),
+            invoice("Cal Doe", "U14 Blues (*)", "2023-10-01", 0.0),
+            invoice("Dan Doe", "U14 Blues (*)", "2023-11-01", 20.0),
+        ];
+        transactions(&invoices, &codes, parse_date("2023-09-01"), parse_date("2023-10-31"))
+    }
+
+    #[test]
+    fn test_transactions_filter_and_code() {
+        let transactions = sample();
+        let summary: Vec<(&str, Option<&str>, f64)> =
+            transactions.iter().map(|t| (t.player.as_str(), t.account_code.as_deref(), t.amount)).collect();
+        assert_eq!(summary, vec![("Amy Doe", Some("210"), 50.0), ("Amy Doe", Some("201"), 20.0), ("Ben Doe", Some("200"), 20.0)]);
+        assert_eq!(transactions[1].description(), "Amy Doe - U12 Reds - U12 Reds (*)");
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let transactions = sample();
+
+        let mut xero = Vec::new();
+        write_export(&ExportFormat::Xero, &mut xero, &transactions).unwrap();
+        let xero = String::from_utf8(xero).unwrap();
+        assert!(xero.starts_with("*Date,*Amount,Payee,Description,Reference,Account Code\n"));
+        assert!(xero.contains("15/09/2023,50.00,Jo Doe,Amy Doe - Summer Camp,Amy Doe,210\n"));
+
+        let mut quickbooks = Vec::new();
+        write_export(&ExportFormat::QuickBooks, &mut quickbooks, &transactions).unwrap();
+        assert!(String::from_utf8(quickbooks).unwrap().contains("01/10/2023,Jo Doe: Ben Doe - U14 Blues - U14 Blues (*) [200],20.00,\n"));
+
+        let mut qif = Vec::new();
+        write_export(&ExportFormat::Qif, &mut qif, &transactions).unwrap();
+        let qif = String::from_utf8(qif).unwrap();
+        assert!(qif.starts_with("!Type:Bank\nD15/09/2023\nT50.00\nPJo Doe\nMAmy Doe - Summer Camp\nL210\n^\n"));
+        assert_eq!(qif.matches('^').count(), 3);
+    }
+}
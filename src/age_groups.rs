@@ -0,0 +1,263 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::data_structures::Wholegame;
+use crate::dates::{age_on, parse_date};
+use crate::matching::full_name;
+
+// FA age groups are fixed by a player's age on the cut-off date (31 August)
+// at the start of the season: anyone aged 10 on that day plays Under 11s.
+pub struct SeasonConfig {
+    // Calendar year the season starts in, e.g. 2023 for 2023/24
+    pub start_year: i32,
+    pub cut_off_month: u32,
+    pub cut_off_day: u32,
+    // Oldest youth age group; players past it are adults
+    pub oldest_youth: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AgeGroup {
+    Under(u32),
+    Adult,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AgeGroupIssue {
+    UnknownDateOfBirth,
+    UnreadableStoredAgeGroup,
+    UnknownTeamAgeGroup,
+    StoredMismatch { stored: AgeGroup, computed: AgeGroup },
+    PlayingUp { team: AgeGroup, computed: AgeGroup },
+    PlayingDown { team: AgeGroup, computed: AgeGroup },
+}
+
+pub struct AgeGroupCheck {
+    pub fan_id: String,
+    pub name: String,
+    pub team: String,
+    pub stored: String,
+    pub computed: Option<AgeGroup>,
+    pub issues: Vec<AgeGroupIssue>,
+}
+
+impl SeasonConfig {
+    pub fn new(start_year: i32) -> Self {
+        SeasonConfig { start_year, cut_off_month: 8, cut_off_day: 31, oldest_youth: 18 }
+    }
+
+    pub fn containing(date: NaiveDate) -> Self {
+        SeasonConfig::new(date.year()).season_of(date)
+    }
+
+    // The season a date falls in under this cut-off; the day after the cut-off
+    // starts the next one
+    pub fn season_of(mut self, date: NaiveDate) -> Self {
+        self.start_year = date.year();
+        if date <= self.cut_off_date() {
+            self.start_year -= 1;
+        }
+        self
+    }
+
+    pub fn cut_off_date(&self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.start_year, self.cut_off_month, self.cut_off_day)
+            .or_else(|| NaiveDate::from_ymd_opt(self.start_year, self.cut_off_month + 1, 1).and_then(|date| date.pred_opt()))
+            .expect("cut-off month must be 1-12")
+    }
+
+    pub fn age_group_for(&self, date_of_birth: NaiveDate) -> AgeGroup {
+        let group = age_on(date_of_birth, self.cut_off_date()) + 1;
+        if group > self.oldest_youth {
+            AgeGroup::Adult
+        } else {
+            AgeGroup::Under(group)
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{}/{:02}", self.start_year, (self.start_year + 1) % 100)
+    }
+}
+
+impl fmt::Display for AgeGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AgeGroup::Under(age) => write!(f, "U{}", age),
+            AgeGroup::Adult => write!(f, "Adult"),
+        }
+    }
+}
+
+impl AgeGroup {
+    // Accepts the spellings seen in Wholegame: "U15", "u15s", "Under 15", "Adult", "Open Age"
+    pub fn parse(value: &str) -> Option<AgeGroup> {
+        let lower = value.trim().to_lowercase();
+        if ["adult", "open age", "open", "senior", "seniors", "veterans"].contains(&lower.as_str()) {
+            return Some(AgeGroup::Adult);
+        }
+        let rest = lower.strip_prefix("under").or_else(|| lower.strip_prefix('u'))?;
+        let digits: String = rest.trim_start_matches([' ', '-']).chars().take_while(char::is_ascii_digit).collect();
+        let suffix = &rest.trim_start_matches([' ', '-'])[digits.len()..];
+        if digits.is_empty() || !(suffix.is_empty() || suffix == "s" || suffix == "'s") {
+            return None;
+        }
+        digits.parse().ok().map(AgeGroup::Under)
+    }
+
+    // Team names carry their age group as a word, e.g. "Wilpshire Wanderers U12 Reds"
+    // or "Wilpshire Wanderers Open Age"
+    pub fn from_team(team: &str) -> Option<AgeGroup> {
+        let words: Vec<&str> = team.split_whitespace().collect();
+        for (index, word) in words.iter().enumerate() {
+            if word.eq_ignore_ascii_case("under") {
+                if let Some(next) = words.get(index + 1) {
+                    return AgeGroup::parse(&format!("under {}", next));
+                }
+            }
+            if let Some(group) = AgeGroup::parse(word) {
+                return Some(group);
+            }
+        }
+        None
+    }
+
+    // Players may play up into an older age group but never down into a younger one
+    pub fn can_play_in(&self, team: AgeGroup) -> bool {
+        self.rank() <= team.rank()
+    }
+
+    fn rank(&self) -> u32 {
+        match self {
+            AgeGroup::Under(age) => *age,
+            AgeGroup::Adult => u32::MAX,
+        }
+    }
+}
+
+impl fmt::Display for AgeGroupIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AgeGroupIssue::UnknownDateOfBirth => write!(f, "date of birth missing or unreadable"),
+            AgeGroupIssue::UnreadableStoredAgeGroup => write!(f, "stored age group not recognised"),
+            AgeGroupIssue::UnknownTeamAgeGroup => write!(f, "team age group not recognised"),
+            AgeGroupIssue::StoredMismatch { stored, computed } => write!(f, "stored as {} but is {}", stored, computed),
+            AgeGroupIssue::PlayingUp { team, computed } => write!(f, "{} playing up in a {} team", computed, team),
+            AgeGroupIssue::PlayingDown { team, computed } => write!(f, "{} playing down in a {} team", computed, team),
+        }
+    }
+}
+
+pub fn check_player(player: &Wholegame, season: &SeasonConfig) -> AgeGroupCheck {
+    let computed = parse_date(player.get_date_of_birth()).map(|dob| season.age_group_for(dob));
+    let mut issues = Vec::new();
+
+    match computed {
+        None => issues.push(AgeGroupIssue::UnknownDateOfBirth),
+        Some(computed) => {
+            match AgeGroup::parse(player.get_age_group()) {
+                Some(stored) if stored != computed => issues.push(AgeGroupIssue::StoredMismatch { stored, computed }),
+                Some(_) => {}
+                None => issues.push(AgeGroupIssue::UnreadableStoredAgeGroup),
+            }
+            match AgeGroup::from_team(player.get_team()).map(|team| (team, team.rank().cmp(&computed.rank()))) {
+                None => issues.push(AgeGroupIssue::UnknownTeamAgeGroup),
+                Some((team, Ordering::Greater)) => issues.push(AgeGroupIssue::PlayingUp { team, computed }),
+                Some((team, Ordering::Less)) => issues.push(AgeGroupIssue::PlayingDown { team, computed }),
+                Some((_, Ordering::Equal)) => {}
+            }
+        }
+    }
+
+    AgeGroupCheck {
+        fan_id: player.get_fan_id().clone(),
+        name: full_name(player),
+        team: player.get_team().clone(),
+        stored: player.get_age_group().clone(),
+        computed,
+        issues,
+    }
+}
+
+// Players whose stored age group or team doesn't fit the season's computed age group
+pub fn validation_report(players: &[Wholegame], season: &SeasonConfig) -> Vec<AgeGroupCheck> {
+    players
+        .iter()
+        .map(|player| check_player(player, season))
+        .filter(|check| !check.issues.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod age_groups_tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        parse_date(value).unwrap()
+    }
+
+    fn player(date_of_birth: &str, age_group: &str, team: &str) -> Wholegame {
+        let mut player = Wholegame::new();
+        player.set_fan_id("1".to_string());
+        player.set_date_of_birth(date_of_birth.to_string());
+        player.set_age_group(age_group.to_string());
+        player.set_team(team.to_string());
+        player
+    }
+
+    #[test]
+    fn test_age_group_uses_age_on_cut_off() {
+        let season = SeasonConfig::new(2023);
+        assert_eq!(season.age_group_for(date("2012-08-31")), AgeGroup::Under(12));
+        assert_eq!(season.age_group_for(date("2012-09-01")), AgeGroup::Under(11));
+        assert_eq!(season.age_group_for(date("2005-09-04")), AgeGroup::Under(18));
+        assert_eq!(season.age_group_for(date("2005-08-31")), AgeGroup::Adult);
+        assert_eq!(season.label(), "2023/24");
+    }
+
+    #[test]
+    fn test_season_containing_date() {
+        assert_eq!(SeasonConfig::containing(date("2023-08-31")).start_year, 2022);
+        assert_eq!(SeasonConfig::containing(date("2023-09-01")).start_year, 2023);
+        let january = SeasonConfig { cut_off_month: 12, cut_off_day: 31, ..SeasonConfig::new(0) };
+        assert_eq!(january.season_of(date("2023-09-01")).start_year, 2022);
+    }
+
+    #[test]
+    fn test_parse_age_group_spellings() {
+        assert_eq!(AgeGroup::parse("U15"), Some(AgeGroup::Under(15)));
+        assert_eq!(AgeGroup::parse("u9s"), Some(AgeGroup::Under(9)));
+        assert_eq!(AgeGroup::parse("Under 11"), Some(AgeGroup::Under(11)));
+        assert_eq!(AgeGroup::parse("Open Age"), Some(AgeGroup::Adult));
+        assert_eq!(AgeGroup::parse("Unknown"), None);
+        assert_eq!(AgeGroup::from_team("Wilpshire Wanderers U12 Reds"), Some(AgeGroup::Under(12)));
+        assert_eq!(AgeGroup::from_team("Under 9 Blues"), Some(AgeGroup::Under(9)));
+        assert_eq!(AgeGroup::from_team("City Open Age"), Some(AgeGroup::Adult));
+        assert_eq!(AgeGroup::from_team("City Veterans"), Some(AgeGroup::Adult));
+        assert_eq!(AgeGroup::from_team("City Reserves"), None);
+    }
+
+    #[test]
+    fn test_report_flags_stale_age_group_and_playing_up_or_down() {
+        let season = SeasonConfig::new(2023);
+        let players = vec![
+            player("2012-10-01", "U11", "City U11 Reds"),
+            player("2012-10-01", "U10", "City U11 Reds"),
+            player("2012-10-01", "U11", "City U12 Blues"),
+            player("2012-10-01", "U11", "City U10 Whites"),
+            player("1990-01-01", "Adult", "City Open Age"),
+            player("1990-01-01", "Adult", "City Reserves"),
+        ];
+
+        let report = validation_report(&players, &season);
+
+        assert_eq!(report.len(), 4);
+        let eleven = AgeGroup::Under(11);
+        assert_eq!(report[0].issues, vec![AgeGroupIssue::StoredMismatch { stored: AgeGroup::Under(10), computed: eleven }]);
+        assert_eq!(report[1].issues, vec![AgeGroupIssue::PlayingUp { team: AgeGroup::Under(12), computed: eleven }]);
+        assert_eq!(report[2].issues, vec![AgeGroupIssue::PlayingDown { team: AgeGroup::Under(10), computed: eleven }]);
+        assert_eq!(report[3].issues, vec![AgeGroupIssue::UnknownTeamAgeGroup]);
+    }
+}
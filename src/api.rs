@@ -0,0 +1,451 @@
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use crate::compliance;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{
+    get_all_loveadmin, get_all_loveadmin_with_ids, get_all_wholegame, get_import_batches, get_loveadmin_by_id, get_wholegame_by_fan_id,
+};
+use crate::http::{Request, Response};
+use crate::import::{self, ImportError, LOVEADMIN_SOURCE, WHOLEGAME_SOURCE};
+use crate::matching::{full_name, invoice_month, invoices_for_player, normalise_name, outstanding_for_player, team_from_product, teams_match};
+use crate::reconciliation::{self, ExceptionFilter, ExceptionKind, TrackedException};
+use crate::validation::{InvalidRows, ValidationReport};
+use crate::web::WebOptions;
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+// Listed at GET /api so whoever wires up the website can discover the API
+const ENDPOINTS: &[(&str, &str, &str)] = &[
+    ("GET", "/api/players", "Wholegame players. Filters: team, q (name or FAN ID), status, suspended"),
+    ("GET", "/api/players/{fan_id}", "One player with their LoveAdmin invoices and outstanding balance"),
+    ("GET", "/api/invoices", "LoveAdmin invoices. Filters: team, q (member or account owner), month (YYYY-MM), fan_id, outstanding"),
+    ("GET", "/api/invoices/{id}", "One LoveAdmin invoice"),
+    ("GET", "/api/imports", "Import batches, newest first"),
+    ("POST", "/api/imports/loveadmin?file={name}", "Replace LoveAdmin data with the CSV export in the request body"),
+    ("POST", "/api/imports/wholegame?file={name}", "Replace Wholegame data with the XLSX export in the request body"),
+    ("GET", "/api/reconciliation", "Reconciliation exceptions with their follow-up status. Filters: kind, team, q, assignee, all (include resolved and ignored)"),
+    ("POST", "/api/reconciliation", "Re-run reconciliation over the stored data and return the exceptions, with the same filters"),
+    ("GET", "/api/compliance", "Safeguarding compliance per player. Filters: team, rag (red, amber or green)"),
+];
+
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        ApiError { status, message: message.into() }
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApiError::new(500, format!("database error: {}", e))
+    }
+}
+
+type ApiResult = Result<Response, ApiError>;
+
+fn json_response(status: u16, value: &Value) -> Response {
+    Response::new(status, "application/json", value.to_string().into_bytes())
+}
+
+fn number_param(request: &Request, name: &str, default: usize) -> Result<usize, ApiError> {
+    match request.param(name) {
+        Some(value) => value.parse().map_err(|_| ApiError::new(400, format!("{} must be a whole number, got '{}'", name, value))),
+        None => Ok(default),
+    }
+}
+
+fn bool_param(request: &Request, name: &str) -> Result<Option<bool>, ApiError> {
+    match request.param(name).map(str::to_lowercase).as_deref() {
+        None => Ok(None),
+        Some("true" | "yes" | "1") => Ok(Some(true)),
+        Some("false" | "no" | "0") => Ok(Some(false)),
+        Some(value) => Err(ApiError::new(400, format!("{} must be true or false, got '{}'", name, value))),
+    }
+}
+
+// Every list endpoint pages the same way: ?offset=0&limit=50
+fn paginate(request: &Request, items: Vec<Value>) -> ApiResult {
+    let offset = number_param(request, "offset", 0)?;
+    let limit = number_param(request, "limit", DEFAULT_LIMIT)?;
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::new(400, format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let total = items.len();
+    let page: Vec<Value> = items.into_iter().skip(offset).take(limit).collect();
+    Ok(json_response(200, &json!({ "total": total, "offset": offset, "limit": limit, "items": page })))
+}
+
+fn contains(haystack: &str, needle: &str) -> bool {
+    normalise_name(haystack).contains(&normalise_name(needle))
+}
+
+pub fn player_json(player: &Wholegame) -> Value {
+    json!({
+        "fan_id": player.get_fan_id(),
+        "name": full_name(player),
+        "first_names": player.get_first_names(),
+        "surname": player.get_surname(),
+        "date_of_birth": player.get_date_of_birth(),
+        "age_group": player.get_age_group(),
+        "gender": player.get_gender(),
+        "suspended": player.is_suspended(),
+        "team": player.get_team(),
+        "date_submitted": player.get_date_submitted(),
+        "date_registered": player.get_date_registered(),
+        "registration_expiry": player.get_registration_expiry(),
+        "registration_status": player.get_registration_status(),
+        "email_address": player.get_email_address(),
+        "parent_carer_name": player.get_parent_carer_name(),
+        "parent_carer_email_address": player.get_parent_carer_email_address(),
+        "emergency_contact": player.get_emergency_contact(),
+        "emergency_contact_phone_number": player.get_emergency_contact_phone_number(),
+        "other_clubs": player.get_other_clubs(),
+        "consent_given": player.is_consent_given(),
+        "contract_status": player.get_contract_status(),
+        "photo_uploaded_date": player.get_photo_uploaded_date(),
+    })
+}
+
+pub fn invoice_json(id: Option<i64>, invoice: &LoveAdmin) -> Value {
+    json!({
+        "id": id,
+        "name": invoice.get_name(),
+        "account_owner": invoice.get_account_owner(),
+        "product": invoice.get_product(),
+        "team": team_from_product(invoice.get_product()),
+        "date": invoice.get_date(),
+        "invoiced": invoice.get_invoiced(),
+        "paid": invoice.get_paid(),
+        "pending": invoice.get_pending(),
+        "outstanding": invoice.get_outstanding(),
+        "failed": invoice.get_failed(),
+        "days_overdue": invoice.get_days_overdue(),
+        "last_reminder_sent": invoice.get_last_reminder_sent(),
+    })
+}
+
+fn list_players(conn: &Connection, request: &Request) -> ApiResult {
+    let suspended = bool_param(request, "suspended")?;
+    let items = get_all_wholegame(conn)?
+        .iter()
+        .filter(|player| request.param("team").is_none_or(|team| teams_match(player.get_team(), team)))
+        .filter(|player| request.param("q").is_none_or(|q| contains(&full_name(player), q) || player.get_fan_id().contains(q)))
+        .filter(|player| request.param("status").is_none_or(|status| player.get_registration_status().eq_ignore_ascii_case(status)))
+        .filter(|player| suspended.is_none_or(|suspended| player.is_suspended() == suspended))
+        .map(player_json)
+        .collect();
+    paginate(request, items)
+}
+
+fn get_player(conn: &Connection, fan_id: &str) -> ApiResult {
+    let player = get_wholegame_by_fan_id(conn, fan_id)?.ok_or_else(|| ApiError::new(404, format!("No player with FAN ID {}", fan_id)))?;
+    let invoices = get_all_loveadmin(conn)?;
+    let mut value = player_json(&player);
+    value["invoices"] = invoices_for_player(&player, &invoices).into_iter().map(|invoice| invoice_json(None, invoice)).collect();
+    value["outstanding"] = json!(outstanding_for_player(&player, &invoices));
+    Ok(json_response(200, &value))
+}
+
+fn list_invoices(conn: &Connection, request: &Request) -> ApiResult {
+    let outstanding = bool_param(request, "outstanding")?;
+    let month = match request.param("month") {
+        Some(month) => {
+            let parsed = month.split_once('-').and_then(|(year, month)| Some((year.parse::<i32>().ok()?, month.parse::<u32>().ok()?)));
+            Some(parsed.ok_or_else(|| ApiError::new(400, format!("month must be YYYY-MM, got '{}'", month)))?)
+        }
+        None => None,
+    };
+    let player = match request.param("fan_id") {
+        Some(fan_id) => Some(get_wholegame_by_fan_id(conn, fan_id)?.ok_or_else(|| ApiError::new(404, format!("No player with FAN ID {}", fan_id)))?),
+        None => None,
+    };
+    let player_name = player.as_ref().map(|player| normalise_name(&full_name(player)));
+
+    let items = get_all_loveadmin_with_ids(conn)?
+        .iter()
+        .filter(|(_, invoice)| {
+            request.param("team").is_none_or(|team| {
+                team_from_product(invoice.get_product()).is_some_and(|invoice_team| teams_match(team, &invoice_team))
+            })
+        })
+        .filter(|(_, invoice)| request.param("q").is_none_or(|q| contains(invoice.get_name(), q) || contains(invoice.get_account_owner(), q)))
+        .filter(|(_, invoice)| month.is_none_or(|month| invoice_month(invoice) == Some(month)))
+        .filter(|(_, invoice)| player_name.as_ref().is_none_or(|name| &normalise_name(invoice.get_name()) == name))
+        .filter(|(_, invoice)| outstanding.is_none_or(|outstanding| (invoice.get_outstanding() > 0.0) == outstanding))
+        .map(|(id, invoice)| invoice_json(Some(*id), invoice))
+        .collect();
+    paginate(request, items)
+}
+
+fn get_invoice(conn: &Connection, id: &str) -> ApiResult {
+    let id: i64 = id.parse().map_err(|_| ApiError::new(404, format!("No invoice {}", id)))?;
+    let invoice = get_loveadmin_by_id(conn, id)?.ok_or_else(|| ApiError::new(404, format!("No invoice {}", id)))?;
+    Ok(json_response(200, &invoice_json(Some(id), &invoice)))
+}
+
+fn list_imports(conn: &Connection, request: &Request) -> ApiResult {
+    let items = get_import_batches(conn)?
+        .iter()
+        .map(|batch| {
+            json!({
+                "id": batch.id,
+                "source": batch.source,
+                "file_name": batch.file_name,
+                "imported_at": batch.imported_at,
+                "row_count": batch.row_count,
+                "format_version": batch.format_version,
+            })
+        })
+        .collect();
+    paginate(request, items)
+}
+
+fn problems_json(report: &ValidationReport) -> Value {
+    json!(report
+        .diagnostics
+        .iter()
+        .map(|diagnostic| json!({ "row": diagnostic.row, "column": diagnostic.column, "value": diagnostic.value, "problem": diagnostic.problem }))
+        .collect::<Vec<Value>>())
+}
+
+fn run_import(conn: &Connection, request: &Request, source: &str, options: &WebOptions) -> ApiResult {
+    let file_name = request.param("file").unwrap_or("upload");
+    let invalid_rows = match request.param("invalid") {
+        Some(value) => InvalidRows::parse(value).ok_or_else(|| ApiError::new(400, format!("invalid must be reject or skip, got '{}'", value)))?,
+        None => InvalidRows::Reject,
+    };
+    match import::import_file(conn, source, &request.body, file_name, &options.aliases, invalid_rows) {
+        Ok(summary) => {
+            reconciliation::refresh(conn, options.reference_date, options.photo_max_years)?;
+            Ok(json_response(
+                201,
+                &json!({
+                    "batch": summary.batch,
+                    "source": source,
+                    "file_name": file_name,
+                    "rows": summary.rows,
+                    "format_version": summary.schema.version_label(),
+                    "warnings": summary.schema.warnings(),
+                    "skipped": problems_json(&summary.validation),
+                }),
+            ))
+        }
+        Err(ImportError::Rejected(report)) => Ok(json_response(
+            422,
+            &json!({ "error": format!("Cannot import {}: nothing imported, {}", file_name, report.summary()), "problems": problems_json(&report) }),
+        )),
+        Err(ImportError::Invalid(e)) => Err(ApiError::new(400, format!("Cannot import {}: {}", file_name, e))),
+        Err(ImportError::Database(e)) => Err(e.into()),
+    }
+}
+
+// GET reads what the last run found; imports and POST run reconciliation again
+fn list_exceptions(request: &Request, tracked: Vec<TrackedException>, options: &WebOptions) -> ApiResult {
+    let kind = match request.param("kind") {
+        Some(code) => Some(ExceptionKind::parse(code).ok_or_else(|| ApiError::new(400, format!("Unknown exception kind '{}'", code)))?),
+        None => None,
+    };
+    let filter = ExceptionFilter {
+        kind,
+        team: request.param("team").map(String::from),
+        search: request.param("q").map(String::from),
+        assignee: request.param("assignee").map(String::from),
+        include_closed: bool_param(request, "all")?.unwrap_or(false),
+    };
+    let items = tracked
+        .iter()
+        .filter(|tracked| filter.matches(tracked, options.reference_date))
+        .map(|tracked| {
+            let exception = &tracked.exception;
+            json!({
+                "id": tracked.id,
+                "key": exception.key(),
+                "kind": exception.kind.code(),
+                "description": exception.kind.to_string(),
+                "team": exception.team,
+                "name": exception.name,
+                "fan_id": exception.fan_id,
+                "detail": exception.detail,
+                "month": exception.month.map(|(year, month)| format!("{:04}-{:02}", year, month)),
+                "status": tracked.status.code(),
+                "ignored_until": tracked.status.until().map(|until| until.format("%Y-%m-%d").to_string()),
+                "assignee": tracked.assignee,
+                "notes": tracked.notes,
+                "first_seen": tracked.first_seen,
+                "new": tracked.is_new,
+            })
+        })
+        .collect();
+    paginate(request, items)
+}
+
+fn list_compliance(conn: &Connection, request: &Request, options: &WebOptions) -> ApiResult {
+    let results = compliance::check_all(&get_all_wholegame(conn)?, &get_all_loveadmin(conn)?, options.reference_date, options.photo_max_years);
+    let items = results
+        .iter()
+        .filter(|result| request.param("team").is_none_or(|team| teams_match(&result.team, team)))
+        .filter(|result| request.param("rag").is_none_or(|rag| result.rag().to_string().eq_ignore_ascii_case(rag)))
+        .map(|result| {
+            json!({
+                "fan_id": result.fan_id,
+                "name": result.name,
+                "team": result.team,
+                "rag": result.rag().to_string().to_lowercase(),
+                "issues": result.issues.iter().map(|issue| issue.to_string()).collect::<Vec<String>>(),
+            })
+        })
+        .collect();
+    paginate(request, items)
+}
+
+fn index() -> ApiResult {
+    let endpoints: Vec<Value> =
+        ENDPOINTS.iter().map(|(method, path, description)| json!({ "method": method, "path": path, "description": description })).collect();
+    Ok(json_response(200, &json!({ "pagination": "offset and limit (default 50, at most 500)", "endpoints": endpoints })))
+}
+
+// The access token is checked by the web server before anything is routed here
+pub fn unauthorised() -> Response {
+    json_response(401, &json!({ "error": "Missing or wrong access token" }))
+}
+
+fn route(conn: &Connection, request: &Request, options: &WebOptions) -> ApiResult {
+    let path = request.path.trim_end_matches('/');
+    match (request.method.as_str(), path) {
+        ("GET", "/api") => index(),
+        ("GET", "/api/players") => list_players(conn, request),
+        ("GET", "/api/invoices") => list_invoices(conn, request),
+        ("GET", "/api/imports") => list_imports(conn, request),
+        ("POST", "/api/imports/loveadmin") => run_import(conn, request, LOVEADMIN_SOURCE, options),
+        ("POST", "/api/imports/wholegame") => run_import(conn, request, WHOLEGAME_SOURCE, options),
+        ("GET", "/api/reconciliation") => list_exceptions(request, reconciliation::tracked(conn)?, options),
+        ("POST", "/api/reconciliation") => list_exceptions(request, reconciliation::refresh(conn, options.reference_date, options.photo_max_years)?, options),
+        ("GET", "/api/compliance") => list_compliance(conn, request, options),
+        ("GET", _) if path.starts_with("/api/players/") => get_player(conn, &path["/api/players/".len()..]),
+        ("GET", _) if path.starts_with("/api/invoices/") => get_invoice(conn, &path["/api/invoices/".len()..]),
+        _ => Err(ApiError::new(404, format!("No endpoint {} {}", request.method, request.path))),
+    }
+}
+
+pub fn handle(conn: &Connection, request: &Request, options: &WebOptions) -> Response {
+    route(conn, request, options).unwrap_or_else(|e| json_response(e.status, &json!({ "error": e.message })))
+}
+
+#[cfg(test)]
+mod api_tests {
+    use super::*;
+    use crate::database::{create_tables, insert_wholegame, setup_database};
+    use crate::dates::parse_date;
+    use crate::export_schema::ColumnAliases;
+
+    fn database() -> Connection {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        for (first, surname, fan_id, team) in [("Amy", "Doe", "100", "Wanderers U12 Reds"), ("Ben", "Roe", "200", "Wanderers U14 Blues")] {
+            let mut player = Wholegame::new();
+            player.set_first_names(first.to_string());
+            player.set_surname(surname.to_string());
+            player.set_fan_id(fan_id.to_string());
+            player.set_team(team.to_string());
+            player.set_registration_status("Registered".to_string());
+            player.set_consent_given(true);
+            player.set_photo_uploaded_date(Some("2023-09-01".to_string()));
+            insert_wholegame(&conn, &player).unwrap();
+        }
+        conn
+    }
+
+    fn options() -> WebOptions {
+        WebOptions {
+            reference_date: parse_date("2023-10-15").unwrap(),
+            photo_max_years: 3,
+            access_token: None,
+            aliases: ColumnAliases::new(),
+            hosts: Vec::new(),
+            form_token: String::new(),
+        }
+    }
+
+    fn call(conn: &Connection, request: Request) -> (u16, Value) {
+        let response = handle(conn, &request, &options());
+        assert_eq!(response.content_type, "application/json");
+        (response.status, serde_json::from_slice(&response.body).unwrap())
+    }
+
+    fn get(conn: &Connection, url: &str) -> (u16, Value) {
+        call(conn, Request::new("GET", url, Vec::new()))
+    }
+
+    #[test]
+    fn test_players_invoices_and_imports() {
+        let conn = database();
+        let csv = "Name,Account Owner,Product,Date,Invoiced,Paid,Outstanding\n\
+                   Amy Doe,Jo Doe,U12 Reds (*),2023-09-01,20,20,0\nAmy Doe,Jo Doe,U12 Reds (*),2023-10-01,20,0,20\nCat Poe,Al Poe,U14 Blues (*),2023-10-01,20,20,0\n";
+        let bad_row = format!("{}Dan Poe,Al Poe,U14 Blues (*),2023-10-01,20,20,5\n", csv);
+        let (status, body) = call(&conn, Request::new("POST", "/api/imports/loveadmin?file=october.csv", bad_row.as_bytes().to_vec()));
+        assert_eq!(status, 422);
+        assert_eq!(body["problems"][0]["row"], 5);
+        let (status, body) = call(&conn, Request::new("POST", "/api/imports/loveadmin?file=october.csv&invalid=skip", bad_row.as_bytes().to_vec()));
+        assert_eq!(status, 201);
+        assert_eq!(body["rows"], 3);
+        assert_eq!(body["skipped"][0]["column"], "Invoiced");
+        let (status, body) = call(&conn, Request::new("POST", "/api/imports/wholegame", b"junk".to_vec()));
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().starts_with("Cannot import upload"));
+        assert_eq!(get(&conn, "/api/imports").1["items"][0]["file_name"], "october.csv");
+
+        let (_, body) = get(&conn, "/api/players?team=U12+Reds");
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["items"][0]["name"], "Amy Doe");
+        assert_eq!(get(&conn, "/api/players?q=roe").1["items"][0]["fan_id"], "200");
+        assert_eq!(get(&conn, "/api/players?limit=1&offset=1").1["items"].as_array().unwrap().len(), 1);
+        assert_eq!(get(&conn, "/api/players?limit=0").0, 400);
+        assert_eq!(get(&conn, "/api/players?suspended=maybe").0, 400);
+
+        let (_, body) = get(&conn, "/api/players/100");
+        assert_eq!(body["invoices"].as_array().unwrap().len(), 2);
+        assert_eq!(body["outstanding"], 20.0);
+        assert_eq!(get(&conn, "/api/players/999").0, 404);
+
+        let (_, body) = get(&conn, "/api/invoices?month=2023-10&outstanding=true");
+        assert_eq!(body["total"], 1);
+        let id = body["items"][0]["id"].as_i64().unwrap();
+        assert_eq!(get(&conn, &format!("/api/invoices/{}", id)).1["team"], "U12 Reds");
+        assert_eq!(get(&conn, "/api/invoices?fan_id=100").1["total"], 2);
+        assert_eq!(get(&conn, "/api/invoices?team=Wanderers+U14+Blues").1["total"], 1);
+        assert_eq!(get(&conn, "/api/invoices?month=October").0, 400);
+        assert_eq!(get(&conn, "/api/invoices/abc").0, 404);
+    }
+
+    #[test]
+    fn test_reconciliation_compliance_and_auth() {
+        let conn = database();
+        let csv = "Name,Product,Date,Invoiced\nAmy Doe,U12 Reds (*),2023-10-01,20\n";
+        call(&conn, Request::new("POST", "/api/imports/loveadmin", csv.as_bytes().to_vec()));
+
+        let (_, body) = get(&conn, "/api/reconciliation?kind=not-in-loveadmin");
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["items"][0]["fan_id"], "200");
+        assert_eq!(body["items"][0]["status"], "open");
+        assert_eq!(body["items"][0]["new"], true);
+        assert_eq!(get(&conn, "/api/reconciliation?kind=not-in-loveadmin").1, body);
+        let (status, rerun) = call(&conn, Request::new("POST", "/api/reconciliation?kind=not-in-loveadmin", Vec::new()));
+        assert_eq!(status, 200);
+        assert_eq!(rerun["items"][0]["id"], body["items"][0]["id"]);
+        assert_eq!(rerun["items"][0]["new"], false);
+        assert_eq!(get(&conn, "/api/reconciliation?kind=bogus").0, 400);
+
+        let (_, body) = get(&conn, "/api/compliance?rag=green");
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["items"][0]["issues"], json!([]));
+
+        assert_eq!(get(&conn, "/api").1["endpoints"].as_array().unwrap().len(), ENDPOINTS.len());
+        assert_eq!(get(&conn, "/api/nothing").0, 404);
+    }
+}
@@ -0,0 +1,184 @@
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use crate::api::{invoice_json, player_json};
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{get_audit_entries, insert_audit_entry, redact_audit_entries, AuditEntry, AuditFilter, ExceptionRecord};
+
+// Who ran the tool, as far as the operating system says
+pub fn os_user() -> String {
+    std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_string())
+}
+
+// Appends an entry to the audit log
+pub fn record(conn: &Connection, action: &str, entity: &str, before: Option<&Value>, after: Option<&Value>) -> rusqlite::Result<i64> {
+    insert_audit_entry(
+        conn,
+        &AuditEntry {
+            timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            os_user: os_user(),
+            action: action.to_string(),
+            entity: entity.to_string(),
+            before: before.map(Value::to_string),
+            after: after.map(Value::to_string),
+        },
+    )
+}
+
+// Erasure and retention can't leave the person's details behind in the log, so
+// the values recorded against their entities are replaced. The entries stay,
+// so the log still shows what was done and when.
+pub fn redact(conn: &Connection, entities: &[String]) -> rusqlite::Result<usize> {
+    let mut redacted = 0;
+    for entity in entities {
+        redacted += redact_audit_entries(conn, entity)?;
+    }
+    Ok(redacted)
+}
+
+// Every entry recorded against the entities, oldest first
+pub fn history(conn: &Connection, entities: &[String]) -> rusqlite::Result<Vec<AuditEntry>> {
+    let mut entries = Vec::new();
+    for entity in entities {
+        let filter = AuditFilter { entity: Some(entity.clone()), limit: i64::MAX as usize, ..AuditFilter::default() };
+        entries.extend(get_audit_entries(conn, &filter)?.into_iter().rev());
+    }
+    entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    Ok(entries)
+}
+
+pub fn player_entity(fan_id: &str) -> String {
+    format!("wholegame:{}", fan_id.trim())
+}
+
+pub fn invoice_entity(id: i64) -> String {
+    format!("loveadmin:{}", id)
+}
+
+pub fn exception_entity(key: &str) -> String {
+    format!("exception:{}", key)
+}
+
+pub fn record_player(conn: &Connection, action: &str, before: Option<&Wholegame>, after: Option<&Wholegame>) -> rusqlite::Result<i64> {
+    let fan_id = after.or(before).map(|player| player.get_fan_id().as_str()).unwrap_or_default();
+    record(conn, action, &player_entity(fan_id), before.map(player_json).as_ref(), after.map(player_json).as_ref())
+}
+
+pub fn record_invoice(conn: &Connection, action: &str, id: i64, before: Option<&LoveAdmin>, after: Option<&LoveAdmin>) -> rusqlite::Result<i64> {
+    let value = |invoice: &LoveAdmin| invoice_json(Some(id), invoice);
+    record(conn, action, &invoice_entity(id), before.map(value).as_ref(), after.map(value).as_ref())
+}
+
+// Only the workflow fields; the rest is regenerated by each reconciliation run
+pub fn record_exception(conn: &Connection, action: &str, before: Option<&ExceptionRecord>, after: Option<&ExceptionRecord>) -> rusqlite::Result<i64> {
+    let key = after.or(before).map(|record| record.key.as_str()).unwrap_or_default();
+    let value = |record: &ExceptionRecord| {
+        json!({
+            "status": record.status,
+            "ignored_until": record.ignored_until,
+            "assignee": record.assignee,
+            "notes": record.notes,
+        })
+    };
+    record(conn, action, &exception_entity(key), before.map(value).as_ref(), after.map(value).as_ref())
+}
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+// One line describing an entry: the fields that changed for an update,
+// otherwise whichever value was recorded
+pub fn describe(entry: &AuditEntry) -> String {
+    let parse = |value: &Option<String>| value.as_deref().and_then(|text| serde_json::from_str::<Value>(text).ok());
+    match (parse(&entry.before), parse(&entry.after)) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let changes: Vec<String> = after
+                .iter()
+                .filter(|(key, value)| before.get(*key) != Some(*value))
+                .map(|(key, value)| match before.get(key) {
+                    Some(old) => format!("{}: {} -> {}", key, show(old), show(value)),
+                    None => format!("{}: {}", key, show(value)),
+                })
+                .collect();
+            if changes.is_empty() { "no change".to_string() } else { changes.join("; ") }
+        }
+        (_, Some(after)) => after.to_string(),
+        (Some(before), None) => before.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use super::*;
+    use crate::database::{
+        create_tables, delete_wholegame, get_all_loveadmin_with_ids, get_audit_entries, insert_loveadmin, insert_wholegame, reopen_exception,
+        resolve_exception, setup_database, update_loveadmin, AuditFilter,
+    };
+
+    fn entries(conn: &Connection, filter: AuditFilter) -> Vec<AuditEntry> {
+        get_audit_entries(conn, &AuditFilter { limit: 100, ..filter }).unwrap()
+    }
+
+    #[test]
+    fn test_changes_are_recorded_and_queried() {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        let mut player = Wholegame::new();
+        player.set_first_names("Amy".to_string());
+        player.set_surname("Doe".to_string());
+        player.set_fan_id("100".to_string());
+        insert_wholegame(&conn, &player).unwrap();
+        delete_wholegame(&conn, "100").unwrap();
+        // Nothing to delete, nothing recorded
+        delete_wholegame(&conn, "100").unwrap();
+
+        let mut invoice = LoveAdmin::new();
+        invoice.set_name("Amy Doe".to_string());
+        invoice.set_invoiced(20.0);
+        insert_loveadmin(&conn, &invoice).unwrap();
+        let id = get_all_loveadmin_with_ids(&conn).unwrap()[0].0;
+        invoice.set_paid(20.0);
+        update_loveadmin(&conn, id, &invoice).unwrap();
+
+        resolve_exception(&conn, "not-in-loveadmin:100:u12 reds", "2023-10-01 10:00:00").unwrap();
+        reopen_exception(&conn, "not-in-loveadmin:100:u12 reds").unwrap();
+
+        let all = entries(&conn, AuditFilter::default());
+        let actions: Vec<&str> = all.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, vec!["reopen", "resolve", "update", "insert", "delete", "insert"]);
+        assert!(all.iter().all(|entry| entry.os_user == os_user()));
+        assert_eq!(describe(&all[2]), "paid: 0.0 -> 20.0");
+
+        let players = entries(&conn, AuditFilter { entity: Some("wholegame".to_string()), ..AuditFilter::default() });
+        assert_eq!(players.len(), 2);
+        assert!(players.iter().all(|entry| entry.entity == "wholegame:100"));
+        assert_eq!(entries(&conn, AuditFilter { entity: Some(invoice_entity(id)), ..AuditFilter::default() }).len(), 2);
+        assert_eq!(entries(&conn, AuditFilter { action: Some("delete".to_string()), ..AuditFilter::default() }).len(), 1);
+        assert_eq!(entries(&conn, AuditFilter { search: Some("Amy".to_string()), ..AuditFilter::default() }).len(), 4);
+        assert!(entries(&conn, AuditFilter { since: Some("2999-01-01".to_string()), ..AuditFilter::default() }).is_empty());
+        assert_eq!(get_audit_entries(&conn, &AuditFilter { limit: 1, ..AuditFilter::default() }).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_entries_are_never_rewritten() {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        let mut player = Wholegame::new();
+        player.set_fan_id("100".to_string());
+        player.set_first_names("Amy".to_string());
+        insert_wholegame(&conn, &player).unwrap();
+        let first = entries(&conn, AuditFilter::default());
+        delete_wholegame(&conn, "100").unwrap();
+        record(&conn, "import", "wholegame", None, Some(&json!({ "rows": 1 }))).unwrap();
+
+        let all = entries(&conn, AuditFilter::default());
+        assert_eq!(all.len(), 3);
+        assert_eq!((all[2].entity.as_str(), all[2].after.as_deref()), (player_entity("100").as_str(), first[0].after.as_deref()));
+        assert_eq!(entries(&conn, AuditFilter { search: Some("Amy".to_string()), ..AuditFilter::default() }).len(), 2);
+    }
+}
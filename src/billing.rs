@@ -0,0 +1,124 @@
+use std::fmt;
+
+use chrono::NaiveDate;
+
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::matching::{full_name, invoice_month, invoices_for_player, latest_invoice_month};
+use crate::registration::is_registered;
+
+#[derive(Debug, PartialEq)]
+pub enum BillingAction {
+    // For the disciplinary meeting: stop billing or lift the suspension
+    SuspendedButBilled,
+    // For the finance meeting: chase payment or suspend
+    FailedPaymentStillRegistered,
+}
+
+pub struct BillingCheck {
+    pub fan_id: String,
+    pub name: String,
+    pub team: String,
+    pub action: BillingAction,
+    pub invoices: usize,
+    pub invoiced: f64,
+    pub outstanding: f64,
+    pub failed: i32,
+}
+
+impl fmt::Display for BillingAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BillingAction::SuspendedButBilled => write!(f, "suspended but still being billed"),
+            BillingAction::FailedPaymentStillRegistered => write!(f, "failed payments but registered and not suspended"),
+        }
+    }
+}
+
+// An invoice is active while it's from the current billing month or still has money to collect
+fn is_active(invoice: &LoveAdmin, latest_month: Option<(i32, u32)>) -> bool {
+    let current = latest_month.is_some() && invoice_month(invoice) == latest_month && invoice.get_invoiced() > 0.0;
+    current || invoice.get_pending() > 0.0 || invoice.get_outstanding() > 0.0
+}
+
+fn check(player: &Wholegame, action: BillingAction, invoices: &[&LoveAdmin]) -> BillingCheck {
+    BillingCheck {
+        fan_id: player.get_fan_id().clone(),
+        name: full_name(player),
+        team: player.get_team().clone(),
+        action,
+        invoices: invoices.len(),
+        invoiced: invoices.iter().fold(0.0, |total, invoice| total + invoice.get_invoiced()),
+        outstanding: invoices.iter().fold(0.0, |total, invoice| total + invoice.get_outstanding()),
+        failed: invoices.iter().map(|invoice| invoice.get_failed()).sum(),
+    }
+}
+
+pub fn cross_check(players: &[Wholegame], invoices: &[LoveAdmin], reference_date: NaiveDate) -> Vec<BillingCheck> {
+    let latest_month = latest_invoice_month(invoices);
+    let mut results = Vec::new();
+
+    for player in players {
+        let linked = invoices_for_player(player, invoices);
+        if player.is_suspended() {
+            let active: Vec<&LoveAdmin> = linked.iter().copied().filter(|invoice| is_active(invoice, latest_month)).collect();
+            if !active.is_empty() {
+                results.push(check(player, BillingAction::SuspendedButBilled, &active));
+            }
+        } else if is_registered(player, reference_date) {
+            let failed: Vec<&LoveAdmin> = linked.iter().copied().filter(|invoice| invoice.get_failed() > 0).collect();
+            if !failed.is_empty() {
+                results.push(check(player, BillingAction::FailedPaymentStillRegistered, &failed));
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod billing_tests {
+    use super::*;
+    use crate::dates::parse_date;
+    use crate::fixtures;
+
+    fn player(name: &str, suspended: bool, status: &str) -> Wholegame {
+        let mut player = fixtures::player(name, "");
+        player.set_suspended(suspended);
+        player.set_registration_status(status.to_string());
+        player
+    }
+
+    fn invoice(name: &str, date: &str, outstanding: f64, failed: i32) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, "", date, 20.0);
+        invoice.set_paid(20.0 - outstanding);
+        invoice.set_outstanding(outstanding);
+        invoice.set_failed(failed);
+        invoice
+    }
+
+    #[test]
+    fn test_cross_check_lists_suspended_billing_and_failed_payments() {
+        let players = vec![
+            player("Sam", true, "Registered"),
+            player("Sue", true, "Registered"),
+            player("Fay", false, "Registered"),
+            player("Fin", false, "Expired"),
+            player("Pat", false, "Registered"),
+        ];
+        let invoices = vec![
+            invoice("Sam Doe", "2024-02-01", 0.0, 0),
+            invoice("Sue Doe", "2024-01-01", 0.0, 0),
+            invoice("Fay Doe", "2024-01-01", 20.0, 2),
+            invoice("Fin Doe", "2024-01-01", 20.0, 1),
+            invoice("Pat Doe", "2024-02-01", 0.0, 0),
+        ];
+
+        let results = cross_check(&players, &invoices, parse_date("2024-02-10").unwrap());
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "Sam Doe");
+        assert_eq!(results[0].action, BillingAction::SuspendedButBilled);
+        assert_eq!(results[1].name, "Fay Doe");
+        assert_eq!(results[1].action, BillingAction::FailedPaymentStillRegistered);
+        assert_eq!((results[1].failed, results[1].outstanding), (2, 20.0));
+    }
+}
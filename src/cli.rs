@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Command line arguments: leading words pick the command, `--name value`
+// pairs are options and a `--name` with nothing after it is a flag.
+pub struct Args {
+    words: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Args {
+        let mut parsed = Args { words: Vec::new(), options: HashMap::new(), flags: Vec::new() };
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => match args.peek() {
+                    Some(next) if !next.starts_with("--") => {
+                        let value = args.next().unwrap_or_default();
+                        parsed.options.insert(name.to_string(), value);
+                    }
+                    _ => parsed.flags.push(name.to_string()),
+                },
+                None => parsed.words.push(arg),
+            }
+        }
+        parsed
+    }
+
+    pub fn command(&self) -> Option<&str> {
+        self.word(0)
+    }
+
+    pub fn word(&self, index: usize) -> Option<&str> {
+        self.words.get(index).map(String::as_str)
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str, String> {
+        self.option(name).ok_or_else(|| format!("Missing required option --{}", name))
+    }
+
+    pub fn parsed<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.option(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Invalid value '{}' for --{}", value, name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn args(line: &str) -> Args {
+        Args::parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse_words_options_and_flags() {
+        let args = args("mail-merge --template t.txt --outstanding-only --port 25");
+        assert_eq!(args.command(), Some("mail-merge"));
+        assert_eq!(args.option("template"), Some("t.txt"));
+        assert!(args.flag("outstanding-only"));
+        assert_eq!(args.parsed::<u16>("port"), Ok(Some(25)));
+        assert!(args.required("from").is_err());
+    }
+
+    #[test]
+    fn test_parsed_reports_bad_values() {
+        assert!(args("x --port abc").parsed::<u16>("port").is_err());
+        assert_eq!(args("x").parsed::<u16>("port"), Ok(None));
+    }
+}
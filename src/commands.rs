@@ -0,0 +1,1083 @@
+use std::error::Error;
+use std::path::Path;
+
+use rusqlite::Connection;
+
+use crate::accounting_export::{self, AccountCodes, ExportFormat};
+use crate::age_groups::{self, SeasonConfig};
+use crate::audit;
+use crate::billing::{self, BillingAction};
+use crate::cli::Args;
+use crate::compliance;
+use crate::contacts::{resolve_contacts, Contact};
+use crate::database::{
+    create_tables, get_all_loveadmin, get_all_loveadmin_with_ids, get_all_wholegame, get_audit_entries, get_encryption_settings,
+    get_exception_record, setup_database, AuditFilter,
+};
+use crate::dates::{parse_date, today};
+use crate::eligibility::{check_team_sheet, parse_team_sheet};
+use crate::encryption;
+use crate::export_schema::ColumnAliases;
+use crate::fees::{verify_fees, FeeSchedule, SiblingDiscount};
+use crate::forecast::season_forecast;
+use crate::gdpr::{self, SubjectKey};
+use crate::golden::{self, CaseOutcome};
+use crate::households::build_households;
+use crate::import::{self as importer, ImportError};
+use crate::mail_merge::{self, EmailTemplate, SmtpConfig};
+use crate::matching::{full_name, outstanding_for_player, teams_match};
+use crate::reconciliation::{self, ExceptionFilter, ExceptionKind, ExceptionStatus, WorkflowChange};
+use crate::redact;
+use crate::registration::expiry_report;
+use crate::retention::{self, RetentionPolicy};
+use crate::roster::{self, RosterColumn, RosterFormat, DEFAULT_COLUMNS};
+use crate::synthetic::{self, SyntheticOptions};
+use crate::tui::{self, App};
+use crate::validation::{self, InvalidRows, ValidationReport};
+use crate::web::{self, WebOptions};
+
+type CommandResult = Result<(), Box<dyn Error>>;
+
+const USAGE: &str = "Usage: loveadmin-tool <command> [options] [--db <path>]
+
+Commands:
+  mail-merge --template <file> --from <address> [--eml-dir <dir> | --mbox <file>]
+             [--smtp-host <host> [--smtp-port <port>]] [--outstanding-only]
+             [--expiring-before <date>] [--date <reference date>]
+  contacts [--fan-id <id>] [--missing-only] [--date <reference date>]
+  age-groups [--season <start year>] [--cut-off <MM-DD>] [--oldest-youth <age>]
+  registrations [--within <days>] [--date <reference date>]
+  compliance [--photo-years <years>] [--date <reference date>] [--summary-only]
+  billing-check [--date <reference date>]
+  households [--min-players <count>]
+  fee-check --fees <schedule.csv> [--second-child <percent>] [--further-children <percent>]
+  forecast [--season <start year>] [--by-team] [--date <reference date>]
+  export-accounts --format <xero|quickbooks|qif> --output <file> [--accounts <codes.csv>]
+                  [--default-account <code>] [--from <date>] [--to <date>]
+  roster --format <csv|xlsx|html> --output <file> [--team <team>] [--columns <list>]
+         [--photo-years <years>] [--date <reference date>]
+  eligibility (--sheet <file> | --players <names or FAN IDs>) [--team <team>]
+              [--check-payments] [--season <start year>] [--date <reference date>]
+  reconcile [--kind <kind>] [--team <team>] [--search <text>] [--assignee <name>] [--all]
+            [--photo-years <years>] [--date <reference date>]
+  exception <id> [--status <open|investigating|resolved|ignored>] [--until <date>]
+                 [--assignee <name>] [--note <text>]
+  tui [--photo-years <years>] [--date <reference date>]
+  import [--loveadmin <export.csv>] [--wholegame <export.xlsx>] [--aliases <aliases.csv>]
+         [--invalid-rows <reject|skip>] [--report <problems.csv>]
+  serve [--port <port>] [--bind <address>] [--host <names>] [--aliases <aliases.csv>]
+        [--photo-years <years>] [--date <reference date>]
+        (set LOVEADMIN_ACCESS_TOKEN to require a login)
+  encryption <status | enable [--iterations <count>] | rotate [--iterations <count>] | disable>
+             (passphrases are read from LOVEADMIN_PASSPHRASE and LOVEADMIN_NEW_PASSPHRASE)
+  subject-access (--fan-id <id> | --name <name> | --email <address>) [--json <file>] [--summary <file>]
+  erase (--fan-id <id> | --name <name> | --email <address>) [--dry-run]
+  retention apply [--dry-run] [--wholegame-seasons <seasons>] [--loveadmin-years <years>] [--date <reference date>]
+  redact --output <new database> [--seed <text>]
+  audit [--entity <entity>] [--action <action>] [--user <name>] [--since <date>] [--until <date>]
+        [--search <text>] [--limit <count>] [--json]
+  generate --output <dir> [--seed <text>] [--club <name>] [--teams <count>] [--players <per team>]
+           [--months <count>] [--start <YYYY-MM>] [--lapse-rate <0-1>] [--typo-rate <0-1>]
+           [--suspended-rate <0-1>]
+  golden [--dir <cases dir>] [--update]";
+
+pub fn run(args: &Args) -> CommandResult {
+    match args.command() {
+        Some("mail-merge") => mail_merge(args),
+        Some("contacts") => contacts(args),
+        Some("age-groups") => age_groups(args),
+        Some("registrations") => registrations(args),
+        Some("compliance") => compliance(args),
+        Some("billing-check") => billing_check(args),
+        Some("households") => households(args),
+        Some("fee-check") => fee_check(args),
+        Some("forecast") => forecast(args),
+        Some("export-accounts") => export_accounts(args),
+        Some("roster") => roster(args),
+        Some("eligibility") => eligibility(args),
+        Some("reconcile") => reconcile(args),
+        Some("exception") => exception(args),
+        Some("tui") => tui(args),
+        Some("import") => import(args),
+        Some("serve") => serve(args),
+        Some("encryption") => encryption(args),
+        Some("subject-access") => subject_access(args),
+        Some("erase") => erase(args),
+        Some("retention") => retention(args),
+        Some("redact") => redact(args),
+        Some("audit") => audit_log(args),
+        Some("generate") => generate(args),
+        Some("golden") => golden_files(args),
+        Some("help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => Err(format!("Unknown command '{}'\n\n{}", other, USAGE).into()),
+        None => Err(USAGE.into()),
+    }
+}
+
+fn open_database(args: &Args) -> Result<Connection, Box<dyn Error>> {
+    let conn = open_locked_database(args)?;
+    encryption::unlock(&conn, passphrase(encryption::PASSPHRASE_VARIABLE).as_deref())?;
+    Ok(conn)
+}
+
+// Without unlocking, for commands that handle the passphrase themselves
+fn open_locked_database(args: &Args) -> Result<Connection, Box<dyn Error>> {
+    let conn = setup_database(args.option("db"))?;
+    create_tables(&conn)?;
+    Ok(conn)
+}
+
+fn passphrase(variable: &str) -> Option<String> {
+    std::env::var(variable).ok().filter(|value| !value.is_empty())
+}
+
+// A number of years that will later be counted in months
+fn years_option(args: &Args, name: &str, default: u32) -> Result<u32, Box<dyn Error>> {
+    let years = args.parsed(name)?.unwrap_or(default);
+    if years.checked_mul(12).is_none() {
+        return Err(format!("Invalid value '{}' for --{}, too many years", years, name).into());
+    }
+    Ok(years)
+}
+
+fn reference_date(args: &Args) -> Result<chrono::NaiveDate, Box<dyn Error>> {
+    match args.option("date") {
+        Some(value) => parse_date(value).ok_or_else(|| format!("Invalid date '{}'", value).into()),
+        None => Ok(today()),
+    }
+}
+
+// Season parameters from --season, --cut-off and --oldest-youth, defaulting
+// to the FA rules for the season containing the reference date
+fn season_config(args: &Args) -> Result<SeasonConfig, Box<dyn Error>> {
+    let start_year: Option<i32> = args.parsed("season")?;
+    if let Some(year) = start_year.filter(|year| !(1900..=9999).contains(year)) {
+        return Err(format!("Invalid season '{}', expected a start year such as 2023", year).into());
+    }
+    let mut season = SeasonConfig::new(start_year.unwrap_or_default());
+    if let Some(cut_off) = args.option("cut-off") {
+        let (month, day) = cut_off
+            .split_once('-')
+            .and_then(|(month, day)| Some((month.parse().ok()?, day.parse().ok()?)))
+            .filter(|(month, day)| (1..=12).contains(month) && (1..=31).contains(day))
+            .ok_or_else(|| format!("Invalid cut-off '{}', expected MM-DD", cut_off))?;
+        season.cut_off_month = month;
+        season.cut_off_day = day;
+    }
+    if let Some(oldest_youth) = args.parsed("oldest-youth")? {
+        season.oldest_youth = oldest_youth;
+    }
+    // Without --season, whichever season the reference date falls in under the chosen cut-off
+    if start_year.is_none() {
+        season = season.season_of(reference_date(args)?);
+    }
+    Ok(season)
+}
+
+// Prints rows as left-aligned columns sized to their widest value
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let format_row = |values: Vec<&str>| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", format_row(headers.to_vec()));
+    println!("{}", format_row(widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<String>>().iter().map(String::as_str).collect()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
+
+fn date_option(args: &Args, name: &str) -> Result<Option<chrono::NaiveDate>, Box<dyn Error>> {
+    match args.option(name) {
+        Some(value) => Ok(Some(parse_date(value).ok_or_else(|| format!("Invalid date '{}' for --{}", value, name))?)),
+        None => Ok(None),
+    }
+}
+
+fn mail_merge(args: &Args) -> CommandResult {
+    if args.option("eml-dir").is_none() && args.option("mbox").is_none() && args.option("smtp-host").is_none() {
+        return Err("Nothing to do: give --eml-dir, --mbox and/or --smtp-host".into());
+    }
+    let conn = open_database(args)?;
+    let template = EmailTemplate::load(Path::new(args.required("template")?))?;
+    let from = args.required("from")?;
+    let reference_date = reference_date(args)?;
+    let expiring_before = date_option(args, "expiring-before")?;
+
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+    let selected: Vec<_> = players
+        .iter()
+        .filter(|player| !args.flag("outstanding-only") || outstanding_for_player(player, &invoices) > 0.0)
+        .filter(|player| match expiring_before {
+            Some(cut_off) => player
+                .get_registration_expiry()
+                .as_deref()
+                .and_then(parse_date)
+                .is_some_and(|expiry| expiry < cut_off),
+            None => true,
+        })
+        .collect();
+
+    let result = mail_merge::merge(&template, &selected, &invoices, reference_date);
+    for (player, reason) in &result.skipped {
+        eprintln!("Skipped {}: {}", player, reason);
+    }
+
+    if let Some(dir) = args.option("eml-dir") {
+        let paths = mail_merge::write_eml_files(Path::new(dir), from, &result.emails)?;
+        println!("Wrote {} .eml files to {}", paths.len(), dir);
+    }
+    if let Some(path) = args.option("mbox") {
+        mail_merge::write_mbox(Path::new(path), from, &result.emails)?;
+        println!("Wrote {} messages to {}", result.emails.len(), path);
+    }
+    if let Some(host) = args.option("smtp-host") {
+        let config = SmtpConfig {
+            host: host.to_string(),
+            port: args.parsed("smtp-port")?.unwrap_or(25),
+            from: from.to_string(),
+            helo_name: args.option("smtp-helo").unwrap_or("localhost").to_string(),
+        };
+        let sent = mail_merge::send_smtp(&config, &result.emails)?;
+        println!("Sent {} messages via {}:{}", sent, config.host, config.port);
+    }
+    Ok(())
+}
+
+fn describe_contact(contact: &Option<Contact>) -> String {
+    match contact {
+        Some(contact) => {
+            let detail = contact.email.as_deref().or(contact.phone.as_deref()).unwrap_or("");
+            format!("{} <{}>", contact.name, detail).trim_start().to_string()
+        }
+        None => "-".to_string(),
+    }
+}
+
+fn contacts(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let reference_date = reference_date(args)?;
+
+    let mut rows = Vec::new();
+    for player in get_all_wholegame(&conn)? {
+        if args.option("fan-id").is_some_and(|fan_id| fan_id != player.get_fan_id()) {
+            continue;
+        }
+        let contacts = resolve_contacts(&player, reference_date);
+        if args.flag("missing-only") && contacts.issues.is_empty() {
+            continue;
+        }
+        rows.push(vec![
+            player.get_fan_id().clone(),
+            full_name(&player),
+            match (contacts.age, contacts.is_junior()) {
+                (Some(age), Some(true)) => format!("{} (junior)", age),
+                (Some(age), _) => age.to_string(),
+                (None, _) => "?".to_string(),
+            },
+            describe_contact(&contacts.primary),
+            describe_contact(&contacts.secondary),
+            describe_contact(&contacts.emergency),
+            contacts.issues.iter().map(|issue| issue.to_string()).collect::<Vec<String>>().join("; "),
+        ]);
+    }
+    print_table(&["FAN ID", "Player", "Age", "Primary", "Secondary", "Emergency", "Issues"], &rows);
+    Ok(())
+}
+
+fn age_groups(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let season = season_config(args)?;
+    let players = get_all_wholegame(&conn)?;
+
+    let rows: Vec<Vec<String>> = age_groups::validation_report(&players, &season)
+        .into_iter()
+        .map(|check| {
+            vec![
+                check.fan_id,
+                check.name,
+                check.team,
+                check.stored,
+                check.computed.map_or("?".to_string(), |group| group.to_string()),
+                check.issues.iter().map(|issue| issue.to_string()).collect::<Vec<String>>().join("; "),
+            ]
+        })
+        .collect();
+    println!("Season {} (age on {})", season.label(), season.cut_off_date().format("%d/%m/%Y"));
+    print_table(&["FAN ID", "Player", "Team", "Stored", "Computed", "Issues"], &rows);
+    Ok(())
+}
+
+fn registrations(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let reference_date = reference_date(args)?;
+    let window_days = args.parsed("within")?.unwrap_or(30);
+    let players = get_all_wholegame(&conn)?;
+
+    let report = expiry_report(&players, reference_date, window_days);
+    if report.is_empty() {
+        println!("No registrations expired or expiring within {} days", window_days);
+    }
+    for (team, entries) in report {
+        println!("\n{} ({})", team, entries.len());
+        let rows: Vec<Vec<String>> = entries
+            .into_iter()
+            .map(|entry| {
+                vec![
+                    entry.fan_id,
+                    entry.name,
+                    entry.expiry.map_or("-".to_string(), |date| date.format("%d/%m/%Y").to_string()),
+                    entry.registration_status,
+                    entry.state.to_string(),
+                    entry.contact_name,
+                    entry.contact_email.unwrap_or_else(|| "-".to_string()),
+                ]
+            })
+            .collect();
+        print_table(&["FAN ID", "Player", "Expiry", "Status", "Warning", "Contact", "Email"], &rows);
+    }
+    Ok(())
+}
+
+fn compliance(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let reference_date = reference_date(args)?;
+    let photo_years = years_option(args, "photo-years", 3)?;
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+
+    let results = compliance::check_all(&players, &invoices, reference_date, photo_years);
+    let summary: Vec<Vec<String>> = compliance::team_summary(&results)
+        .into_iter()
+        .map(|team| vec![team.team, team.rag.to_string(), team.players.to_string(), team.red.to_string(), team.amber.to_string()])
+        .collect();
+    print_table(&["Team", "RAG", "Players", "Red", "Amber"], &summary);
+
+    if !args.flag("summary-only") {
+        let rows: Vec<Vec<String>> = results
+            .into_iter()
+            .filter(|result| !result.issues.is_empty())
+            .map(|result| {
+                vec![
+                    result.team.clone(),
+                    result.rag().to_string(),
+                    result.fan_id.clone(),
+                    result.name.clone(),
+                    result.issues.iter().map(|issue| issue.to_string()).collect::<Vec<String>>().join("; "),
+                ]
+            })
+            .collect();
+        println!();
+        print_table(&["Team", "RAG", "FAN ID", "Player", "Issues"], &rows);
+    }
+    Ok(())
+}
+
+fn billing_check(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let reference_date = reference_date(args)?;
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+    let results = billing::cross_check(&players, &invoices, reference_date);
+
+    for (action, heading) in [
+        (BillingAction::SuspendedButBilled, "Suspended but still being billed (disciplinary)"),
+        (BillingAction::FailedPaymentStillRegistered, "Failed payments, still registered and not suspended (finance)"),
+    ] {
+        let rows: Vec<Vec<String>> = results
+            .iter()
+            .filter(|result| result.action == action)
+            .map(|result| {
+                vec![
+                    result.team.clone(),
+                    result.fan_id.clone(),
+                    result.name.clone(),
+                    result.invoices.to_string(),
+                    format!("{:.2}", result.invoiced),
+                    format!("{:.2}", result.outstanding),
+                    result.failed.to_string(),
+                ]
+            })
+            .collect();
+        println!("{} ({})", heading, rows.len());
+        print_table(&["Team", "FAN ID", "Player", "Invoices", "Invoiced", "Outstanding", "Failed"], &rows);
+        println!();
+    }
+    Ok(())
+}
+
+fn households(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let min_players = args.parsed("min-players")?.unwrap_or(1);
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+
+    let rows: Vec<Vec<String>> = build_households(&players, &invoices)
+        .into_iter()
+        .filter(|household| household.players.len() >= min_players)
+        .map(|household| {
+            vec![
+                household.label(),
+                household.parent_carer_emails.iter().cloned().collect::<Vec<String>>().join(", "),
+                household.players.join(", "),
+                format!("{:.2}", household.invoiced),
+                format!("{:.2}", household.paid),
+                format!("{:.2}", household.outstanding),
+            ]
+        })
+        .collect();
+    print_table(&["Family", "Parent/carer email", "Players", "Invoiced", "Paid", "Outstanding"], &rows);
+    Ok(())
+}
+
+fn fee_check(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let schedule = FeeSchedule::load(Path::new(args.required("fees")?))?;
+    let second_child_percent = args.parsed("second-child")?.unwrap_or(0.0);
+    let discount = SiblingDiscount {
+        second_child_percent,
+        further_children_percent: args.parsed("further-children")?.unwrap_or(second_child_percent),
+    };
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+    let households = build_households(&players, &invoices);
+
+    let rows: Vec<Vec<String>> = verify_fees(&players, &invoices, &households, &schedule, &discount)
+        .into_iter()
+        .map(|check| {
+            vec![
+                check.date,
+                check.name,
+                check.account_owner,
+                check.product,
+                check.sibling_position.to_string(),
+                check.finding.to_string(),
+            ]
+        })
+        .collect();
+    print_table(&["Date", "Player", "Account owner", "Product", "Child", "Finding"], &rows);
+    Ok(())
+}
+
+fn forecast(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let season = season_config(args)?;
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+    let forecast = season_forecast(&players, &invoices, &season, reference_date(args)?);
+
+    let latest_month = match forecast.latest_month {
+        Some(month) => month,
+        None => {
+            println!("No team subscriptions invoiced yet");
+            return Ok(());
+        }
+    };
+    let month_label = |(year, month): (i32, u32)| format!("{}-{:02}", year, month);
+    let amount = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{:.2}", value));
+
+    let mut totals: Vec<((i32, u32), f64, Option<f64>)> = Vec::new();
+    for entry in &forecast.months {
+        match totals.last_mut() {
+            Some((month, forecast, actual)) if *month == entry.month => {
+                *forecast += entry.forecast;
+                *actual = actual.map(|actual| actual + entry.actual.unwrap_or(0.0));
+            }
+            _ => totals.push((entry.month, entry.forecast, entry.actual)),
+        }
+    }
+    println!("Season {} forecast, invoiced to {}", season.label(), month_label(latest_month));
+    let rows: Vec<Vec<String>> = if args.flag("by-team") {
+        forecast
+            .months
+            .iter()
+            .map(|entry| vec![month_label(entry.month), entry.team.clone(), format!("{:.2}", entry.forecast), amount(entry.actual)])
+            .collect()
+    } else {
+        totals
+            .iter()
+            .map(|(month, forecast, actual)| vec![month_label(*month), "All teams".to_string(), format!("{:.2}", forecast), amount(*actual)])
+            .collect()
+    };
+    print_table(&["Month", "Team", "Forecast", "Actual"], &rows);
+    println!("\nProjected season income: {:.2}", forecast.projected_total());
+
+    if !forecast.lapsed.is_empty() {
+        println!("\nLapsed since {} ({})", month_label(latest_month), forecast.lapsed.len());
+        let rows: Vec<Vec<String>> = forecast.lapsed.iter().map(|player| vec![player.team.clone(), player.name.clone(), format!("{:.2}", player.amount)]).collect();
+        print_table(&["Team", "Player", "Last invoiced"], &rows);
+    }
+    if !forecast.unbilled.is_empty() {
+        println!("\nRegistered but not yet billed ({})", forecast.unbilled.len());
+        let rows: Vec<Vec<String>> = forecast.unbilled.iter().map(|player| vec![player.team.clone(), player.name.clone(), format!("{:.2}", player.amount)]).collect();
+        print_table(&["Team", "Player", "Expected monthly"], &rows);
+    }
+    Ok(())
+}
+
+fn export_accounts(args: &Args) -> CommandResult {
+    let format_name = args.required("format")?;
+    let format = ExportFormat::parse(format_name).ok_or_else(|| format!("Unknown export format '{}'", format_name))?;
+    let output = args.required("output")?;
+    let mut codes = match args.option("accounts") {
+        Some(path) => AccountCodes::load(Path::new(path))?,
+        None => AccountCodes::new(),
+    };
+    if let Some(code) = args.option("default-account") {
+        codes.set_default(code);
+    }
+    let conn = open_database(args)?;
+    let invoices = get_all_loveadmin(&conn)?;
+
+    let transactions = accounting_export::transactions(&invoices, &codes, date_option(args, "from")?, date_option(args, "to")?);
+    let file = std::fs::File::create(output)?;
+    accounting_export::write_export(&format, std::io::BufWriter::new(file), &transactions)?;
+    let total = transactions.iter().fold(0.0, |total, transaction| total + transaction.amount);
+    println!("Exported {} transactions totalling {:.2} to {}", transactions.len(), total, output);
+    let uncoded = transactions.iter().filter(|transaction| transaction.account_code.is_none()).count();
+    if uncoded > 0 {
+        eprintln!("{} transactions have no account code", uncoded);
+    }
+    Ok(())
+}
+
+fn roster(args: &Args) -> CommandResult {
+    let format_name = args.required("format")?;
+    let format = RosterFormat::parse(format_name).ok_or_else(|| format!("Unknown roster format '{}'", format_name))?;
+    let output = args.required("output")?;
+    let columns = match args.option("columns") {
+        Some(list) => RosterColumn::parse_list(list)?,
+        None => DEFAULT_COLUMNS.to_vec(),
+    };
+    let conn = open_database(args)?;
+    let reference_date = reference_date(args)?;
+    let photo_years = years_option(args, "photo-years", 3)?;
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+
+    let mut rosters = roster::build_rosters(&players, &invoices, reference_date, photo_years);
+    if let Some(team) = args.option("team") {
+        rosters.retain(|name, _| teams_match(name, team));
+        if rosters.is_empty() {
+            return Err(format!("No players found for team '{}'", team).into());
+        }
+    }
+
+    match format {
+        RosterFormat::Csv => roster::write_csv(std::fs::File::create(output)?, &rosters, &columns)?,
+        RosterFormat::Xlsx => roster::write_xlsx(Path::new(output), &rosters, &columns)?,
+        RosterFormat::Html => {
+            let title = format!("Team roster at {}", reference_date.format("%d/%m/%Y"));
+            roster::write_html(std::io::BufWriter::new(std::fs::File::create(output)?), &rosters, &columns, &title)?
+        }
+    }
+    let players = rosters.values().fold(0, |total, entries| total + entries.len());
+    println!("Wrote {} players in {} teams to {}", players, rosters.len(), output);
+    Ok(())
+}
+
+fn eligibility(args: &Args) -> CommandResult {
+    let entries = match (args.option("sheet"), args.option("players")) {
+        (Some(path), _) => parse_team_sheet(&std::fs::read_to_string(path)?),
+        (None, Some(list)) => parse_team_sheet(list),
+        (None, None) => return Err("Missing --sheet <file> or --players <names or FAN IDs>".into()),
+    };
+    let conn = open_database(args)?;
+    let reference_date = reference_date(args)?;
+    let season = season_config(args)?;
+    let players = get_all_wholegame(&conn)?;
+    let invoices = if args.flag("check-payments") { Some(get_all_loveadmin(&conn)?) } else { None };
+
+    let checks = check_team_sheet(&entries, &players, invoices.as_deref(), args.option("team"), &season, reference_date);
+    let rows: Vec<Vec<String>> = checks
+        .iter()
+        .map(|check| {
+            vec![
+                if check.passed() { "PASS".to_string() } else { "FAIL".to_string() },
+                check.name.clone().unwrap_or_else(|| check.entry.clone()),
+                check.fan_id.clone().unwrap_or_default(),
+                check.issues.iter().map(|issue| issue.to_string()).collect::<Vec<String>>().join("; "),
+            ]
+        })
+        .collect();
+    print_table(&["Result", "Player", "FAN ID", "Reasons"], &rows);
+
+    let failed = checks.iter().filter(|check| !check.passed()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} players are not eligible", failed, checks.len()).into());
+    }
+    println!("All {} players are eligible", checks.len());
+    Ok(())
+}
+
+fn reconcile(args: &Args) -> CommandResult {
+    let kind = match args.option("kind") {
+        Some(code) => Some(ExceptionKind::parse(code).ok_or_else(|| format!("Unknown exception kind '{}'", code))?),
+        None => None,
+    };
+    let filter = ExceptionFilter {
+        kind,
+        team: args.option("team").map(String::from),
+        search: args.option("search").map(String::from),
+        assignee: args.option("assignee").map(String::from),
+        include_closed: args.flag("all"),
+    };
+    let reference_date = reference_date(args)?;
+    let conn = open_database(args)?;
+    let tracked = reconciliation::refresh(&conn, reference_date, years_option(args, "photo-years", 3)?)?;
+
+    let rows: Vec<Vec<String>> = tracked
+        .iter()
+        .filter(|tracked| filter.matches(tracked, reference_date))
+        .map(|tracked| {
+            let exception = &tracked.exception;
+            vec![
+                tracked.id.to_string(),
+                exception.kind.to_string(),
+                exception.team.clone(),
+                exception.name.clone(),
+                exception.fan_id.clone().unwrap_or_default(),
+                exception.detail.clone(),
+                tracked.status_label(),
+                tracked.assignee.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    let new = tracked.iter().filter(|tracked| tracked.is_new).count();
+    let open = tracked.iter().filter(|tracked| tracked.status.is_active(reference_date)).count();
+    println!("{} exceptions found, {} new, {} still to follow up", tracked.len(), new, open);
+    if !rows.is_empty() {
+        print_table(&["ID", "Exception", "Team", "Name", "FAN ID", "Detail", "Status", "Assignee"], &rows);
+    }
+    Ok(())
+}
+
+fn exception(args: &Args) -> CommandResult {
+    let id: i64 = args
+        .word(1)
+        .and_then(|word| word.parse().ok())
+        .ok_or("Usage: exception <id> [--status <open|investigating|resolved|ignored>] [--until <date>] [--assignee <name>] [--note <text>]")?;
+    let status = match args.option("status") {
+        Some(code) => Some(ExceptionStatus::parse(code, args.option("until"))?),
+        None => None,
+    };
+    let change = WorkflowChange { status, assignee: args.option("assignee").map(String::from), note: args.option("note").map(String::from) };
+    let conn = open_database(args)?;
+    let record = match (&change.status, &change.assignee, &change.note) {
+        (None, None, None) => get_exception_record(&conn, id)?.ok_or_else(|| format!("No exception with id {}", id))?,
+        _ => reconciliation::update_workflow(&conn, id, &change)?,
+    };
+
+    println!("Exception {}: {}", record.id, record.key);
+    let kind = ExceptionKind::parse(&record.kind).map_or(record.kind.clone(), |kind| kind.to_string());
+    println!("{} - {} ({}): {}", kind, record.name, record.team, record.detail);
+    let status = match &record.ignored_until {
+        Some(until) => format!("{} until {}", record.status, until),
+        None => record.status.clone(),
+    };
+    println!("Status: {}{}", status, if record.present { "" } else { " (no longer found)" });
+    println!("Assignee: {}", record.assignee.as_deref().unwrap_or("nobody"));
+    println!("First seen {}, last seen {}", record.first_seen, record.last_seen);
+    if let Some(notes) = &record.notes {
+        println!("Notes:\n{}", notes);
+    }
+    Ok(())
+}
+
+fn tui(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let reference_date = reference_date(args)?;
+    let photo_years = years_option(args, "photo-years", 3)?;
+    let app = App::load(&conn, reference_date, photo_years)?;
+    tui::run(&conn, app)
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| path.to_string())
+}
+
+fn column_aliases(args: &Args) -> Result<ColumnAliases, String> {
+    match args.option("aliases") {
+        Some(path) => ColumnAliases::load(Path::new(path)),
+        None => Ok(ColumnAliases::new()),
+    }
+}
+
+fn import(args: &Args) -> CommandResult {
+    if args.option("loveadmin").is_none() && args.option("wholegame").is_none() {
+        return Err("Nothing to import: give --loveadmin <export.csv> and/or --wholegame <export.xlsx>".into());
+    }
+    let aliases = column_aliases(args)?;
+    let invalid_rows = match args.option("invalid-rows") {
+        Some(value) => InvalidRows::parse(value).ok_or_else(|| format!("--invalid-rows must be reject or skip, got '{}'", value))?,
+        None => InvalidRows::Reject,
+    };
+    let mut reports = Vec::new();
+    let conn = open_database(args)?;
+    for (source, label) in [(importer::LOVEADMIN_SOURCE, "LoveAdmin"), (importer::WHOLEGAME_SOURCE, "Wholegame")] {
+        if let Some(path) = args.option(source) {
+            let bytes = std::fs::read(path)?;
+            match importer::import_file(&conn, source, &bytes, &file_name(path), &aliases, invalid_rows) {
+                Ok(summary) => {
+                    for warning in summary.schema.warnings() {
+                        eprintln!("Warning: {}", warning);
+                    }
+                    println!("Imported {} {} rows from {} (batch {}, {})", summary.rows, label, path, summary.batch, summary.schema);
+                    if !summary.validation.is_clean() {
+                        println!("Skipped {}:", summary.validation.summary());
+                        print_table(&["Row", "Column", "Value", "Problem"], &summary.validation.table_rows());
+                        reports.push(summary.validation);
+                    }
+                }
+                Err(ImportError::Rejected(report)) => {
+                    println!("Rejected {}, {}:", path, report.summary());
+                    print_table(&["Row", "Column", "Value", "Problem"], &report.table_rows());
+                    write_validation_report(args, &[report])?;
+                    return Err(format!("{}: nothing imported; fix the rows above or rerun with --invalid-rows skip", path).into());
+                }
+                Err(e) => return Err(format!("{}: {}", path, e).into()),
+            }
+        }
+    }
+    write_validation_report(args, &reports)
+}
+
+// Problems from every file go into the one --report CSV
+fn write_validation_report(args: &Args, reports: &[ValidationReport]) -> CommandResult {
+    if let Some(path) = args.option("report") {
+        validation::write_csv(std::fs::File::create(path)?, reports)?;
+        println!("Wrote validation report to {}", path);
+    }
+    Ok(())
+}
+
+fn serve(args: &Args) -> CommandResult {
+    let conn = open_database(args)?;
+    let port: u16 = args.parsed("port")?.unwrap_or(8080);
+    // Localhost only unless the user explicitly chooses otherwise
+    let bind = args.option("bind").unwrap_or("127.0.0.1");
+    let address = if bind.contains(':') { format!("[{}]:{}", bind, port) } else { format!("{}:{}", bind, port) };
+    let local = ["127.0.0.1", "localhost", "::1"].contains(&bind);
+    // Browsers must name the server by an address it was started with
+    let mut hosts = vec![address.clone()];
+    if local {
+        hosts.extend(["127.0.0.1", "localhost", "[::1]"].iter().map(|name| format!("{}:{}", name, port)));
+    }
+    let names = args.option("host").unwrap_or_default().split(',').map(str::trim).filter(|name| !name.is_empty());
+    hosts.extend(names.map(|name| format!("{}:{}", name, port)));
+    if ["0.0.0.0", "::"].contains(&bind) && hosts.len() == 1 {
+        return Err(format!("Serving on {} needs --host with the names other machines use to reach it", bind).into());
+    }
+    let options = WebOptions {
+        reference_date: reference_date(args)?,
+        photo_max_years: years_option(args, "photo-years", 3)?,
+        access_token: passphrase("LOVEADMIN_ACCESS_TOKEN"),
+        aliases: column_aliases(args)?,
+        hosts,
+        form_token: web::new_form_token(),
+    };
+    if !local {
+        eprintln!("Warning: serving on {} makes the club's data reachable from other machines", bind);
+        if options.access_token.is_none() {
+            eprintln!("Warning: LOVEADMIN_ACCESS_TOKEN is not set, so anyone who can reach it can read and change the data");
+        }
+    }
+    crate::http::serve(&address, |request| web::handle(&conn, request, &options))
+}
+
+fn encryption(args: &Args) -> CommandResult {
+    let conn = open_locked_database(args)?;
+    let required = |variable: &str| passphrase(variable).ok_or_else(|| format!("Set {} to the passphrase", variable));
+    let iterations = args.parsed("iterations")?.unwrap_or(encryption::DEFAULT_ITERATIONS);
+    match args.word(1) {
+        Some("status") | None => match get_encryption_settings(&conn)? {
+            Some(settings) => println!(
+                "Encrypted since {} ({} PBKDF2 iterations): {}",
+                settings.created_at,
+                settings.iterations,
+                encryption::SENSITIVE_COLUMNS.join(", ")
+            ),
+            None => println!("Not encrypted"),
+        },
+        Some("enable") => {
+            let count = encryption::enable(&conn, &required(encryption::PASSPHRASE_VARIABLE)?, iterations)?;
+            println!("Encrypted personal data in {} Wholegame rows. Keep the passphrase safe: without it the data can't be recovered.", count);
+        }
+        Some("rotate") => {
+            let old = required(encryption::PASSPHRASE_VARIABLE)?;
+            let new = required(encryption::NEW_PASSPHRASE_VARIABLE)?;
+            let count = encryption::rotate(&conn, &old, &new, iterations)?;
+            println!("Re-encrypted {} Wholegame rows with the new passphrase", count);
+        }
+        Some("disable") => {
+            let count = encryption::disable(&conn, &required(encryption::PASSPHRASE_VARIABLE)?)?;
+            println!("Decrypted {} Wholegame rows", count);
+        }
+        Some(other) => return Err(format!("Unknown encryption action '{}': use status, enable, rotate or disable", other).into()),
+    }
+    Ok(())
+}
+
+fn subject_key(args: &Args) -> Result<SubjectKey, String> {
+    match (args.option("fan-id"), args.option("name"), args.option("email")) {
+        (Some(fan_id), None, None) => Ok(SubjectKey::FanId(fan_id.to_string())),
+        (None, Some(name), None) => Ok(SubjectKey::Name(name.to_string())),
+        (None, None, Some(email)) => Ok(SubjectKey::Email(email.to_string())),
+        _ => Err("Give exactly one of --fan-id, --name or --email".to_string()),
+    }
+}
+
+fn subject_access(args: &Args) -> CommandResult {
+    let key = subject_key(args)?;
+    let conn = open_database(args)?;
+    let records = gdpr::find_subject(&conn, &key)?;
+    if records.players.is_empty() && records.invoices.is_empty() {
+        println!("Nothing is held about {}", key);
+        return Ok(());
+    }
+    let generated_at = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    if let Some(path) = args.option("json") {
+        let report = gdpr::report_json(&key, &records, &generated_at);
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        println!("Wrote {}", path);
+    }
+    match args.option("summary") {
+        Some(path) => {
+            gdpr::write_summary(std::fs::File::create(path)?, &key, &records, &generated_at)?;
+            println!("Wrote {}", path);
+        }
+        None => gdpr::write_summary(std::io::stdout().lock(), &key, &records, &generated_at)?,
+    }
+    Ok(())
+}
+
+fn erase(args: &Args) -> CommandResult {
+    let key = subject_key(args)?;
+    let dry_run = args.flag("dry-run");
+    let conn = open_database(args)?;
+    let records = gdpr::find_subject(&conn, &key)?;
+    if records.players.is_empty() && records.invoices.is_empty() {
+        println!("Nothing is held about {}", key);
+        return Ok(());
+    }
+    let summary = gdpr::erase(&conn, &records, dry_run)?;
+    let verb = if dry_run { "Would erase" } else { "Erased" };
+    println!(
+        "{} {}: {} Wholegame registrations deleted, {} cleared of parent or emergency contact details, \
+         {} invoices pseudonymised (amounts kept), {} exceptions forgotten",
+        verb, key, summary.players_deleted, summary.players_updated, summary.invoices_pseudonymised, summary.exceptions_forgotten
+    );
+    Ok(())
+}
+
+fn retention(args: &Args) -> CommandResult {
+    if args.word(1) != Some("apply") {
+        return Err("Usage: retention apply [--dry-run] [--wholegame-seasons <seasons>] [--loveadmin-years <years>]".into());
+    }
+    let defaults = RetentionPolicy::default();
+    let policy = RetentionPolicy {
+        wholegame_seasons: args.parsed("wholegame-seasons")?.unwrap_or(defaults.wholegame_seasons),
+        loveadmin_years: years_option(args, "loveadmin-years", defaults.loveadmin_years)?,
+    };
+    let reference_date = reference_date(args)?;
+    let conn = open_database(args)?;
+    let plan = retention::plan(&get_all_wholegame(&conn)?, &get_all_loveadmin_with_ids(&conn)?, &policy, reference_date);
+
+    let rows: Vec<Vec<String>> = plan
+        .players
+        .iter()
+        .map(|expired| {
+            vec![
+                expired.player.get_fan_id().clone(),
+                full_name(&expired.player),
+                expired.player.get_team().clone(),
+                expired.left.format("%d/%m/%Y").to_string(),
+            ]
+        })
+        .collect();
+    println!("Wholegame records to delete ({} full seasons after leaving): {}", policy.wholegame_seasons, rows.len());
+    if !rows.is_empty() {
+        print_table(&["FAN ID", "Player", "Team", "Registration expired"], &rows);
+    }
+    let rows: Vec<Vec<String>> = plan
+        .invoices
+        .iter()
+        .map(|(id, invoice)| vec![id.to_string(), invoice.get_date().clone(), invoice.get_product().clone(), invoice.get_name().clone(), invoice.get_account_owner().clone()])
+        .collect();
+    println!("LoveAdmin invoices to anonymise (older than {} years, amounts kept): {}", policy.loveadmin_years, rows.len());
+    if !rows.is_empty() {
+        print_table(&["Invoice", "Date", "Product", "Member", "Account owner"], &rows);
+    }
+
+    if args.flag("dry-run") {
+        println!("Dry run: nothing changed");
+    } else {
+        retention::apply(&conn, &plan, &policy, reference_date)?;
+        println!("Applied and recorded in the audit log");
+    }
+    Ok(())
+}
+
+fn redact(args: &Args) -> CommandResult {
+    let output = args.required("output")?;
+    if Path::new(output).exists() {
+        return Err(format!("{} already exists; redact writes a new database", output).into());
+    }
+    let conn = open_database(args)?;
+    let seed = args.option("seed").map(String::from).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let target = setup_database(Some(output))?;
+    create_tables(&target)?;
+    let summary = redact::redact_database(&conn, &target, &seed)?;
+    println!(
+        "Wrote {} with {} players, {} invoices and {} exceptions; {} people replaced with pseudonyms",
+        output, summary.players, summary.invoices, summary.exceptions, summary.people
+    );
+    Ok(())
+}
+
+// Longer changes are cut short in the table; --json prints them in full
+const AUDIT_CHANGE_WIDTH: usize = 100;
+
+fn audit_log(args: &Args) -> CommandResult {
+    let day = |name: &str| -> Result<Option<String>, Box<dyn Error>> {
+        match args.option(name) {
+            Some(value) => Ok(Some(parse_date(value).ok_or_else(|| format!("Invalid date '{}'", value))?.format("%Y-%m-%d").to_string())),
+            None => Ok(None),
+        }
+    };
+    let filter = AuditFilter {
+        entity: args.option("entity").map(String::from),
+        action: args.option("action").map(String::from),
+        os_user: args.option("user").map(String::from),
+        since: day("since")?,
+        until: day("until")?,
+        search: args.option("search").map(String::from),
+        limit: args.parsed("limit")?.unwrap_or(50),
+    };
+    let conn = open_database(args)?;
+    let entries = get_audit_entries(&conn, &filter)?;
+
+    if args.flag("json") {
+        let parse = |value: &Option<String>| value.as_deref().map_or(serde_json::Value::Null, |text| serde_json::from_str(text).unwrap_or_else(|_| text.into()));
+        let values: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "timestamp": entry.timestamp,
+                    "user": entry.os_user,
+                    "action": entry.action,
+                    "entity": entry.entity,
+                    "before": parse(&entry.before),
+                    "after": parse(&entry.after),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&values)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No audit entries match");
+        return Ok(());
+    }
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| {
+            let mut change = audit::describe(entry);
+            if change.chars().count() > AUDIT_CHANGE_WIDTH {
+                change = change.chars().take(AUDIT_CHANGE_WIDTH - 3).collect::<String>() + "...";
+            }
+            vec![entry.timestamp.clone(), entry.os_user.clone(), entry.action.clone(), entry.entity.clone(), change]
+        })
+        .collect();
+    print_table(&["Time", "User", "Action", "Entity", "Change"], &rows);
+    Ok(())
+}
+
+fn generate(args: &Args) -> CommandResult {
+    let output = Path::new(args.required("output")?);
+    let defaults = SyntheticOptions::default();
+    let start = match args.option("start") {
+        Some(value) => parse_date(&format!("{}-01", value))
+            .map(|date| (chrono::Datelike::year(&date), chrono::Datelike::month(&date)))
+            .ok_or_else(|| format!("Invalid month '{}'; use YYYY-MM", value))?,
+        None => defaults.start,
+    };
+    let rate = |name: &str, default: f64| -> Result<f64, String> {
+        match args.parsed::<f64>(name)?.unwrap_or(default) {
+            rate if (0.0..=1.0).contains(&rate) => Ok(rate),
+            _ => Err(format!("--{} must be between 0 and 1", name)),
+        }
+    };
+    let options = SyntheticOptions {
+        seed: args.option("seed").map(String::from).unwrap_or(defaults.seed),
+        club: args.option("club").map(String::from).unwrap_or(defaults.club),
+        teams: args.parsed("teams")?.unwrap_or(defaults.teams),
+        players_per_team: args.parsed("players")?.unwrap_or(defaults.players_per_team),
+        months: args.parsed("months")?.unwrap_or(defaults.months),
+        start,
+        lapse_rate: rate("lapse-rate", defaults.lapse_rate)?,
+        typo_rate: rate("typo-rate", defaults.typo_rate)?,
+        suspended_rate: rate("suspended-rate", defaults.suspended_rate)?,
+    };
+    if options.teams > synthetic::MAX_TEAMS {
+        return Err(format!("At most {} teams can be generated", synthetic::MAX_TEAMS).into());
+    }
+    if options.months == 0 {
+        return Err("--months must be at least 1".into());
+    }
+
+    let data = synthetic::generate(&options);
+    std::fs::create_dir_all(output)?;
+    synthetic::write_loveadmin_csv(std::fs::File::create(output.join("loveadmin.csv"))?, &data.invoices)?;
+    std::fs::write(output.join("wholegame.xlsx"), synthetic::wholegame_xlsx_bytes(&data.players, &options.club, synthetic::reference_date(&options))?)?;
+    std::fs::write(output.join("expected.json"), serde_json::to_string_pretty(&synthetic::expected_json(&options, &data))?)?;
+    println!(
+        "Wrote loveadmin.csv ({} invoices), wholegame.xlsx ({} players) and expected.json ({} exceptions) to {}",
+        data.invoices.len(),
+        data.players.len(),
+        data.expected.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn golden_files(args: &Args) -> CommandResult {
+    let root = Path::new(args.option("dir").unwrap_or(golden::DEFAULT_DIR));
+    let update = args.flag("update");
+    let dirs = golden::case_dirs(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    if dirs.is_empty() {
+        return Err(format!("No golden cases in {}", root.display()).into());
+    }
+    let mut failed = 0;
+    for dir in &dirs {
+        match golden::run_case(dir, update)? {
+            CaseOutcome::Passed => println!("PASS     {}", dir.display()),
+            CaseOutcome::Updated => println!("UPDATED  {}", dir.display()),
+            CaseOutcome::Failed(diff) => {
+                failed += 1;
+                print!("FAIL     {}\n{}", dir.display(), diff);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} golden cases differ; rerun with --update to accept the new results", failed, dirs.len()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use chrono::{Months, NaiveDate};
+
+use crate::contacts::ADULT_AGE;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::dates::{age_on, parse_date};
+use crate::matching::{full_name, is_paying};
+
+// Players can't hold a contract with the club before this age
+const MIN_CONTRACT_AGE: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Rag {
+    Green,
+    Amber,
+    Red,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ComplianceIssue {
+    NoConsent,
+    NoPhoto,
+    PhotoOutOfDate(NaiveDate),
+    SuspendedButPaying,
+    ContractStatusInconsistent { status: String, age: u32 },
+}
+
+pub struct PlayerCompliance {
+    pub fan_id: String,
+    pub name: String,
+    pub team: String,
+    pub issues: Vec<ComplianceIssue>,
+}
+
+pub struct TeamCompliance {
+    pub team: String,
+    pub players: usize,
+    pub red: usize,
+    pub amber: usize,
+    pub rag: Rag,
+}
+
+impl fmt::Display for Rag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rag::Green => write!(f, "GREEN"),
+            Rag::Amber => write!(f, "AMBER"),
+            Rag::Red => write!(f, "RED"),
+        }
+    }
+}
+
+impl fmt::Display for ComplianceIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ComplianceIssue::NoConsent => write!(f, "no consent given"),
+            ComplianceIssue::NoPhoto => write!(f, "no photo uploaded"),
+            ComplianceIssue::PhotoOutOfDate(date) => write!(f, "photo out of date (uploaded {})", date.format("%d/%m/%Y")),
+            ComplianceIssue::SuspendedButPaying => write!(f, "suspended but still paying in LoveAdmin"),
+            ComplianceIssue::ContractStatusInconsistent { status, age } => {
+                write!(f, "contract status '{}' inconsistent with age {}", status, age)
+            }
+        }
+    }
+}
+
+impl ComplianceIssue {
+    // Safeguarding and disciplinary problems are red, paperwork is amber
+    pub fn rag(&self) -> Rag {
+        match self {
+            ComplianceIssue::NoConsent | ComplianceIssue::SuspendedButPaying => Rag::Red,
+            _ => Rag::Amber,
+        }
+    }
+}
+
+impl PlayerCompliance {
+    pub fn rag(&self) -> Rag {
+        self.issues.iter().map(ComplianceIssue::rag).fold(Rag::Green, |worst, rag| if rag > worst { rag } else { worst })
+    }
+}
+
+// Contracts below MIN_CONTRACT_AGE, or youth statuses for adults
+fn contract_status_issue(status: &str, age: u32) -> bool {
+    let status = status.to_lowercase();
+    let is_contract = status.contains("contract") && !status.contains("non-contract") && !status.contains("non contract");
+    let is_youth = status.contains("youth") || status.contains("junior");
+    (is_contract && age < MIN_CONTRACT_AGE) || (is_youth && age >= ADULT_AGE)
+}
+
+// Missing photo, or one uploaded more than `photo_max_years` before the reference date
+pub fn photo_issue(player: &Wholegame, reference_date: NaiveDate, photo_max_years: u32) -> Option<ComplianceIssue> {
+    // A limit reaching back past the earliest date never makes a photo out of date
+    let photo_cut_off = photo_max_years
+        .checked_mul(12)
+        .and_then(|months| reference_date.checked_sub_months(Months::new(months)))
+        .unwrap_or(NaiveDate::MIN);
+    match player.get_photo_uploaded_date().as_deref().and_then(parse_date) {
+        None => Some(ComplianceIssue::NoPhoto),
+        Some(uploaded) if uploaded < photo_cut_off => Some(ComplianceIssue::PhotoOutOfDate(uploaded)),
+        Some(_) => None,
+    }
+}
+
+pub fn check_player(player: &Wholegame, invoices: &[LoveAdmin], reference_date: NaiveDate, photo_max_years: u32) -> PlayerCompliance {
+    let mut issues = Vec::new();
+
+    if !player.is_consent_given() {
+        issues.push(ComplianceIssue::NoConsent);
+    }
+
+    if let Some(issue) = photo_issue(player, reference_date, photo_max_years) {
+        issues.push(issue);
+    }
+
+    if player.is_suspended() && is_paying(player, invoices) {
+        issues.push(ComplianceIssue::SuspendedButPaying);
+    }
+
+    if let Some(dob) = parse_date(player.get_date_of_birth()) {
+        let age = age_on(dob, reference_date);
+        if contract_status_issue(player.get_contract_status(), age) {
+            issues.push(ComplianceIssue::ContractStatusInconsistent { status: player.get_contract_status().clone(), age });
+        }
+    }
+
+    PlayerCompliance {
+        fan_id: player.get_fan_id().clone(),
+        name: full_name(player),
+        team: player.get_team().clone(),
+        issues,
+    }
+}
+
+pub fn check_all(players: &[Wholegame], invoices: &[LoveAdmin], reference_date: NaiveDate, photo_max_years: u32) -> Vec<PlayerCompliance> {
+    players
+        .iter()
+        .map(|player| check_player(player, invoices, reference_date, photo_max_years))
+        .collect()
+}
+
+// One RAG line per team: red if any player is red, amber if any is amber
+pub fn team_summary(results: &[PlayerCompliance]) -> Vec<TeamCompliance> {
+    let mut teams: BTreeMap<&str, TeamCompliance> = BTreeMap::new();
+    for result in results {
+        let team = teams.entry(result.team.as_str()).or_insert_with(|| TeamCompliance {
+            team: result.team.clone(),
+            players: 0,
+            red: 0,
+            amber: 0,
+            rag: Rag::Green,
+        });
+        team.players += 1;
+        match result.rag() {
+            Rag::Red => team.red += 1,
+            Rag::Amber => team.amber += 1,
+            Rag::Green => {}
+        }
+        if result.rag() > team.rag {
+            team.rag = result.rag();
+        }
+    }
+    teams.into_values().collect()
+}
+
+// One row per player with at least one issue
+pub fn write_csv<W: Write>(writer: W, results: &[PlayerCompliance]) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(["Team", "RAG", "FAN ID", "Player", "Issues"]).map_err(io::Error::other)?;
+    for result in results.iter().filter(|result| !result.issues.is_empty()) {
+        let issues: Vec<String> = result.issues.iter().map(|issue| issue.to_string()).collect();
+        csv.write_record([result.team.as_str(), &result.rag().to_string(), &result.fan_id, &result.name, &issues.join("; ")])
+            .map_err(io::Error::other)?;
+    }
+    csv.flush()
+}
+
+#[cfg(test)]
+mod compliance_tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn player(name: &str, team: &str) -> Wholegame {
+        let mut player = fixtures::player(name, team);
+        player.set_date_of_birth("2012-01-01".to_string());
+        player.set_consent_given(true);
+        player.set_contract_status("Registered".to_string());
+        player.set_photo_uploaded_date(Some("2023-09-01".to_string()));
+        player
+    }
+
+    fn reference_date() -> NaiveDate {
+        parse_date("2024-01-01").unwrap()
+    }
+
+    #[test]
+    fn test_player_checks() {
+        let mut invoice = LoveAdmin::new();
+        invoice.set_name("Jim Doe".to_string());
+        invoice.set_date("2023-12-01".to_string());
+        invoice.set_invoiced(20.0);
+        let invoices = vec![invoice];
+
+        let mut jim = player("Jim", "U12");
+        jim.set_suspended(true);
+        jim.set_consent_given(false);
+        jim.set_photo_uploaded_date(Some("2019-06-01".to_string()));
+        jim.set_contract_status("Contract".to_string());
+        let result = check_player(&jim, &invoices, reference_date(), 3);
+
+        assert_eq!(
+            result.issues,
+            vec![
+                ComplianceIssue::NoConsent,
+                ComplianceIssue::PhotoOutOfDate(parse_date("2019-06-01").unwrap()),
+                ComplianceIssue::SuspendedButPaying,
+                ComplianceIssue::ContractStatusInconsistent { status: "Contract".to_string(), age: 12 },
+            ]
+        );
+        assert_eq!(result.rag(), Rag::Red);
+        assert!(check_player(&player("Jane", "U12"), &invoices, reference_date(), 3).issues.is_empty());
+        assert_eq!(photo_issue(&jim, reference_date(), u32::MAX), None);
+    }
+
+    #[test]
+    fn test_team_summary_takes_worst_rag() {
+        let mut no_photo = player("Jill", "U14");
+        no_photo.set_photo_uploaded_date(None);
+        let mut no_consent = player("Jim", "U12");
+        no_consent.set_consent_given(false);
+        let players = vec![player("Jane", "U12"), no_consent, no_photo, player("Joe", "U16")];
+
+        let summary = team_summary(&check_all(&players, &[], reference_date(), 3));
+
+        let rags: Vec<(&str, Rag)> = summary.iter().map(|team| (team.team.as_str(), team.rag)).collect();
+        assert_eq!(rags, vec![("U12", Rag::Red), ("U14", Rag::Amber), ("U16", Rag::Green)]);
+        assert_eq!((summary[0].players, summary[0].red, summary[0].amber), (2, 1, 0));
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use chrono::NaiveDate;
+
+use crate::data_structures::Wholegame;
+use crate::dates::{age_on, parse_date};
+use crate::matching::full_name;
+
+// Players under this age on the reference date are contacted through their parent/carer
+pub const ADULT_AGE: u32 = 18;
+
+pub struct Contact {
+    pub name: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ContactIssue {
+    UnknownDateOfBirth,
+    MissingParentCarerEmail,
+    MissingPlayerEmail,
+    MissingEmergencyContact,
+}
+
+pub struct PlayerContacts {
+    // None when the date of birth can't be read
+    pub age: Option<u32>,
+    pub primary: Option<Contact>,
+    pub secondary: Option<Contact>,
+    pub emergency: Option<Contact>,
+    pub issues: Vec<ContactIssue>,
+}
+
+impl fmt::Display for ContactIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            ContactIssue::UnknownDateOfBirth => "date of birth missing or unreadable",
+            ContactIssue::MissingParentCarerEmail => "under 18 with no parent/carer email",
+            ContactIssue::MissingPlayerEmail => "adult with no email address",
+            ContactIssue::MissingEmergencyContact => "no emergency contact name and phone number",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl PlayerContacts {
+    pub fn is_junior(&self) -> Option<bool> {
+        self.age.map(|age| age < ADULT_AGE)
+    }
+
+    pub fn primary_email(&self) -> Option<&str> {
+        self.primary.as_ref().and_then(|contact| contact.email.as_deref())
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(String::from)
+}
+
+fn parent_carer(player: &Wholegame) -> Option<Contact> {
+    non_empty(player.get_parent_carer_email_address()).map(|email| Contact {
+        name: non_empty(player.get_parent_carer_name()).unwrap_or_default(),
+        email: Some(email),
+        phone: None,
+    })
+}
+
+fn own(player: &Wholegame) -> Option<Contact> {
+    non_empty(&Some(player.get_email_address().clone())).map(|email| Contact {
+        name: full_name(player),
+        email: Some(email),
+        phone: None,
+    })
+}
+
+// Works out who to contact about a player. Under-18s on the reference date
+// are contacted through their parent/carer with the player's own address as
+// a secondary; adults the other way round. The emergency contact is the same
+// for everyone.
+pub fn resolve_contacts(player: &Wholegame, reference_date: NaiveDate) -> PlayerContacts {
+    let age = parse_date(player.get_date_of_birth()).map(|dob| age_on(dob, reference_date));
+    let mut issues = Vec::new();
+
+    let (primary, secondary) = match age {
+        Some(age) if age < ADULT_AGE => {
+            let primary = parent_carer(player);
+            if primary.is_none() {
+                issues.push(ContactIssue::MissingParentCarerEmail);
+            }
+            (primary, own(player))
+        }
+        Some(_) => {
+            let primary = own(player);
+            if primary.is_none() {
+                issues.push(ContactIssue::MissingPlayerEmail);
+            }
+            (primary, parent_carer(player))
+        }
+        None => {
+            issues.push(ContactIssue::UnknownDateOfBirth);
+            match parent_carer(player) {
+                Some(parent) => (Some(parent), own(player)),
+                None => (own(player), None),
+            }
+        }
+    };
+
+    let emergency_name = non_empty(player.get_emergency_contact());
+    let emergency_phone = non_empty(player.get_emergency_contact_phone_number());
+    let emergency = match (emergency_name, emergency_phone) {
+        (Some(name), Some(phone)) => Some(Contact { name, email: None, phone: Some(phone) }),
+        _ => {
+            issues.push(ContactIssue::MissingEmergencyContact);
+            None
+        }
+    };
+
+    PlayerContacts { age, primary, secondary, emergency, issues }
+}
+
+#[cfg(test)]
+mod contacts_tests {
+    use super::*;
+
+    fn player(date_of_birth: &str) -> Wholegame {
+        let mut player = Wholegame::new();
+        player.set_first_names("Jane".to_string());
+        player.set_surname("Doe".to_string());
+        player.set_date_of_birth(date_of_birth.to_string());
+        player.set_email_address("janedoe@example.com".to_string());
+        player.set_parent_carer_name(Some("John Doe".to_string()));
+        player.set_parent_carer_email_address(Some("johndoe@example.com".to_string()));
+        player.set_emergency_contact(Some("John Doe".to_string()));
+        player.set_emergency_contact_phone_number(Some("555-1234".to_string()));
+        player
+    }
+
+    fn reference_date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 10, 1).unwrap()
+    }
+
+    #[test]
+    fn test_junior_primary_contact_is_parent_carer() {
+        let contacts = resolve_contacts(&player("2012-09-04"), reference_date());
+        assert_eq!(contacts.is_junior(), Some(true));
+        assert_eq!(contacts.primary_email(), Some("johndoe@example.com"));
+        assert_eq!(contacts.secondary.unwrap().name, "Jane Doe");
+        assert_eq!(contacts.emergency.unwrap().phone.as_deref(), Some("555-1234"));
+        assert!(contacts.issues.is_empty());
+    }
+
+    #[test]
+    fn test_adult_primary_contact_is_player() {
+        let contacts = resolve_contacts(&player("1990-01-01"), reference_date());
+        assert_eq!(contacts.is_junior(), Some(false));
+        assert_eq!(contacts.primary_email(), Some("janedoe@example.com"));
+        assert_eq!(contacts.secondary.unwrap().email.as_deref(), Some("johndoe@example.com"));
+    }
+
+    #[test]
+    fn test_missing_contacts_are_flagged() {
+        let mut junior = player("2012-09-04");
+        junior.set_parent_carer_email_address(Some(" ".to_string()));
+        junior.set_emergency_contact_phone_number(None);
+        let contacts = resolve_contacts(&junior, reference_date());
+        assert!(contacts.primary.is_none());
+        assert_eq!(contacts.issues, vec![ContactIssue::MissingParentCarerEmail, ContactIssue::MissingEmergencyContact]);
+
+        let contacts = resolve_contacts(&player("unknown"), reference_date());
+        assert_eq!(contacts.issues, vec![ContactIssue::UnknownDateOfBirth]);
+        assert_eq!(contacts.primary_email(), Some("johndoe@example.com"));
+    }
+}
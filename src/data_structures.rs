@@ -1,4 +1,5 @@
 // Define a struct to hold player data
+#[derive(Clone)]
 pub struct Wholegame {
     first_names: String,
     surname: String,
@@ -23,6 +24,7 @@ pub struct Wholegame {
     photo_uploaded_date: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct LoveAdmin {
     name: String,
     account_owner: String,
@@ -1,5 +1,7 @@
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+use crate::audit;
 use crate::data_structures::{Wholegame, LoveAdmin}; // Adjust path as necessary
+use crate::encryption;
 
 
 pub fn setup_database(explicit_path: Option<&str>) -> Result<Connection> {
@@ -17,7 +19,14 @@ pub fn setup_database(explicit_path: Option<&str>) -> Result<Connection> {
         },
     };
 
-    Connection::open(db_path)
+    let conn = Connection::open(db_path)?;
+    // Sensitive Wholegame columns go through these; they pass values straight
+    // through until the database is unlocked with a passphrase
+    encryption::register_functions(&conn, None)?;
+    // Every write to the data tables is audited, so the log has to exist even
+    // when a caller only creates the table it needs
+    create_table(&conn, AUDIT_LOG_TABLE_SQL)?;
+    Ok(conn)
 }
 
 pub fn create_table(conn: &Connection, sql: &str) -> Result<()> {
@@ -25,7 +34,225 @@ pub fn create_table(conn: &Connection, sql: &str) -> Result<()> {
     Ok(())
 }
 
+// SQL to create the 'loveadmin' table
+pub const LOVEADMIN_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS loveadmin (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        Name TEXT NOT NULL,
+        AccountOwner TEXT NOT NULL,
+        Product TEXT NOT NULL,
+        Date TEXT NOT NULL,
+        Invoiced REAL NOT NULL,
+        Paid REAL NOT NULL,
+        Pending REAL NOT NULL,
+        Outstanding REAL NOT NULL,
+        Failed INTEGER NOT NULL,
+        DaysOverdue INTEGER NOT NULL,
+        LastReminderSent TEXT NOT NULL
+    )";
+
+// SQL to create the 'wholegame' table
+pub const WHOLEGAME_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS wholegame (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        FirstNames TEXT NOT NULL,
+        Surname TEXT NOT NULL,
+        FAN_ID TEXT UNIQUE NOT NULL,
+        DateOfBirth DATE NOT NULL,
+        AgeGroup TEXT NOT NULL,
+        Gender TEXT NOT NULL,
+        Suspended BOOLEAN NOT NULL,
+        Team TEXT NOT NULL,
+        DateSubmitted DATETIME NOT NULL,
+        DateRegistered DATETIME,
+        RegistrationExpiry DATE,
+        RegistrationStatus TEXT NOT NULL,
+        EmailAddress TEXT NOT NULL,
+        ParentCarerName TEXT,
+        ParentCarerEmailAddress TEXT,
+        EmergencyContact TEXT,
+        EmergencyContactPhoneNumber TEXT,
+        OtherClubs TEXT,
+        ConsentGiven BOOLEAN NOT NULL,
+        ContractStatus TEXT NOT NULL,
+        PhotoUploadedDate DATETIME
+    )";
+
+// SQL to create the 'exceptions' table: every reconciliation exception ever
+// found, keyed by exception key, with where it is in the follow-up workflow
+pub const EXCEPTIONS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS exceptions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        ExceptionKey TEXT NOT NULL UNIQUE,
+        Kind TEXT NOT NULL,
+        Name TEXT NOT NULL,
+        Team TEXT NOT NULL,
+        FanId TEXT,
+        Detail TEXT NOT NULL,
+        Status TEXT NOT NULL,
+        IgnoredUntil DATE,
+        Assignee TEXT,
+        Notes TEXT,
+        FirstSeen DATETIME NOT NULL,
+        LastSeen DATETIME NOT NULL,
+        UpdatedAt DATETIME,
+        Present INTEGER NOT NULL
+    )";
+
+// SQL to create the 'import_batches' table, one row per imported export file
+pub const IMPORT_BATCHES_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS import_batches (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        Source TEXT NOT NULL,
+        FileName TEXT NOT NULL,
+        ImportedAt DATETIME NOT NULL,
+        RowCount INTEGER NOT NULL,
+        FormatVersion TEXT
+    )";
+
+// SQL to create the 'encryption_settings' table, holding at most one row when
+// sensitive Wholegame columns are encrypted
+pub const ENCRYPTION_SETTINGS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS encryption_settings (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        Salt TEXT NOT NULL,
+        Iterations INTEGER NOT NULL,
+        KeyCheck TEXT NOT NULL,
+        CreatedAt DATETIME NOT NULL
+    )";
+
+// SQL to create the 'audit_log' table; Before and After hold JSON. Entries are
+// only added, though erasure and retention redact the values they hold.
+pub const AUDIT_LOG_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS audit_log (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        Timestamp DATETIME NOT NULL,
+        OsUser TEXT NOT NULL,
+        Action TEXT NOT NULL,
+        Entity TEXT NOT NULL,
+        Before TEXT,
+        After TEXT
+    )";
+
+pub struct AuditEntry {
+    pub timestamp: String,
+    pub os_user: String,
+    pub action: String,
+    pub entity: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Default)]
+pub struct AuditFilter {
+    pub entity: Option<String>,
+    pub action: Option<String>,
+    pub os_user: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub search: Option<String>,
+    pub limit: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct ExceptionRecord {
+    pub id: i64,
+    pub key: String,
+    pub kind: String,
+    pub name: String,
+    pub team: String,
+    pub fan_id: Option<String>,
+    pub detail: String,
+    pub status: String,
+    pub ignored_until: Option<String>,
+    pub assignee: Option<String>,
+    pub notes: Option<String>,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub updated_at: Option<String>,
+    // Whether the latest reconciliation run found it
+    pub present: bool,
+}
+
+pub struct EncryptionSettings {
+    pub salt: String,
+    pub iterations: u32,
+    pub key_check: String,
+    pub created_at: String,
+}
+
+pub struct ImportBatch {
+    pub id: i64,
+    pub source: String,
+    pub file_name: String,
+    pub imported_at: String,
+    pub row_count: i64,
+    // The export format the file matched, e.g. "2023" or "2023 (changed)"
+    pub format_version: Option<String>,
+}
+
+// Create the data and bookkeeping tables if they are not already there
+pub fn create_tables(conn: &Connection) -> Result<()> {
+    create_table(conn, LOVEADMIN_TABLE_SQL)?;
+    create_table(conn, WHOLEGAME_TABLE_SQL)?;
+    create_table(conn, EXCEPTIONS_TABLE_SQL)?;
+    create_table(conn, IMPORT_BATCHES_TABLE_SQL)?;
+    create_table(conn, ENCRYPTION_SETTINGS_TABLE_SQL)?;
+    create_table(conn, AUDIT_LOG_TABLE_SQL)?;
+    migrate_loveadmin_ids(conn)
+}
+
+// The audit log refers to invoices by id, so ids must not be reused when an
+// import replaces the table. Older databases reused them; the table is
+// rebuilt with AUTOINCREMENT, keeping the existing ids.
+fn migrate_loveadmin_ids(conn: &Connection) -> Result<()> {
+    let sql: String = conn.query_row("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'loveadmin'", [], |row| row.get(0))?;
+    if sql.contains("AUTOINCREMENT") {
+        return Ok(());
+    }
+    conn.execute_batch(&format!(
+        "SAVEPOINT migrate_loveadmin_ids;
+         ALTER TABLE loveadmin RENAME TO loveadmin_reused_ids;
+         {table};
+         INSERT INTO loveadmin (id, {columns}) SELECT id, {columns} FROM loveadmin_reused_ids;
+         DROP TABLE loveadmin_reused_ids;
+         RELEASE migrate_loveadmin_ids;",
+        table = LOVEADMIN_TABLE_SQL,
+        columns = LOVEADMIN_COLUMNS
+    ))
+}
+
 pub fn insert_loveadmin(conn: &Connection, loveadmin: &LoveAdmin) -> Result<usize> {
+    let count = write_loveadmin(conn, loveadmin)?;
+    audit::record_invoice(conn, "insert", conn.last_insert_rowid(), None, Some(loveadmin))?;
+    Ok(count)
+}
+
+pub fn insert_wholegame(conn: &Connection, wholegame: &Wholegame) -> Result<usize> {
+    let count = write_wholegame(conn, wholegame)?;
+    audit::record_player(conn, "insert", None, Some(wholegame))?;
+    Ok(count)
+}
+
+// Swaps a whole table for a fresh export. The caller records the import as a
+// single audit entry rather than one per row.
+pub fn replace_loveadmin(conn: &Connection, invoices: &[LoveAdmin]) -> Result<usize> {
+    let removed = conn.execute("DELETE FROM loveadmin", [])?;
+    for invoice in invoices {
+        write_loveadmin(conn, invoice)?;
+    }
+    Ok(removed)
+}
+
+pub fn replace_wholegame(conn: &Connection, players: &[Wholegame]) -> Result<usize> {
+    let removed = conn.execute("DELETE FROM wholegame", [])?;
+    for player in players {
+        write_wholegame(conn, player)?;
+    }
+    Ok(removed)
+}
+
+fn write_loveadmin(conn: &Connection, loveadmin: &LoveAdmin) -> Result<usize> {
     let insert_sql = "
         INSERT INTO loveadmin (
             Name, AccountOwner, Product, Date, Invoiced, Paid, Pending, Outstanding, Failed, DaysOverdue, LastReminderSent
@@ -38,16 +265,15 @@ pub fn insert_loveadmin(conn: &Connection, loveadmin: &LoveAdmin) -> Result<usiz
     ])
 }
 
-
-pub fn insert_wholegame(conn: &Connection, wholegame: &Wholegame) -> Result<usize> {
+fn write_wholegame(conn: &Connection, wholegame: &Wholegame) -> Result<usize> {
     let insert_sql = "
         INSERT INTO wholegame (
             FirstNames, Surname, FAN_ID, DateOfBirth, AgeGroup, Gender, Suspended,
             Team, DateSubmitted, DateRegistered, RegistrationExpiry, RegistrationStatus,
             EmailAddress, ParentCarerName, ParentCarerEmailAddress, EmergencyContact,
             EmergencyContactPhoneNumber, OtherClubs, ConsentGiven, ContractStatus, PhotoUploadedDate
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, 
-                  ?16, ?17, ?18, ?19, ?20, ?21)";
+        ) VALUES (?1, ?2, ?3, encrypt_field(?4), ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, encrypt_field(?13), ?14, encrypt_field(?15),
+                  encrypt_field(?16), encrypt_field(?17), ?18, ?19, ?20, ?21)";
     
     conn.execute(insert_sql, params![
         wholegame.get_first_names(), wholegame.get_surname(), wholegame.get_fan_id(), wholegame.get_date_of_birth(),
@@ -60,7 +286,384 @@ pub fn insert_wholegame(conn: &Connection, wholegame: &Wholegame) -> Result<usiz
     ])
 }
 
+// Rewrites every column of an invoice, keyed by its row id
+pub fn update_loveadmin(conn: &Connection, id: i64, loveadmin: &LoveAdmin) -> Result<usize> {
+    let before = get_loveadmin_by_id(conn, id)?;
+    let update_sql = "
+        UPDATE loveadmin SET
+            Name = ?2, AccountOwner = ?3, Product = ?4, Date = ?5, Invoiced = ?6, Paid = ?7, Pending = ?8,
+            Outstanding = ?9, Failed = ?10, DaysOverdue = ?11, LastReminderSent = ?12
+        WHERE id = ?1";
+
+    let count = conn.execute(update_sql, params![
+        id, loveadmin.get_name(), loveadmin.get_account_owner(), loveadmin.get_product(), loveadmin.get_date(),
+        loveadmin.get_invoiced(), loveadmin.get_paid(), loveadmin.get_pending(), loveadmin.get_outstanding(),
+        loveadmin.get_failed(), loveadmin.get_days_overdue(), loveadmin.get_last_reminder_sent()
+    ])?;
+    if count > 0 {
+        audit::record_invoice(conn, "update", id, before.as_ref(), Some(loveadmin))?;
+    }
+    Ok(count)
+}
+
+// Rewrites every column of a player, keyed by FAN ID
+pub fn update_wholegame(conn: &Connection, wholegame: &Wholegame) -> Result<usize> {
+    let before = get_wholegame_by_fan_id(conn, wholegame.get_fan_id())?;
+    let update_sql = "
+        UPDATE wholegame SET
+            FirstNames = ?1, Surname = ?2, DateOfBirth = encrypt_field(?4), AgeGroup = ?5, Gender = ?6, Suspended = ?7,
+            Team = ?8, DateSubmitted = ?9, DateRegistered = ?10, RegistrationExpiry = ?11, RegistrationStatus = ?12,
+            EmailAddress = encrypt_field(?13), ParentCarerName = ?14, ParentCarerEmailAddress = encrypt_field(?15),
+            EmergencyContact = encrypt_field(?16), EmergencyContactPhoneNumber = encrypt_field(?17), OtherClubs = ?18,
+            ConsentGiven = ?19, ContractStatus = ?20, PhotoUploadedDate = ?21
+        WHERE FAN_ID = ?3";
+
+    let count = conn.execute(update_sql, params![
+        wholegame.get_first_names(), wholegame.get_surname(), wholegame.get_fan_id(), wholegame.get_date_of_birth(),
+        wholegame.get_age_group(), wholegame.get_gender(), wholegame.is_suspended(), wholegame.get_team(),
+        wholegame.get_date_submitted(), wholegame.get_date_registered(), wholegame.get_registration_expiry(),
+        wholegame.get_registration_status(), wholegame.get_email_address(), wholegame.get_parent_carer_name(),
+        wholegame.get_parent_carer_email_address(), wholegame.get_emergency_contact(),
+        wholegame.get_emergency_contact_phone_number(), wholegame.get_other_clubs(), wholegame.is_consent_given(),
+        wholegame.get_contract_status(), wholegame.get_photo_uploaded_date()
+    ])?;
+    if let Some(before) = before.filter(|_| count > 0) {
+        audit::record_player(conn, "update", Some(&before), Some(wholegame))?;
+    }
+    Ok(count)
+}
+
+pub fn delete_wholegame(conn: &Connection, fan_id: &str) -> Result<usize> {
+    let before = get_wholegame_by_fan_id(conn, fan_id)?;
+    let count = conn.execute("DELETE FROM wholegame WHERE FAN_ID = ?1", params![fan_id])?;
+    if let Some(before) = before.filter(|_| count > 0) {
+        audit::record_player(conn, "delete", Some(&before), None)?;
+    }
+    Ok(count)
+}
+
+
+const LOVEADMIN_COLUMNS: &str = "Name, AccountOwner, Product, Date, Invoiced, Paid, Pending, Outstanding, Failed, DaysOverdue, LastReminderSent";
+
+const WHOLEGAME_COLUMNS: &str = "FirstNames, Surname, FAN_ID, decrypt_field(DateOfBirth), AgeGroup, Gender, Suspended,
+    Team, DateSubmitted, DateRegistered, RegistrationExpiry, RegistrationStatus,
+    decrypt_field(EmailAddress), ParentCarerName, decrypt_field(ParentCarerEmailAddress), decrypt_field(EmergencyContact),
+    decrypt_field(EmergencyContactPhoneNumber), OtherClubs, ConsentGiven, ContractStatus, PhotoUploadedDate";
+
+fn loveadmin_from_row(row: &Row) -> Result<LoveAdmin> {
+    let mut loveadmin = LoveAdmin::new();
+    loveadmin.set_name(row.get(0)?);
+    loveadmin.set_account_owner(row.get(1)?);
+    loveadmin.set_product(row.get(2)?);
+    loveadmin.set_date(row.get(3)?);
+    loveadmin.set_invoiced(row.get(4)?);
+    loveadmin.set_paid(row.get(5)?);
+    loveadmin.set_pending(row.get(6)?);
+    loveadmin.set_outstanding(row.get(7)?);
+    loveadmin.set_failed(row.get(8)?);
+    loveadmin.set_days_overdue(row.get(9)?);
+    loveadmin.set_last_reminder_sent(row.get(10)?);
+    Ok(loveadmin)
+}
+
+fn wholegame_from_row(row: &Row) -> Result<Wholegame> {
+    let mut wholegame = Wholegame::new();
+    wholegame.set_first_names(row.get(0)?);
+    wholegame.set_surname(row.get(1)?);
+    wholegame.set_fan_id(row.get(2)?);
+    wholegame.set_date_of_birth(row.get(3)?);
+    wholegame.set_age_group(row.get(4)?);
+    wholegame.set_gender(row.get(5)?);
+    wholegame.set_suspended(row.get(6)?);
+    wholegame.set_team(row.get(7)?);
+    wholegame.set_date_submitted(row.get(8)?);
+    wholegame.set_date_registered(row.get(9)?);
+    wholegame.set_registration_expiry(row.get(10)?);
+    wholegame.set_registration_status(row.get(11)?);
+    wholegame.set_email_address(row.get(12)?);
+    wholegame.set_parent_carer_name(row.get(13)?);
+    wholegame.set_parent_carer_email_address(row.get(14)?);
+    wholegame.set_emergency_contact(row.get(15)?);
+    wholegame.set_emergency_contact_phone_number(row.get(16)?);
+    wholegame.set_other_clubs(row.get(17)?);
+    wholegame.set_consent_given(row.get(18)?);
+    wholegame.set_contract_status(row.get(19)?);
+    wholegame.set_photo_uploaded_date(row.get(20)?);
+    Ok(wholegame)
+}
+
+pub fn get_all_loveadmin(conn: &Connection) -> Result<Vec<LoveAdmin>> {
+    let sql = format!("SELECT {} FROM loveadmin ORDER BY id", LOVEADMIN_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], loveadmin_from_row)?;
+    rows.collect()
+}
+
+pub fn get_all_wholegame(conn: &Connection) -> Result<Vec<Wholegame>> {
+    let sql = format!("SELECT {} FROM wholegame ORDER BY id", WHOLEGAME_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], wholegame_from_row)?;
+    rows.collect()
+}
+
+// Invoices with their row ids, which is how the API refers to a single invoice
+pub fn get_all_loveadmin_with_ids(conn: &Connection) -> Result<Vec<(i64, LoveAdmin)>> {
+    let sql = format!("SELECT {}, id FROM loveadmin ORDER BY id", LOVEADMIN_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(11)?, loveadmin_from_row(row)?)))?;
+    rows.collect()
+}
+
+pub fn get_loveadmin_by_id(conn: &Connection, id: i64) -> Result<Option<LoveAdmin>> {
+    let sql = format!("SELECT {} FROM loveadmin WHERE id = ?1", LOVEADMIN_COLUMNS);
+    conn.query_row(&sql, params![id], loveadmin_from_row).optional()
+}
+
+pub fn get_wholegame_by_fan_id(conn: &Connection, fan_id: &str) -> Result<Option<Wholegame>> {
+    let sql = format!("SELECT {} FROM wholegame WHERE FAN_ID = ?1", WHOLEGAME_COLUMNS);
+    conn.query_row(&sql, params![fan_id.trim()], wholegame_from_row).optional()
+}
+
+const EXCEPTION_COLUMNS: &str = "id, ExceptionKey, Kind, Name, Team, FanId, Detail, Status, IgnoredUntil, Assignee, decrypt_field(Notes),
+    FirstSeen, LastSeen, UpdatedAt, Present";
+
+fn exception_from_row(row: &Row) -> Result<ExceptionRecord> {
+    Ok(ExceptionRecord {
+        id: row.get(0)?,
+        key: row.get(1)?,
+        kind: row.get(2)?,
+        name: row.get(3)?,
+        team: row.get(4)?,
+        fan_id: row.get(5)?,
+        detail: row.get(6)?,
+        status: row.get(7)?,
+        ignored_until: row.get(8)?,
+        assignee: row.get(9)?,
+        notes: row.get(10)?,
+        first_seen: row.get(11)?,
+        last_seen: row.get(12)?,
+        updated_at: row.get(13)?,
+        present: row.get(14)?,
+    })
+}
+
+pub fn get_exception_records(conn: &Connection) -> Result<Vec<ExceptionRecord>> {
+    let sql = format!("SELECT {} FROM exceptions ORDER BY id", EXCEPTION_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], exception_from_row)?;
+    rows.collect()
+}
+
+pub fn get_exception_record(conn: &Connection, id: i64) -> Result<Option<ExceptionRecord>> {
+    let sql = format!("SELECT {} FROM exceptions WHERE id = ?1", EXCEPTION_COLUMNS);
+    conn.query_row(&sql, params![id], exception_from_row).optional()
+}
+
+pub fn get_exception_record_by_key(conn: &Connection, key: &str) -> Result<Option<ExceptionRecord>> {
+    let sql = format!("SELECT {} FROM exceptions WHERE ExceptionKey = ?1", EXCEPTION_COLUMNS);
+    conn.query_row(&sql, params![key], exception_from_row).optional()
+}
+
+// Records that a reconciliation run found the exception: new ones start open,
+// known ones get the latest description and keep their workflow state.
+// Returns the exception's id.
+pub fn save_found_exception(conn: &Connection, found: &ExceptionRecord) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO exceptions (ExceptionKey, Kind, Name, Team, FanId, Detail, Status, FirstSeen, LastSeen, Present)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'open', ?7, ?7, 1)
+         ON CONFLICT (ExceptionKey) DO UPDATE SET
+             Kind = excluded.Kind, Name = excluded.Name, Team = excluded.Team, FanId = excluded.FanId,
+             Detail = excluded.Detail, LastSeen = excluded.LastSeen, Present = 1",
+        params![found.key, found.kind, found.name, found.team, found.fan_id, found.detail, found.last_seen],
+    )?;
+    conn.query_row("SELECT id FROM exceptions WHERE ExceptionKey = ?1", params![found.key], |row| row.get(0))
+}
+
+// The latest run no longer finds it; its history and workflow state are kept
+pub fn mark_exception_absent(conn: &Connection, id: i64) -> Result<usize> {
+    conn.execute("UPDATE exceptions SET Present = 0 WHERE id = ?1", params![id])
+}
+
+// Copies a whole record, for writing one database from another
+pub fn insert_exception_record(conn: &Connection, record: &ExceptionRecord) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO exceptions (ExceptionKey, Kind, Name, Team, FanId, Detail, Status, IgnoredUntil, Assignee, Notes,
+             FirstSeen, LastSeen, UpdatedAt, Present)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, encrypt_field(?10), ?11, ?12, ?13, ?14)",
+        params![
+            record.key, record.kind, record.name, record.team, record.fan_id, record.detail, record.status, record.ignored_until,
+            record.assignee, record.notes, record.first_seen, record.last_seen, record.updated_at, record.present
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+// Saves the status, ignore date, assignee and notes, keyed by id
+pub fn update_exception_workflow(conn: &Connection, record: &ExceptionRecord) -> Result<usize> {
+    let before = get_exception_record(conn, record.id)?;
+    let count = conn.execute(
+        "UPDATE exceptions SET Status = ?2, IgnoredUntil = ?3, Assignee = ?4, Notes = encrypt_field(?5), UpdatedAt = ?6 WHERE id = ?1",
+        params![record.id, record.status, record.ignored_until, record.assignee, record.notes, record.updated_at],
+    )?;
+    if let Some(before) = before.filter(|_| count > 0) {
+        let action = match (before.status.as_str(), record.status.as_str()) {
+            (old, "resolved") if old != "resolved" => "resolve",
+            ("resolved" | "ignored", "open" | "investigating") => "reopen",
+            _ => "update",
+        };
+        audit::record_exception(conn, action, Some(&before), Some(record))?;
+    }
+    Ok(count)
+}
+
+// Resolves an exception by key, whether or not a reconciliation run has stored it yet
+pub fn resolve_exception(conn: &Connection, key: &str, resolved_at: &str) -> Result<usize> {
+    let mut record = match get_exception_record_by_key(conn, key)? {
+        Some(record) => record,
+        None => {
+            let record = ExceptionRecord {
+                key: key.to_string(),
+                kind: key.split(':').next().unwrap_or_default().to_string(),
+                status: "open".to_string(),
+                first_seen: resolved_at.to_string(),
+                last_seen: resolved_at.to_string(),
+                ..ExceptionRecord::default()
+            };
+            ExceptionRecord { id: insert_exception_record(conn, &record)?, ..record }
+        }
+    };
+    record.status = "resolved".to_string();
+    record.ignored_until = None;
+    record.updated_at = Some(resolved_at.to_string());
+    update_exception_workflow(conn, &record)
+}
 
+pub fn reopen_exception(conn: &Connection, key: &str) -> Result<usize> {
+    match get_exception_record_by_key(conn, key)? {
+        Some(mut record) if record.status != "open" => {
+            record.status = "open".to_string();
+            record.ignored_until = None;
+            record.updated_at = Some(chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string());
+            update_exception_workflow(conn, &record)
+        }
+        _ => Ok(0),
+    }
+}
+
+pub fn delete_exception(conn: &Connection, key: &str) -> Result<usize> {
+    let before = get_exception_record_by_key(conn, key)?;
+    let count = conn.execute("DELETE FROM exceptions WHERE ExceptionKey = ?1", params![key])?;
+    if let Some(before) = before.filter(|_| count > 0) {
+        audit::record_exception(conn, "delete", Some(&before), None)?;
+    }
+    Ok(count)
+}
+
+
+pub fn insert_import_batch(
+    conn: &Connection,
+    source: &str,
+    file_name: &str,
+    imported_at: &str,
+    row_count: usize,
+    format_version: Option<&str>,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO import_batches (Source, FileName, ImportedAt, RowCount, FormatVersion) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![source, file_name, imported_at, row_count as i64, format_version],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+// Newest first
+pub fn get_import_batches(conn: &Connection) -> Result<Vec<ImportBatch>> {
+    let mut stmt = conn.prepare("SELECT id, Source, FileName, ImportedAt, RowCount, FormatVersion FROM import_batches ORDER BY id DESC")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ImportBatch {
+            id: row.get(0)?,
+            source: row.get(1)?,
+            file_name: row.get(2)?,
+            imported_at: row.get(3)?,
+            row_count: row.get(4)?,
+            format_version: row.get(5)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn get_encryption_settings(conn: &Connection) -> Result<Option<EncryptionSettings>> {
+    conn.query_row("SELECT Salt, Iterations, KeyCheck, CreatedAt FROM encryption_settings WHERE id = 1", [], |row| {
+        Ok(EncryptionSettings { salt: row.get(0)?, iterations: row.get(1)?, key_check: row.get(2)?, created_at: row.get(3)? })
+    })
+    .optional()
+}
+
+pub fn save_encryption_settings(conn: &Connection, settings: &EncryptionSettings) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO encryption_settings (id, Salt, Iterations, KeyCheck, CreatedAt) VALUES (1, ?1, ?2, ?3, ?4)",
+        params![settings.salt, settings.iterations, settings.key_check, settings.created_at],
+    )
+}
+
+pub fn delete_encryption_settings(conn: &Connection) -> Result<usize> {
+    conn.execute("DELETE FROM encryption_settings", [])
+}
+
+// Before and after values hold personal data, so they're encrypted like the
+// sensitive Wholegame columns
+pub fn insert_audit_entry(conn: &Connection, entry: &AuditEntry) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO audit_log (Timestamp, OsUser, Action, Entity, Before, After)
+         VALUES (?1, ?2, ?3, ?4, encrypt_field(?5), encrypt_field(?6))",
+        params![entry.timestamp, entry.os_user, entry.action, entry.entity, entry.before, entry.after],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+// Newest first. Entity matches exactly or as a prefix ("wholegame" finds every
+// player), dates compare against the day of the timestamp and the search text
+// is looked for in the before and after values.
+pub fn get_audit_entries(conn: &Connection, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT Timestamp, OsUser, Action, Entity, decrypt_field(Before), decrypt_field(After) FROM audit_log
+         WHERE (?1 IS NULL OR Entity = ?1 OR Entity LIKE ?1 || ':%')
+           AND (?2 IS NULL OR Action = ?2)
+           AND (?3 IS NULL OR OsUser = ?3)
+           AND (?4 IS NULL OR substr(Timestamp, 1, 10) >= ?4)
+           AND (?5 IS NULL OR substr(Timestamp, 1, 10) <= ?5)
+           AND (?6 IS NULL OR decrypt_field(Before) LIKE '%' || ?6 || '%' OR decrypt_field(After) LIKE '%' || ?6 || '%')
+         ORDER BY id DESC LIMIT ?7",
+    )?;
+    let rows = stmt.query_map(
+        params![filter.entity, filter.action, filter.os_user, filter.since, filter.until, filter.search, filter.limit as i64],
+        |row| {
+            Ok(AuditEntry {
+                timestamp: row.get(0)?,
+                os_user: row.get(1)?,
+                action: row.get(2)?,
+                entity: row.get(3)?,
+                before: row.get(4)?,
+                after: row.get(5)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+// Stands in for the before and after values of a redacted entry
+pub const REDACTED_AUDIT_VALUE: &str = "{\"redacted\":true}";
+
+// Replaces the values held for one entity, keeping the entries themselves.
+// Returns how many entries held values.
+pub fn redact_audit_entries(conn: &Connection, entity: &str) -> Result<usize> {
+    conn.execute(
+        "UPDATE audit_log SET
+             Before = CASE WHEN Before IS NULL THEN NULL ELSE ?2 END,
+             After = CASE WHEN After IS NULL THEN NULL ELSE ?2 END
+         WHERE Entity = ?1 AND (Before IS NOT NULL OR After IS NOT NULL)",
+        params![entity, REDACTED_AUDIT_VALUE],
+    )
+}
 
 #[cfg(test)]
 mod database_tests {
@@ -249,4 +852,92 @@ mod database_tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_get_all_round_trips_inserted_rows() -> Result<()> {
+        let conn = setup_database(Some(":memory:"))?;
+        create_tables(&conn)?;
+
+        let mut invoice = LoveAdmin::new();
+        invoice.set_name("Jane Doe".to_string());
+        invoice.set_account_owner("John Doe".to_string());
+        invoice.set_product("U15 Girls (*)".to_string());
+        invoice.set_date("2023-09-01".to_string());
+        invoice.set_invoiced(25.0);
+        invoice.set_outstanding(25.0);
+        insert_loveadmin(&conn, &invoice)?;
+
+        let mut player = Wholegame::new();
+        player.set_first_names("Jane".to_string());
+        player.set_surname("Doe".to_string());
+        player.set_fan_id("987654321".to_string());
+        player.set_parent_carer_email_address(Some("johndoe@example.com".to_string()));
+        player.set_consent_given(true);
+        insert_wholegame(&conn, &player)?;
+
+        let invoices = get_all_loveadmin(&conn)?;
+        assert_eq!(invoices.len(), 1);
+        assert_eq!(invoices[0].get_product(), "U15 Girls (*)");
+        assert_eq!(invoices[0].get_outstanding(), 25.0);
+
+        let players = get_all_wholegame(&conn)?;
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].get_fan_id(), "987654321");
+        assert_eq!(players[0].get_parent_carer_email_address().as_deref(), Some("johndoe@example.com"));
+        assert!(players[0].get_date_registered().is_none());
+        assert!(players[0].is_consent_given());
+
+        let with_ids = get_all_loveadmin_with_ids(&conn)?;
+        assert_eq!(get_loveadmin_by_id(&conn, with_ids[0].0)?.map(|invoice| invoice.get_name().clone()), Some("Jane Doe".to_string()));
+        assert!(get_loveadmin_by_id(&conn, with_ids[0].0 + 1)?.is_none());
+        assert_eq!(get_wholegame_by_fan_id(&conn, " 987654321 ")?.map(|player| player.get_surname().clone()), Some("Doe".to_string()));
+        assert!(get_wholegame_by_fan_id(&conn, "1")?.is_none());
+
+        let mut updated = get_loveadmin_by_id(&conn, with_ids[0].0)?.unwrap();
+        updated.set_name("Erased member".to_string());
+        assert_eq!(update_loveadmin(&conn, with_ids[0].0, &updated)?, 1);
+        assert_eq!(get_all_loveadmin(&conn)?[0].get_name(), "Erased member");
+        player.set_parent_carer_email_address(None);
+        assert_eq!(update_wholegame(&conn, &player)?, 1);
+        assert!(get_all_wholegame(&conn)?[0].get_parent_carer_email_address().is_none());
+        assert_eq!(delete_wholegame(&conn, "987654321")?, 1);
+        assert!(get_all_wholegame(&conn)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_and_reopen_exception() -> Result<()> {
+        let conn = setup_database(Some(":memory:"))?;
+        create_tables(&conn)?;
+
+        resolve_exception(&conn, "not-in-loveadmin:987654321:u15 girls", "2023-10-01 10:00:00")?;
+        resolve_exception(&conn, "not-in-loveadmin:987654321:u15 girls", "2023-10-02 10:00:00")?;
+        resolve_exception(&conn, "not-in-wholegame:john doe:", "2023-10-01 10:00:00")?;
+        let records = get_exception_records(&conn)?;
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.status == "resolved"));
+
+        reopen_exception(&conn, "not-in-wholegame:john doe:")?;
+        let status = |key: &str| get_exception_record_by_key(&conn, key).map(|record| record.map(|record| record.status));
+        assert_eq!(status("not-in-loveadmin:987654321:u15 girls")?.as_deref(), Some("resolved"));
+        assert_eq!(status("not-in-wholegame:john doe:")?.as_deref(), Some("open"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoice_ids_are_not_reused() -> Result<()> {
+        let conn = setup_database(Some(":memory:"))?;
+        conn.execute_batch(&LOVEADMIN_TABLE_SQL.replace(" AUTOINCREMENT", ""))?;
+        let mut invoice = LoveAdmin::new();
+        invoice.set_name("Amy Doe".to_string());
+        insert_loveadmin(&conn, &invoice)?;
+        create_tables(&conn)?;
+        create_tables(&conn)?;
+
+        let ids = |conn: &Connection| get_all_loveadmin_with_ids(conn).map(|rows| rows.iter().map(|(id, _)| *id).collect::<Vec<i64>>());
+        assert_eq!(ids(&conn)?, vec![1]);
+        replace_loveadmin(&conn, &[invoice.clone(), invoice])?;
+        assert_eq!(ids(&conn)?, vec![2, 3]);
+        Ok(())
+    }
 }
@@ -0,0 +1,74 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+
+// Dates arrive as text from both exports: ISO dates from our own inserts,
+// UK style dates from Wholegame and sometimes a trailing time component.
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    for format in ["%Y-%m-%d", "%d/%m/%Y", "%d-%m-%Y"] {
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Some(date);
+        }
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%d/%m/%Y %H:%M:%S", "%d/%m/%Y %H:%M"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(datetime.date());
+        }
+    }
+    None
+}
+
+// Age in whole years on the given date
+pub fn age_on(date_of_birth: NaiveDate, on: NaiveDate) -> u32 {
+    let mut age = on.year() - date_of_birth.year();
+    if (on.month(), on.day()) < (date_of_birth.month(), date_of_birth.day()) {
+        age -= 1;
+    }
+    age.max(0) as u32
+}
+
+// Months are handled as (year, month) pairs when grouping invoices
+pub fn next_month((year, month): (i32, u32)) -> (i32, u32) {
+    if month == 12 { (year + 1, 1) } else { (year, month + 1) }
+}
+
+pub fn previous_month((year, month): (i32, u32)) -> (i32, u32) {
+    if month == 1 { (year - 1, 12) } else { (year, month - 1) }
+}
+
+pub fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+#[cfg(test)]
+mod dates_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date_accepts_export_formats() {
+        let expected = NaiveDate::from_ymd_opt(2023, 9, 4);
+        assert_eq!(parse_date("2023-09-04"), expected);
+        assert_eq!(parse_date("04/09/2023"), expected);
+        assert_eq!(parse_date("2023-09-04 10:15:00"), expected);
+        assert_eq!(parse_date(" 04/09/2023 10:15 "), expected);
+        assert_eq!(parse_date(""), None);
+        assert_eq!(parse_date("not a date"), None);
+    }
+
+    #[test]
+    fn test_age_on_counts_birthdays() {
+        let dob = NaiveDate::from_ymd_opt(2005, 9, 4).unwrap();
+        assert_eq!(age_on(dob, NaiveDate::from_ymd_opt(2023, 9, 3).unwrap()), 17);
+        assert_eq!(age_on(dob, NaiveDate::from_ymd_opt(2023, 9, 4).unwrap()), 18);
+        assert_eq!(age_on(dob, NaiveDate::from_ymd_opt(2000, 1, 1).unwrap()), 0);
+    }
+
+    #[test]
+    fn test_month_arithmetic_wraps_years() {
+        assert_eq!(next_month((2023, 12)), (2024, 1));
+        assert_eq!(previous_month((2024, 1)), (2023, 12));
+        assert_eq!(previous_month(next_month((2024, 6))), (2024, 6));
+    }
+}
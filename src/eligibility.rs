@@ -0,0 +1,260 @@
+use std::fmt;
+
+use chrono::NaiveDate;
+
+use crate::age_groups::{AgeGroup, SeasonConfig};
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::dates::{age_on, parse_date};
+use crate::matching::{full_name, invoices_for_player, names_match, outstanding_for_player, teams_match, AMOUNT_TOLERANCE};
+use crate::registration::{expiry_state, is_registered, ExpiryState};
+
+// Youngest age allowed in open age (adult) football on the day of the match
+const MIN_ADULT_AGE: u32 = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum EligibilityIssue {
+    NotFound,
+    Ambiguous(usize),
+    NotRegistered(String),
+    RegistrationExpired,
+    Suspended,
+    NoConsent,
+    UnknownDateOfBirth,
+    UnknownTeamAgeGroup(String),
+    TooOld { player: AgeGroup, team: AgeGroup },
+    TooYoungForAdults(u32),
+    PaymentOutstanding(f64),
+    PaymentFailed(i32),
+    // Warnings only: the player can still take part
+    PlayingUp { player: AgeGroup, team: AgeGroup },
+    OtherTeam(String),
+}
+
+pub struct EligibilityCheck {
+    // Name or FAN ID as it appeared on the team sheet
+    pub entry: String,
+    pub fan_id: Option<String>,
+    pub name: Option<String>,
+    pub issues: Vec<EligibilityIssue>,
+}
+
+impl EligibilityIssue {
+    pub fn is_blocking(&self) -> bool {
+        !matches!(self, EligibilityIssue::PlayingUp { .. } | EligibilityIssue::OtherTeam(_))
+    }
+}
+
+impl fmt::Display for EligibilityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EligibilityIssue::NotFound => write!(f, "not found in Wholegame"),
+            EligibilityIssue::Ambiguous(count) => write!(f, "{} Wholegame players match; use the FAN ID", count),
+            EligibilityIssue::NotRegistered(status) if status.trim().is_empty() => write!(f, "not registered"),
+            EligibilityIssue::NotRegistered(status) => write!(f, "not registered (status '{}')", status.trim()),
+            EligibilityIssue::RegistrationExpired => write!(f, "registration expired"),
+            EligibilityIssue::Suspended => write!(f, "suspended"),
+            EligibilityIssue::NoConsent => write!(f, "no consent given"),
+            EligibilityIssue::UnknownDateOfBirth => write!(f, "date of birth missing or unreadable"),
+            EligibilityIssue::UnknownTeamAgeGroup(team) => write!(f, "age group of team '{}' not recognised", team.trim()),
+            EligibilityIssue::TooOld { player, team } => write!(f, "{} player cannot play down in a {} team", player, team),
+            EligibilityIssue::TooYoungForAdults(age) => write!(f, "aged {}, too young for an adult team", age),
+            EligibilityIssue::PaymentOutstanding(amount) => write!(f, "{:.2} outstanding in LoveAdmin", amount),
+            EligibilityIssue::PaymentFailed(count) => write!(f, "{} failed LoveAdmin payment(s)", count),
+            EligibilityIssue::PlayingUp { player, team } => write!(f, "{} player playing up in a {} team", player, team),
+            EligibilityIssue::OtherTeam(team) => write!(f, "registered to {}", team),
+        }
+    }
+}
+
+impl EligibilityCheck {
+    pub fn passed(&self) -> bool {
+        !self.issues.iter().any(EligibilityIssue::is_blocking)
+    }
+}
+
+// Team sheet entries, one per line or comma separated; blank lines and # comments are skipped
+pub fn parse_team_sheet(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split(','))
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+// An exact FAN ID wins; otherwise the name, preferring players in the fixture's team
+fn find_players<'a>(entry: &str, players: &'a [Wholegame], team: Option<&str>) -> Vec<&'a Wholegame> {
+    let by_fan_id: Vec<&Wholegame> = players.iter().filter(|player| player.get_fan_id().trim() == entry).collect();
+    if !by_fan_id.is_empty() {
+        return by_fan_id;
+    }
+    let by_name: Vec<&Wholegame> = players.iter().filter(|player| names_match(&full_name(player), entry)).collect();
+    if let Some(team) = team {
+        let in_team: Vec<&Wholegame> = by_name.iter().copied().filter(|player| teams_match(player.get_team(), team)).collect();
+        if !in_team.is_empty() {
+            return in_team;
+        }
+    }
+    by_name
+}
+
+fn check_player(
+    player: &Wholegame,
+    invoices: Option<&[LoveAdmin]>,
+    team: Option<&str>,
+    season: &SeasonConfig,
+    reference_date: NaiveDate,
+) -> Vec<EligibilityIssue> {
+    let mut issues = Vec::new();
+
+    if expiry_state(player, reference_date, 0) == Some(ExpiryState::Expired) {
+        issues.push(EligibilityIssue::RegistrationExpired);
+    } else if !is_registered(player, reference_date) {
+        issues.push(EligibilityIssue::NotRegistered(player.get_registration_status().clone()));
+    }
+    if player.is_suspended() {
+        issues.push(EligibilityIssue::Suspended);
+    }
+    if !player.is_consent_given() {
+        issues.push(EligibilityIssue::NoConsent);
+    }
+
+    let team_name = team.unwrap_or(player.get_team());
+    if team.is_some_and(|team| !teams_match(player.get_team(), team)) {
+        issues.push(EligibilityIssue::OtherTeam(player.get_team().clone()));
+    }
+    match (parse_date(player.get_date_of_birth()), AgeGroup::from_team(team_name)) {
+        (None, _) => issues.push(EligibilityIssue::UnknownDateOfBirth),
+        (Some(_), None) => issues.push(EligibilityIssue::UnknownTeamAgeGroup(team_name.to_string())),
+        (Some(dob), Some(AgeGroup::Adult)) if age_on(dob, reference_date) < MIN_ADULT_AGE => {
+            issues.push(EligibilityIssue::TooYoungForAdults(age_on(dob, reference_date)))
+        }
+        (Some(dob), Some(team_group)) => match season.age_group_for(dob) {
+            group if !group.can_play_in(team_group) => issues.push(EligibilityIssue::TooOld { player: group, team: team_group }),
+            group if group != team_group => issues.push(EligibilityIssue::PlayingUp { player: group, team: team_group }),
+            _ => {}
+        },
+    }
+
+    if let Some(invoices) = invoices {
+        let outstanding = outstanding_for_player(player, invoices);
+        if outstanding > AMOUNT_TOLERANCE {
+            issues.push(EligibilityIssue::PaymentOutstanding(outstanding));
+        }
+        let failed = invoices_for_player(player, invoices).iter().fold(0, |total, invoice| total + invoice.get_failed());
+        if failed > 0 {
+            issues.push(EligibilityIssue::PaymentFailed(failed));
+        }
+    }
+    issues
+}
+
+// Checks every entry on a team sheet. `team` is the team playing the fixture,
+// or None to judge each player against their own Wholegame team; payments are
+// only checked when invoices are given.
+pub fn check_team_sheet(
+    entries: &[String],
+    players: &[Wholegame],
+    invoices: Option<&[LoveAdmin]>,
+    team: Option<&str>,
+    season: &SeasonConfig,
+    reference_date: NaiveDate,
+) -> Vec<EligibilityCheck> {
+    entries
+        .iter()
+        .map(|entry| match find_players(entry, players, team).as_slice() {
+            [] => EligibilityCheck { entry: entry.clone(), fan_id: None, name: None, issues: vec![EligibilityIssue::NotFound] },
+            [player] => EligibilityCheck {
+                entry: entry.clone(),
+                fan_id: Some(player.get_fan_id().clone()),
+                name: Some(full_name(player)),
+                issues: check_player(player, invoices, team, season, reference_date),
+            },
+            matches => EligibilityCheck { entry: entry.clone(), fan_id: None, name: None, issues: vec![EligibilityIssue::Ambiguous(matches.len())] },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod eligibility_tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn player(first_names: &str, date_of_birth: &str, team: &str) -> Wholegame {
+        let mut player = fixtures::player(first_names, team);
+        player.set_date_of_birth(date_of_birth.to_string());
+        player.set_registration_status("Registered".to_string());
+        player.set_registration_expiry(Some("2024-07-31".to_string()));
+        player.set_consent_given(true);
+        player
+    }
+
+    fn invoice(name: &str, outstanding: f64, failed: i32) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, "", "", 0.0);
+        invoice.set_outstanding(outstanding);
+        invoice.set_failed(failed);
+        invoice
+    }
+
+    #[test]
+    fn test_parse_team_sheet() {
+        assert_eq!(parse_team_sheet("Amy Doe, fan-Ben\n\n# subs\nCal Doe # keeper\n"), vec!["Amy Doe", "fan-Ben", "Cal Doe"]);
+    }
+
+    #[test]
+    fn test_team_sheet_pass_and_fail_reasons() {
+        let mut expired = player("Cal", "2012-01-01", "Wanderers U12 Reds");
+        expired.set_registration_expiry(Some("2023-09-30".to_string()));
+        let mut suspended = player("Dan", "2012-01-01", "Wanderers U12 Reds");
+        suspended.set_suspended(true);
+        suspended.set_consent_given(false);
+        let players = vec![
+            player("Amy", "2012-01-01", "Wanderers U12 Reds"),
+            player("Ben", "2013-01-01", "Wanderers U11 Reds"),
+            expired,
+            suspended,
+            player("Eve", "2010-01-01", "Wanderers U14 Reds"),
+            player("Fay", "2012-01-01", "Wanderers U12 Reds"),
+            player("Fay", "2013-03-01", "Wanderers U11 Reds"),
+            player("Hal", "2008-01-01", "Wanderers Open Age"),
+            player("Ian", "2007-01-01", "Wanderers Open Age"),
+            player("Jo", "2012-01-01", "Wanderers Reserves"),
+        ];
+        let invoices = vec![invoice("Amy Doe", 20.0, 1)];
+        let entries = parse_team_sheet("Amy Doe\nfan-Ben\nCal Doe\nDan Doe\nEve Doe\nFay Doe\nGus Doe");
+        let season = SeasonConfig::new(2023);
+        let date = parse_date("2023-10-14").unwrap();
+
+        let checks = check_team_sheet(&entries, &players, None, Some("U12 Reds"), &season, date);
+        let results: Vec<(bool, Vec<String>)> =
+            checks.iter().map(|check| (check.passed(), check.issues.iter().map(|issue| issue.to_string()).collect())).collect();
+        assert_eq!(
+            results,
+            vec![
+                (true, vec![]),
+                (true, vec!["registered to Wanderers U11 Reds".to_string(), "U11 player playing up in a U12 team".to_string()]),
+                (false, vec!["registration expired".to_string()]),
+                (false, vec!["suspended".to_string(), "no consent given".to_string()]),
+                (false, vec!["registered to Wanderers U14 Reds".to_string(), "U14 player cannot play down in a U12 team".to_string()]),
+                (true, vec![]),
+                (false, vec!["not found in Wholegame".to_string()]),
+            ]
+        );
+        assert_eq!(checks[5].fan_id.as_deref(), Some("fan-Fay"));
+
+        let with_payments = check_team_sheet(&entries[..1], &players, Some(&invoices), Some("U12 Reds"), &season, date);
+        assert_eq!(with_payments[0].issues, vec![EligibilityIssue::PaymentOutstanding(20.0), EligibilityIssue::PaymentFailed(1)]);
+
+        // Each judged against their own team
+        let own_teams = check_team_sheet(&parse_team_sheet("Hal Doe, Ian Doe, Jo Doe"), &players, None, None, &season, date);
+        let results: Vec<(bool, &Vec<EligibilityIssue>)> = own_teams.iter().map(|check| (check.passed(), &check.issues)).collect();
+        assert_eq!(
+            results,
+            vec![
+                (false, &vec![EligibilityIssue::TooYoungForAdults(15)]),
+                (true, &vec![EligibilityIssue::PlayingUp { player: AgeGroup::Under(17), team: AgeGroup::Adult }]),
+                (false, &vec![EligibilityIssue::UnknownTeamAgeGroup("Wanderers Reserves".to_string())]),
+            ]
+        );
+    }
+}
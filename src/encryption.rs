@@ -0,0 +1,297 @@
+use std::panic::AssertUnwindSafe;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::audit;
+use crate::database::{delete_encryption_settings, get_encryption_settings, save_encryption_settings, EncryptionSettings};
+
+// Environment variables the CLI reads passphrases from, so they never appear in `ps`
+pub const PASSPHRASE_VARIABLE: &str = "LOVEADMIN_PASSPHRASE";
+pub const NEW_PASSPHRASE_VARIABLE: &str = "LOVEADMIN_NEW_PASSPHRASE";
+
+// OWASP's 2023 recommendation for PBKDF2-HMAC-SHA256
+pub const DEFAULT_ITERATIONS: u32 = 600_000;
+
+// Wholegame columns holding personal data we don't need in plain text.
+// FAN ID, names and teams stay readable so lookups and matching still work.
+pub const SENSITIVE_COLUMNS: [&str; 5] = ["DateOfBirth", "EmailAddress", "ParentCarerEmailAddress", "EmergencyContact", "EmergencyContactPhoneNumber"];
+// Audit entries copy whole records, so their values are encrypted as well
+pub const AUDIT_COLUMNS: [&str; 2] = ["Before", "After"];
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_BYTES: usize = 12;
+const SALT_BYTES: usize = 16;
+// Encrypted with the key so a wrong passphrase is caught before it garbles anything
+const KEY_CHECK: &str = "loveadmin-tool key check";
+
+#[derive(Clone)]
+pub struct FieldCipher {
+    cipher: Aes256Gcm,
+}
+
+impl FieldCipher {
+    pub fn derive(passphrase: &str, salt: &[u8], iterations: u32) -> Self {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+        FieldCipher { cipher: Aes256Gcm::new(&key.into()) }
+    }
+
+    pub fn encrypt(&self, value: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(&nonce, value.as_bytes()).expect("AES-GCM encryption of a string cannot fail");
+        let mut bytes = nonce.to_vec();
+        bytes.extend(ciphertext);
+        format!("{}{}", PREFIX, BASE64.encode(bytes))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String, String> {
+        let encoded = value.strip_prefix(PREFIX).ok_or("value is not encrypted")?;
+        let bytes = BASE64.decode(encoded).map_err(|_| "encrypted value is corrupt")?;
+        if bytes.len() < NONCE_BYTES {
+            return Err("encrypted value is corrupt".to_string());
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_BYTES);
+        let plain = self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|_| "cannot decrypt value: wrong passphrase?")?;
+        String::from_utf8(plain).map_err(|_| "decrypted value is not text".to_string())
+    }
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+fn user_error(message: String) -> rusqlite::Error {
+    rusqlite::Error::UserFunctionError(message.into())
+}
+
+// (Re)defines the encrypt_field and decrypt_field SQL functions used by the
+// wholegame queries. Without a cipher values pass through unchanged and
+// reading an encrypted value is an error.
+pub fn register_functions(conn: &Connection, cipher: Option<FieldCipher>) -> rusqlite::Result<()> {
+    let encrypting = AssertUnwindSafe(cipher.clone());
+    conn.create_scalar_function("encrypt_field", 1, FunctionFlags::SQLITE_UTF8, move |ctx| {
+        let value: Option<String> = ctx.get(0)?;
+        Ok(match (&*encrypting, value) {
+            (Some(cipher), Some(value)) if !value.is_empty() && !is_encrypted(&value) => Some(cipher.encrypt(&value)),
+            (_, value) => value,
+        })
+    })?;
+    let decrypting = AssertUnwindSafe(cipher);
+    conn.create_scalar_function("decrypt_field", 1, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, move |ctx| {
+        let value: Option<String> = ctx.get(0)?;
+        match (&*decrypting, value) {
+            (Some(cipher), Some(value)) if is_encrypted(&value) => cipher.decrypt(&value).map(Some).map_err(user_error),
+            (None, Some(value)) if is_encrypted(&value) => {
+                Err(user_error(format!("the database is encrypted; set {} to read it", PASSPHRASE_VARIABLE)))
+            }
+            (_, value) => Ok(value),
+        }
+    })
+}
+
+fn cipher_for(settings: &EncryptionSettings, passphrase: &str) -> Result<FieldCipher, String> {
+    let salt = BASE64.decode(&settings.salt).map_err(|_| "stored encryption salt is corrupt".to_string())?;
+    let cipher = FieldCipher::derive(passphrase, &salt, settings.iterations);
+    match cipher.decrypt(&settings.key_check) {
+        Ok(check) if check == KEY_CHECK => Ok(cipher),
+        _ => Err("Wrong passphrase for this database".to_string()),
+    }
+}
+
+fn new_key(passphrase: &str, iterations: u32) -> (FieldCipher, EncryptionSettings) {
+    let mut salt = [0u8; SALT_BYTES];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = FieldCipher::derive(passphrase, &salt, iterations);
+    let settings = EncryptionSettings {
+        salt: BASE64.encode(salt),
+        iterations,
+        key_check: cipher.encrypt(KEY_CHECK),
+        created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    (cipher, settings)
+}
+
+pub fn is_enabled(conn: &Connection) -> rusqlite::Result<bool> {
+    Ok(get_encryption_settings(conn)?.is_some())
+}
+
+// Makes an encrypted database readable on this connection. Unencrypted
+// databases need no passphrase; encrypted ones fail without the right one.
+pub fn unlock(conn: &Connection, passphrase: Option<&str>) -> Result<(), String> {
+    let settings = match get_encryption_settings(conn).map_err(|e| e.to_string())? {
+        Some(settings) => settings,
+        None => return Ok(()),
+    };
+    let passphrase = passphrase.ok_or_else(|| format!("The database is encrypted; set {} to open it", PASSPHRASE_VARIABLE))?;
+    let cipher = cipher_for(&settings, passphrase)?;
+    register_functions(conn, Some(cipher)).map_err(|e| e.to_string())
+}
+
+// Rewrites every sensitive value, decrypting with `from` and encrypting with
+// `to`. Returns the Wholegame rows rewritten.
+fn recrypt(conn: &Connection, from: Option<&FieldCipher>, to: Option<&FieldCipher>) -> Result<usize, String> {
+    recrypt_table(conn, "audit_log", &AUDIT_COLUMNS, from, to)?;
+    recrypt_table(conn, "wholegame", &SENSITIVE_COLUMNS, from, to)
+}
+
+fn recrypt_table(conn: &Connection, table: &str, columns: &[&str], from: Option<&FieldCipher>, to: Option<&FieldCipher>) -> Result<usize, String> {
+    let sql = format!("SELECT id, {} FROM {}", columns.join(", "), table);
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            let values: rusqlite::Result<Vec<Option<String>>> = (1..=columns.len()).map(|index| row.get(index)).collect();
+            Ok((row.get::<_, i64>(0)?, values?))
+        })
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+        .map_err(|e| e.to_string())?;
+
+    let assignments: Vec<String> = columns.iter().enumerate().map(|(index, column)| format!("{} = ?{}", column, index + 2)).collect();
+    let update = format!("UPDATE {} SET {} WHERE id = ?1", table, assignments.join(", "));
+    for (id, values) in &rows {
+        let mut rewritten = Vec::with_capacity(values.len());
+        for value in values {
+            let plain = match (value, from) {
+                (Some(value), Some(from)) if is_encrypted(value) => Some(from.decrypt(value).map_err(|e| format!("{} row {}: {}", table, id, e))?),
+                (Some(value), None) if is_encrypted(value) => return Err(format!("{} row {} is encrypted with an unknown key", table, id)),
+                (value, _) => value.clone(),
+            };
+            rewritten.push(match (plain, to) {
+                (Some(plain), Some(to)) if !plain.is_empty() => Some(to.encrypt(&plain)),
+                (plain, _) => plain,
+            });
+        }
+        let values = std::iter::once(SqlValue::Integer(*id)).chain(rewritten.into_iter().map(|value| value.map_or(SqlValue::Null, SqlValue::Text)));
+        conn.execute(&update, params_from_iter(values)).map_err(|e| e.to_string())?;
+    }
+    Ok(rows.len())
+}
+
+// Recorded before the values are rewritten so the entry ends up under the new key
+fn record_change(conn: &Connection, status: &str) -> Result<i64, String> {
+    audit::record(conn, "encryption", "encryption", None, Some(&json!({ "status": status }))).map_err(|e| e.to_string())
+}
+
+// Encrypts the sensitive columns of every existing row; later inserts on this
+// connection are encrypted as they're written. Returns the rows rewritten.
+pub fn enable(conn: &Connection, passphrase: &str, iterations: u32) -> Result<usize, String> {
+    if is_enabled(conn).map_err(|e| e.to_string())? {
+        return Err("Encryption is already enabled; use `encryption rotate` to change the passphrase".to_string());
+    }
+    let (cipher, settings) = new_key(passphrase, iterations);
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    record_change(&tx, "enabled")?;
+    let count = recrypt(&tx, None, Some(&cipher))?;
+    save_encryption_settings(&tx, &settings).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    register_functions(conn, Some(cipher)).map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+// Re-encrypts everything under a key derived from the new passphrase with a fresh salt
+pub fn rotate(conn: &Connection, old_passphrase: &str, new_passphrase: &str, iterations: u32) -> Result<usize, String> {
+    let settings = get_encryption_settings(conn).map_err(|e| e.to_string())?.ok_or("Encryption is not enabled")?;
+    let old = cipher_for(&settings, old_passphrase)?;
+    let (new, settings) = new_key(new_passphrase, iterations);
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    record_change(&tx, "rotated")?;
+    let count = recrypt(&tx, Some(&old), Some(&new))?;
+    save_encryption_settings(&tx, &settings).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    register_functions(conn, Some(new)).map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+pub fn disable(conn: &Connection, passphrase: &str) -> Result<usize, String> {
+    let settings = get_encryption_settings(conn).map_err(|e| e.to_string())?.ok_or("Encryption is not enabled")?;
+    let cipher = cipher_for(&settings, passphrase)?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    record_change(&tx, "disabled")?;
+    let count = recrypt(&tx, Some(&cipher), None)?;
+    delete_encryption_settings(&tx).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    register_functions(conn, None).map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod encryption_tests {
+    use super::*;
+    use crate::data_structures::Wholegame;
+    use crate::database::{create_tables, get_all_wholegame, get_wholegame_by_fan_id, insert_wholegame, setup_database};
+
+    // Keeps the tests quick; real keys use DEFAULT_ITERATIONS
+    const TEST_ITERATIONS: u32 = 1_000;
+
+    fn player(fan_id: &str, email: &str) -> Wholegame {
+        let mut player = Wholegame::new();
+        player.set_first_names("Jane".to_string());
+        player.set_surname("Doe".to_string());
+        player.set_fan_id(fan_id.to_string());
+        player.set_date_of_birth("2012-05-01".to_string());
+        player.set_email_address(email.to_string());
+        player.set_emergency_contact_phone_number(Some("07700 900123".to_string()));
+        player
+    }
+
+    fn raw_date_of_birth(conn: &Connection, fan_id: &str) -> String {
+        conn.query_row("SELECT DateOfBirth FROM wholegame WHERE FAN_ID = ?1", [fan_id], |row| row.get(0)).unwrap()
+    }
+
+    fn raw_audit_values(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn.prepare("SELECT After FROM audit_log ORDER BY id").unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn test_enable_rotate_and_disable() {
+        let path = std::env::temp_dir().join(format!("encryption-{}.db", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let conn = setup_database(Some(path)).unwrap();
+        create_tables(&conn).unwrap();
+        insert_wholegame(&conn, &player("100", "jane@example.com")).unwrap();
+
+        assert_eq!(enable(&conn, "first secret", TEST_ITERATIONS), Ok(1));
+        assert!(is_encrypted(&raw_date_of_birth(&conn, "100")));
+        assert!(raw_audit_values(&conn).iter().all(|value| is_encrypted(value)));
+        insert_wholegame(&conn, &player("200", "")).unwrap();
+        assert!(is_encrypted(&raw_date_of_birth(&conn, "200")));
+        let found = get_wholegame_by_fan_id(&conn, "100").unwrap().unwrap();
+        assert_eq!(found.get_date_of_birth(), "2012-05-01");
+        assert_eq!(found.get_emergency_contact_phone_number().as_deref(), Some("07700 900123"));
+        assert_eq!(get_all_wholegame(&conn).unwrap()[1].get_email_address(), "");
+
+        // A fresh connection can't read personal data until it's unlocked
+        let reopened = setup_database(Some(path)).unwrap();
+        assert!(get_all_wholegame(&reopened).is_err());
+        assert!(unlock(&reopened, None).is_err());
+        assert_eq!(unlock(&reopened, Some("wrong")), Err("Wrong passphrase for this database".to_string()));
+        unlock(&reopened, Some("first secret")).unwrap();
+        assert_eq!(get_all_wholegame(&reopened).unwrap().len(), 2);
+
+        assert!(rotate(&conn, "wrong", "second secret", TEST_ITERATIONS).is_err());
+        assert_eq!(rotate(&conn, "first secret", "second secret", TEST_ITERATIONS), Ok(2));
+        let reopened = setup_database(Some(path)).unwrap();
+        assert!(unlock(&reopened, Some("first secret")).is_err());
+        unlock(&reopened, Some("second secret")).unwrap();
+        assert_eq!(get_wholegame_by_fan_id(&reopened, "200").unwrap().unwrap().get_date_of_birth(), "2012-05-01");
+        let history = crate::database::get_audit_entries(&reopened, &crate::database::AuditFilter { limit: 10, ..Default::default() }).unwrap();
+        assert_eq!(history.len(), 4);
+        assert!(history[3].after.as_deref().unwrap().contains("jane@example.com"));
+
+        assert_eq!(disable(&conn, "second secret"), Ok(2));
+        assert_eq!(raw_date_of_birth(&conn, "100"), "2012-05-01");
+        assert!(raw_audit_values(&conn).iter().all(|value| !is_encrypted(value)));
+        unlock(&setup_database(Some(path)).unwrap(), None).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
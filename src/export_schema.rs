@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde_derive::Deserialize;
+
+use crate::import::{normalise_header, Sheet, LOVEADMIN_SOURCE, WHOLEGAME_SOURCE};
+
+// The columns one version of an export is known to have. Newer versions go
+// at the end of KNOWN_SCHEMAS; the closest one to a file is reported.
+pub struct ExportSchema {
+    pub source: &'static str,
+    pub version: &'static str,
+    pub columns: &'static [&'static str],
+    // Without these the file can't be imported at all
+    pub required: &'static [&'static str],
+}
+
+pub const LOVEADMIN_2023: ExportSchema = ExportSchema {
+    source: LOVEADMIN_SOURCE,
+    version: "2023",
+    columns: &["Name", "Account Owner", "Product", "Date", "Invoiced", "Paid", "Pending", "Outstanding", "Failed", "Days Overdue", "Last Reminder Sent"],
+    required: &["Name", "Product", "Date", "Invoiced"],
+};
+
+pub const WHOLEGAME_2023: ExportSchema = ExportSchema {
+    source: WHOLEGAME_SOURCE,
+    version: "2023",
+    columns: &[
+        "First names",
+        "Surname",
+        "FAN ID",
+        "Date of Birth",
+        "Age Group",
+        "Gender",
+        "Suspended",
+        "Team",
+        "Date Submitted",
+        "Date Registered",
+        "Registration Expiry",
+        "Registration Status",
+        "Email Address",
+        "Parent/Carer Name",
+        "Parent/Carer Email Address",
+        "Emergency Contact",
+        "Emergency Contact Phone Number",
+        "Other Clubs",
+        "Consent Given",
+        "Contract Status",
+        "Photo Uploaded Date",
+    ],
+    required: &["First names", "Surname", "FAN ID"],
+};
+
+pub const KNOWN_SCHEMAS: &[ExportSchema] = &[LOVEADMIN_2023, WHOLEGAME_2023];
+
+// One line of the alias CSV (columns: source, column, alias): a header
+// called `alias` in that source's files is read as the known `column`
+#[derive(Deserialize)]
+struct AliasRow {
+    source: String,
+    column: String,
+    alias: String,
+}
+
+// Header renames to apply before checking a file, keyed by source and the
+// normalised alias
+#[derive(Default)]
+pub struct ColumnAliases {
+    by_source: HashMap<(String, String), String>,
+}
+
+// How a file's header row compares with the closest known schema
+#[derive(Debug, Default, PartialEq)]
+pub struct SchemaCheck {
+    pub source: String,
+    pub version: Option<&'static str>,
+    // Columns the file has that the schema doesn't
+    pub added: Vec<String>,
+    // Schema columns the file doesn't have
+    pub removed: Vec<String>,
+    // (schema column, file column) pairs that look like the same column
+    // under a new name; these are left out of added and removed
+    pub renamed: Vec<(String, String)>,
+    pub missing_required: Vec<String>,
+    // (file column, schema column) pairs applied from the alias map
+    pub aliased: Vec<(String, String)>,
+}
+
+impl ColumnAliases {
+    pub fn new() -> Self {
+        ColumnAliases::default()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("Cannot read column aliases {}: {}", path.display(), e))?;
+        let mut aliases = ColumnAliases::new();
+        for (index, row) in reader.deserialize::<AliasRow>().enumerate() {
+            let row = row.map_err(|e| format!("Column aliases line {}: {}", index + 2, e))?;
+            aliases.add(&row.source, &row.column, &row.alias);
+        }
+        Ok(aliases)
+    }
+
+    pub fn add(&mut self, source: &str, column: &str, alias: &str) {
+        self.by_source.insert((source.trim().to_lowercase(), normalise_header(alias)), column.trim().to_string());
+    }
+
+    // Renames aliased headers in place, returning (file column, schema column) pairs
+    pub fn apply(&self, source: &str, sheet: &mut Sheet) -> Vec<(String, String)> {
+        let mut applied = Vec::new();
+        for header in sheet.headers.iter_mut() {
+            if let Some(column) = self.by_source.get(&(source.to_string(), normalise_header(header))) {
+                applied.push((header.clone(), column.clone()));
+                *header = column.clone();
+            }
+        }
+        applied
+    }
+}
+
+fn words(header: &str) -> Vec<String> {
+    let mut words: Vec<String> = header.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).map(str::to_lowercase).collect();
+    words.sort();
+    words
+}
+
+// Levenshtein distance, for telling a respelt header from an unrelated one
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            current.push((previous[j] + usize::from(a != *b)).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Same words in another order, one name inside the other ("Invoiced" and
+// "Amount Invoiced") or a couple of letters different in a longer name
+fn looks_renamed(known: &str, found: &str) -> bool {
+    let (known_key, found_key) = (normalise_header(known), normalise_header(found));
+    words(known) == words(found)
+        || (known_key.len() >= 4 && found_key.contains(&known_key))
+        || (found_key.len() >= 4 && known_key.contains(&found_key))
+        || (known_key.len().min(found_key.len()) >= 6 && edit_distance(&known_key, &found_key) <= 2)
+}
+
+fn compare(schema: &ExportSchema, headers: &[String]) -> SchemaCheck {
+    let found: Vec<&String> = headers.iter().filter(|header| !header.trim().is_empty()).collect();
+    let has = |column: &str| found.iter().any(|header| normalise_header(header) == normalise_header(column));
+    let mut removed: Vec<String> = schema.columns.iter().filter(|column| !has(column)).map(|column| column.to_string()).collect();
+    let mut added: Vec<String> = found
+        .iter()
+        .filter(|header| !schema.columns.iter().any(|column| normalise_header(column) == normalise_header(header)))
+        .map(|header| header.trim().to_string())
+        .collect();
+    let mut renamed = Vec::new();
+    removed.retain(|column| match added.iter().position(|header| looks_renamed(column, header)) {
+        Some(index) => {
+            renamed.push((column.clone(), added.remove(index)));
+            false
+        }
+        None => true,
+    });
+    let missing_required = schema.required.iter().filter(|column| !has(column)).map(|column| column.to_string()).collect();
+    SchemaCheck { source: schema.source.to_string(), version: Some(schema.version), added, removed, renamed, missing_required, aliased: Vec::new() }
+}
+
+// Compares a header row with each known schema for the source and keeps the
+// closest. Aliases should already have been applied to the headers.
+pub fn check_headers(source: &str, headers: &[String]) -> SchemaCheck {
+    KNOWN_SCHEMAS
+        .iter()
+        .rev()
+        .filter(|schema| schema.source == source)
+        .map(|schema| compare(schema, headers))
+        .min_by_key(|check| (check.missing_required.len(), check.renamed.len() + check.removed.len() + check.added.len()))
+        .unwrap_or_else(|| SchemaCheck { source: source.to_string(), ..SchemaCheck::default() })
+}
+
+// Applies the aliases for the source, then checks the result
+pub fn check_sheet(source: &str, sheet: &mut Sheet, aliases: &ColumnAliases) -> SchemaCheck {
+    let aliased = aliases.apply(source, sheet);
+    SchemaCheck { aliased, ..check_headers(source, &sheet.headers) }
+}
+
+impl SchemaCheck {
+    pub fn is_exact(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+    }
+
+    // What the import batch records: the closest version, marked when the
+    // file didn't match it exactly
+    pub fn version_label(&self) -> String {
+        match self.version {
+            Some(version) if self.is_exact() => version.to_string(),
+            Some(version) => format!("{} (changed)", version),
+            None => "unknown".to_string(),
+        }
+    }
+
+    // Renamed and missing required columns stop the import; the data would
+    // otherwise be read as blank without anyone noticing
+    pub fn problem(&self) -> Option<String> {
+        let alias_hint = |column: &str, header: &str| format!("{},{},{}", self.source, column, header);
+        if !self.renamed.is_empty() {
+            let renames: Vec<String> = self.renamed.iter().map(|(column, header)| format!("'{}' now looks to be '{}'", column, header)).collect();
+            let hints: Vec<String> = self.renamed.iter().map(|(column, header)| alias_hint(column, header)).collect();
+            return Some(format!(
+                "The {} export format has changed since version {}: {}. If these are the same columns, add them to a column alias file (source,column,alias): {}",
+                self.source,
+                self.version.unwrap_or("unknown"),
+                renames.join(", "),
+                hints.join("; ")
+            ));
+        }
+        if !self.missing_required.is_empty() {
+            let mut message = format!("Missing column(s): {}", self.missing_required.join(", "));
+            if !self.added.is_empty() {
+                message.push_str(&format!(" (the file has unrecognised column(s): {})", self.added.join(", ")));
+            }
+            return Some(message);
+        }
+        None
+    }
+
+    // Drift that doesn't stop the import, to show whoever ran it
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.removed.is_empty() {
+            warnings.push(format!("column(s) missing from this {} export and left blank: {}", self.source, self.removed.join(", ")));
+        }
+        if !self.added.is_empty() {
+            warnings.push(format!("new column(s) in this {} export, ignored: {}", self.source, self.added.join(", ")));
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for SchemaCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} format {}", self.source, self.version_label())?;
+        for (header, column) in &self.aliased {
+            write!(f, ", '{}' read as '{}'", header, column)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod export_schema_tests {
+    use super::*;
+
+    fn headers(columns: &[&str]) -> Vec<String> {
+        columns.iter().map(|column| column.to_string()).collect()
+    }
+
+    #[test]
+    fn test_detects_added_removed_and_renamed_columns() {
+        let exact = check_headers(LOVEADMIN_SOURCE, &headers(LOVEADMIN_2023.columns));
+        assert!(exact.is_exact());
+        assert_eq!(exact.version_label(), "2023");
+
+        let mut columns: Vec<&str> = LOVEADMIN_2023.columns.iter().copied().filter(|column| !["Days Overdue", "Pending"].contains(column)).collect();
+        columns.extend(["Overdue Days", "Discount"]);
+        let drifted = check_headers(LOVEADMIN_SOURCE, &headers(&columns));
+        assert_eq!(drifted.renamed, vec![("Days Overdue".to_string(), "Overdue Days".to_string())]);
+        assert_eq!((drifted.removed.clone(), drifted.added.clone()), (vec!["Pending".to_string()], vec!["Discount".to_string()]));
+        assert_eq!(drifted.version_label(), "2023 (changed)");
+        let problem = drifted.problem().unwrap();
+        assert!(problem.contains("'Days Overdue' now looks to be 'Overdue Days'"), "{}", problem);
+        assert!(problem.contains("loveadmin,Days Overdue,Overdue Days"));
+
+        let missing = check_headers(WHOLEGAME_SOURCE, &headers(&["Forename", "Surname", "Team"]));
+        assert_eq!(missing.problem(), Some("Missing column(s): First names, FAN ID (the file has unrecognised column(s): Forename)".to_string()));
+        assert!(looks_renamed("Photo Uploaded Date", "Photo Upload Date"));
+        assert!(!looks_renamed("Paid", "Pending"));
+    }
+
+    #[test]
+    fn test_aliases_rename_headers_before_checking() {
+        let mut aliases = ColumnAliases::new();
+        aliases.add("loveadmin", "Days Overdue", "Overdue Days");
+        let mut columns: Vec<&str> = LOVEADMIN_2023.columns.iter().copied().filter(|column| *column != "Days Overdue").collect();
+        columns.push("overdue days");
+        let mut sheet = Sheet { headers: headers(&columns), rows: Vec::new(), row_numbers: Vec::new() };
+
+        let check = check_sheet(LOVEADMIN_SOURCE, &mut sheet, &aliases);
+        assert!(check.is_exact() && check.problem().is_none());
+        assert_eq!(check.to_string(), "loveadmin format 2023, 'overdue days' read as 'Days Overdue'");
+        assert_eq!(sheet.headers.last().map(String::as_str), Some("Days Overdue"));
+        assert!(check_sheet(WHOLEGAME_SOURCE, &mut Sheet { headers: headers(&["Overdue Days"]), rows: Vec::new(), row_numbers: Vec::new() }, &aliases).aliased.is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde_derive::Deserialize;
+
+use crate::age_groups::AgeGroup;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::dates::parse_date;
+use crate::households::Household;
+use crate::matching::{full_name, normalise_name, team_from_product, AMOUNT_TOLERANCE};
+
+// One line of the fee schedule CSV (columns: team, age_group, fee). A row
+// with a team applies to that team, one with only an age group to every team
+// in it, and a row with neither is the club-wide default.
+#[derive(Deserialize)]
+struct FeeRow {
+    team: Option<String>,
+    age_group: Option<String>,
+    fee: f64,
+}
+
+pub struct FeeSchedule {
+    by_team: HashMap<String, f64>,
+    by_age_group: HashMap<String, f64>,
+    default: Option<f64>,
+}
+
+// Percentage off for the second child in a family and for each child after that
+pub struct SiblingDiscount {
+    pub second_child_percent: f64,
+    pub further_children_percent: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FeeFinding {
+    Overcharged { expected: f64, invoiced: f64 },
+    Undercharged { expected: f64, invoiced: f64 },
+    NoFeeConfigured,
+}
+
+pub struct FeeCheck {
+    pub name: String,
+    pub account_owner: String,
+    pub product: String,
+    pub date: String,
+    // 1 for the eldest child in the household
+    pub sibling_position: usize,
+    pub finding: FeeFinding,
+}
+
+impl FeeSchedule {
+    pub fn new() -> Self {
+        FeeSchedule { by_team: HashMap::new(), by_age_group: HashMap::new(), default: None }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("Cannot read fee schedule {}: {}", path.display(), e))?;
+        let mut schedule = FeeSchedule::new();
+        for (index, row) in reader.deserialize::<FeeRow>().enumerate() {
+            let row = row.map_err(|e| format!("Fee schedule line {}: {}", index + 2, e))?;
+            match (row.team, row.age_group) {
+                (Some(team), _) => schedule.set_team_fee(&team, row.fee),
+                (None, Some(age_group)) => {
+                    let group = AgeGroup::parse(&age_group).ok_or_else(|| format!("Unknown age group '{}' in fee schedule", age_group))?;
+                    schedule.set_age_group_fee(group, row.fee);
+                }
+                (None, None) => schedule.default = Some(row.fee),
+            }
+        }
+        Ok(schedule)
+    }
+
+    pub fn set_team_fee(&mut self, team: &str, fee: f64) {
+        self.by_team.insert(normalise_name(team), fee);
+    }
+
+    pub fn set_age_group_fee(&mut self, group: AgeGroup, fee: f64) {
+        self.by_age_group.insert(group.to_string(), fee);
+    }
+
+    pub fn fee_for(&self, team: &str, age_group: Option<AgeGroup>) -> Option<f64> {
+        self.by_team
+            .get(&normalise_name(team))
+            .or_else(|| age_group.and_then(|group| self.by_age_group.get(&group.to_string())))
+            .copied()
+            .or(self.default)
+    }
+}
+
+impl SiblingDiscount {
+    pub fn percent_for(&self, sibling_position: usize) -> f64 {
+        match sibling_position {
+            0 | 1 => 0.0,
+            2 => self.second_child_percent,
+            _ => self.further_children_percent,
+        }
+    }
+}
+
+impl fmt::Display for FeeFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeeFinding::Overcharged { expected, invoiced } => {
+                write!(f, "overcharged {:.2} (invoiced {:.2}, expected {:.2})", invoiced - expected, invoiced, expected)
+            }
+            FeeFinding::Undercharged { expected, invoiced } => {
+                write!(f, "undercharged {:.2} (invoiced {:.2}, expected {:.2})", expected - invoiced, invoiced, expected)
+            }
+            FeeFinding::NoFeeConfigured => write!(f, "no fee configured for this team or age group"),
+        }
+    }
+}
+
+// Position of each Wholegame player within their household, eldest first,
+// so the discount falls on the younger siblings. A missing date of birth
+// counts as youngest rather than letting that child take the full fee.
+fn sibling_positions(players: &[Wholegame], households: &[Household]) -> HashMap<String, usize> {
+    let by_fan_id: HashMap<&str, &Wholegame> = players.iter().map(|player| (player.get_fan_id().as_str(), player)).collect();
+    let mut positions = HashMap::new();
+    for household in households {
+        let mut members: Vec<&Wholegame> = household.fan_ids.iter().filter_map(|fan_id| by_fan_id.get(fan_id.as_str()).copied()).collect();
+        members.sort_by_key(|player| {
+            let date_of_birth = parse_date(player.get_date_of_birth());
+            (date_of_birth.is_none(), date_of_birth)
+        });
+        for (index, player) in members.iter().enumerate() {
+            positions.insert(normalise_name(&full_name(player)), index + 1);
+        }
+    }
+    positions
+}
+
+// Compares each team subscription invoice with what the player should be
+// charged. Invoices for people who aren't in Wholegame are left out.
+pub fn verify_fees(
+    players: &[Wholegame],
+    invoices: &[LoveAdmin],
+    households: &[Household],
+    schedule: &FeeSchedule,
+    discount: &SiblingDiscount,
+) -> Vec<FeeCheck> {
+    let positions = sibling_positions(players, households);
+    let by_name: HashMap<String, &Wholegame> = players.iter().map(|player| (normalise_name(&full_name(player)), player)).collect();
+
+    let mut checks = Vec::new();
+    for invoice in invoices {
+        let key = normalise_name(invoice.get_name());
+        let player = match by_name.get(&key) {
+            Some(player) => player,
+            None => continue,
+        };
+        // Only team subscriptions are priced by the schedule; one-off products aren't
+        let team = match team_from_product(invoice.get_product()) {
+            Some(team) => team,
+            None => continue,
+        };
+        let age_group = AgeGroup::from_team(&team).or_else(|| AgeGroup::parse(player.get_age_group()));
+        let sibling_position = positions.get(&key).copied().unwrap_or(1);
+
+        let finding = match schedule.fee_for(&team, age_group) {
+            None => Some(FeeFinding::NoFeeConfigured),
+            Some(fee) => {
+                let expected = ((fee * (100.0 - discount.percent_for(sibling_position))).round()) / 100.0;
+                let invoiced = invoice.get_invoiced();
+                if invoiced - expected > AMOUNT_TOLERANCE {
+                    Some(FeeFinding::Overcharged { expected, invoiced })
+                } else if expected - invoiced > AMOUNT_TOLERANCE {
+                    Some(FeeFinding::Undercharged { expected, invoiced })
+                } else {
+                    None
+                }
+            }
+        };
+        if let Some(finding) = finding {
+            checks.push(FeeCheck {
+                name: invoice.get_name().clone(),
+                account_owner: invoice.get_account_owner().clone(),
+                product: invoice.get_product().clone(),
+                date: invoice.get_date().clone(),
+                sibling_position,
+                finding,
+            });
+        }
+    }
+    checks
+}
+
+#[cfg(test)]
+mod fees_tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::households::build_households;
+    use std::fs;
+
+    fn child(first_names: &str, date_of_birth: &str, team: &str) -> Wholegame {
+        let mut player = fixtures::player(first_names, team);
+        player.set_date_of_birth(date_of_birth.to_string());
+        player.set_parent_carer_email_address(Some("jo@example.com".to_string()));
+        player
+    }
+
+    fn invoice(name: &str, product: &str, invoiced: f64) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, product, "", invoiced);
+        invoice.set_account_owner("Jo Doe".to_string());
+        invoice
+    }
+
+    #[test]
+    fn test_load_schedule_priorities() {
+        let path = std::env::temp_dir().join(format!("fees-{}.csv", uuid::Uuid::new_v4()));
+        fs::write(&path, "team,age_group,fee\nU12 Reds,,25\n,U12,20\n,,15\n").unwrap();
+        let schedule = FeeSchedule::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(schedule.fee_for("u12  reds", Some(AgeGroup::Under(12))), Some(25.0));
+        assert_eq!(schedule.fee_for("U12 Blues", Some(AgeGroup::Under(12))), Some(20.0));
+        assert_eq!(schedule.fee_for("U9 Blues", Some(AgeGroup::Under(9))), Some(15.0));
+    }
+
+    #[test]
+    fn test_verify_fees_applies_sibling_discount() {
+        let players = vec![child("Amy", "2010-01-01", "U14 Reds"), child("Ben", "2012-01-01", "U12 Reds"), child("Cal", "2014-01-01", "U10 Reds")];
+        let invoices = vec![
+            invoice("Amy Doe", "U14 Reds (*)", 20.0),
+            invoice("Ben Doe", "U12 Reds (*)", 20.0),
+            invoice("Cal Doe", "U10 Reds (*)", 15.0),
+            invoice("Dee Doe", "U10 Reds (*)", 20.0),
+            invoice("Amy Doe", "Summer Camp", 50.0),
+        ];
+        let mut schedule = FeeSchedule::new();
+        schedule.set_age_group_fee(AgeGroup::Under(14), 20.0);
+        schedule.set_age_group_fee(AgeGroup::Under(12), 20.0);
+        schedule.set_age_group_fee(AgeGroup::Under(10), 20.0);
+        let discount = SiblingDiscount { second_child_percent: 10.0, further_children_percent: 20.0 };
+        let households = build_households(&players, &invoices);
+
+        let checks = verify_fees(&players, &invoices, &households, &schedule, &discount);
+
+        let findings: Vec<(&str, usize, &FeeFinding)> = checks.iter().map(|check| (check.name.as_str(), check.sibling_position, &check.finding)).collect();
+        assert_eq!(
+            findings,
+            vec![
+                ("Ben Doe", 2, &FeeFinding::Overcharged { expected: 18.0, invoiced: 20.0 }),
+                ("Cal Doe", 3, &FeeFinding::Undercharged { expected: 16.0, invoiced: 15.0 }),
+            ]
+        );
+
+        let players = vec![child("Amy", "", "U14 Reds"), child("Ben", "2012-01-01", "U12 Reds")];
+        let positions = sibling_positions(&players, &build_households(&players, &invoices));
+        assert_eq!((positions["ben doe"], positions["amy doe"]), (1, 2));
+    }
+}
@@ -0,0 +1,23 @@
+// Builders shared by the test modules. Each test sets whatever else it
+// cares about on top of these.
+use crate::data_structures::{LoveAdmin, Wholegame};
+
+// A member of the Doe family whose FAN ID is "fan-<first names>"
+pub fn player(first_names: &str, team: &str) -> Wholegame {
+    let mut player = Wholegame::new();
+    player.set_first_names(first_names.to_string());
+    player.set_surname("Doe".to_string());
+    player.set_fan_id(format!("fan-{}", first_names));
+    player.set_team(team.to_string());
+    player
+}
+
+// Nothing paid, pending or outstanding until the test says so
+pub fn invoice(name: &str, product: &str, date: &str, invoiced: f64) -> LoveAdmin {
+    let mut invoice = LoveAdmin::new();
+    invoice.set_name(name.to_string());
+    invoice.set_product(product.to_string());
+    invoice.set_date(date.to_string());
+    invoice.set_invoiced(invoiced);
+    invoice
+}
@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::age_groups::SeasonConfig;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::dates::{next_month, previous_month};
+use crate::matching::{full_name, invoice_month, invoices_for_player, normalise_name, team_from_product, teams_match};
+use crate::registration::is_registered;
+
+pub struct MonthForecast {
+    pub month: (i32, u32),
+    pub team: String,
+    pub forecast: f64,
+    // None for months that haven't been invoiced yet
+    pub actual: Option<f64>,
+}
+
+pub struct ForecastPlayer {
+    pub name: String,
+    pub team: String,
+    pub amount: f64,
+}
+
+pub struct SeasonForecast {
+    pub latest_month: Option<(i32, u32)>,
+    pub months: Vec<MonthForecast>,
+    // Invoiced the month before the latest month but not in it
+    pub lapsed: Vec<ForecastPlayer>,
+    // Registered in Wholegame but never invoiced, with the amount we expect to bill
+    pub unbilled: Vec<ForecastPlayer>,
+}
+
+impl SeasonForecast {
+    // Actual income for months already invoiced plus the forecast for the rest
+    pub fn projected_total(&self) -> f64 {
+        self.months.iter().fold(0.0, |total, month| total + month.actual.unwrap_or(month.forecast))
+    }
+}
+
+// Team subscription amounts per month, per team, keyed by normalised player
+// name and holding the name as LoveAdmin spells it
+type Subscriptions = BTreeMap<(i32, u32), BTreeMap<String, HashMap<String, (String, f64)>>>;
+
+fn subscriptions(invoices: &[LoveAdmin]) -> Subscriptions {
+    let mut by_month: Subscriptions = BTreeMap::new();
+    for invoice in invoices {
+        if let (Some(month), Some(team)) = (invoice_month(invoice), team_from_product(invoice.get_product())) {
+            by_month
+                .entry(month)
+                .or_default()
+                .entry(team)
+                .or_default()
+                .entry(normalise_name(invoice.get_name()))
+                .or_insert_with(|| (invoice.get_name().trim().to_string(), 0.0))
+                .1 += invoice.get_invoiced();
+        }
+    }
+    by_month
+}
+
+fn team_total(subscriptions: &Subscriptions, month: (i32, u32), team: &str) -> Option<f64> {
+    subscriptions
+        .get(&month)
+        .and_then(|teams| teams.get(team))
+        .map(|players| players.values().fold(0.0, |total, (_, amount)| total + amount))
+}
+
+// Projects income for each month of the season per team. Months up to the
+// latest invoiced month show what was actually invoiced against a forecast
+// made from the month before; later months repeat the latest month's
+// subscriptions (so lapsed players drop out) plus an estimate for registered
+// players who haven't been billed yet, at their team's average subscription.
+pub fn season_forecast(players: &[Wholegame], invoices: &[LoveAdmin], season: &SeasonConfig, reference_date: NaiveDate) -> SeasonForecast {
+    let subscriptions = subscriptions(invoices);
+    let latest_month = match subscriptions.keys().next_back() {
+        Some(month) => *month,
+        None => return SeasonForecast { latest_month: None, months: Vec::new(), lapsed: Vec::new(), unbilled: Vec::new() },
+    };
+    let latest = &subscriptions[&latest_month];
+
+    let mut lapsed = Vec::new();
+    let still_billed: BTreeSet<&String> = latest.values().flat_map(|players| players.keys()).collect();
+    if let Some(previous) = subscriptions.get(&previous_month(latest_month)) {
+        for (team, team_players) in previous {
+            for (key, (name, amount)) in team_players {
+                if !still_billed.contains(key) {
+                    lapsed.push(ForecastPlayer { name: name.clone(), team: team.clone(), amount: *amount });
+                }
+            }
+        }
+    }
+    lapsed.sort_by(|a, b| (&a.team, &a.name).cmp(&(&b.team, &b.name)));
+
+    let all_amounts: Vec<f64> = latest.values().flat_map(|players| players.values().map(|(_, amount)| *amount)).collect();
+    let club_average = all_amounts.iter().fold(0.0, |total, amount| total + amount) / all_amounts.len().max(1) as f64;
+    let mut unbilled = Vec::new();
+    for player in players {
+        if !is_registered(player, reference_date) || !invoices_for_player(player, invoices).is_empty() {
+            continue;
+        }
+        let team = latest
+            .keys()
+            .find(|team| teams_match(player.get_team(), team))
+            .cloned()
+            .unwrap_or_else(|| player.get_team().clone());
+        let amount = latest
+            .get(&team)
+            .map(|players| players.values().fold(0.0, |total, (_, amount)| total + amount) / players.len() as f64)
+            .unwrap_or(club_average);
+        unbilled.push(ForecastPlayer { name: full_name(player), team, amount });
+    }
+
+    let mut teams: BTreeSet<String> = subscriptions.values().flat_map(|teams| teams.keys().cloned()).collect();
+    teams.extend(unbilled.iter().map(|player| player.team.clone()));
+
+    let first_month = next_month((season.start_year, season.cut_off_date().month()));
+    let mut months = Vec::new();
+    let mut month = first_month;
+    for _ in 0..12 {
+        for team in &teams {
+            let entry = if month <= latest_month {
+                MonthForecast {
+                    month,
+                    team: team.clone(),
+                    forecast: team_total(&subscriptions, previous_month(month), team).unwrap_or(0.0),
+                    actual: Some(team_total(&subscriptions, month, team).unwrap_or(0.0)),
+                }
+            } else {
+                let new_players = unbilled.iter().filter(|player| &player.team == team).fold(0.0, |total, player| total + player.amount);
+                MonthForecast {
+                    month,
+                    team: team.clone(),
+                    forecast: team_total(&subscriptions, latest_month, team).unwrap_or(0.0) + new_players,
+                    actual: None,
+                }
+            };
+            months.push(entry);
+        }
+        month = next_month(month);
+    }
+
+    SeasonForecast { latest_month: Some(latest_month), months, lapsed, unbilled }
+}
+
+#[cfg(test)]
+mod forecast_tests {
+    use super::*;
+    use crate::dates::parse_date;
+    use crate::fixtures::{self, invoice};
+
+    fn registered(first_names: &str, team: &str) -> Wholegame {
+        let mut player = fixtures::player(first_names, team);
+        player.set_registration_status("Registered".to_string());
+        player
+    }
+
+    fn month<'a>(forecast: &'a SeasonForecast, month: (i32, u32), team: &str) -> &'a MonthForecast {
+        forecast.months.iter().find(|entry| entry.month == month && entry.team == team).unwrap()
+    }
+
+    #[test]
+    fn test_forecast_drops_lapsed_and_adds_unbilled_players() {
+        let invoices = vec![
+            invoice("Amy Doe", "U12 Reds (*)", "2023-09-01", 20.0),
+            invoice("Ben Doe", "U12 Reds (*)", "2023-09-01", 20.0),
+            invoice("Cal Doe", "U14 Blues (*)", "2023-09-01", 25.0),
+            invoice("Amy Doe", "U12 Reds (*)", "2023-10-01", 20.0),
+            invoice("Cal Doe", "U14 Blues (*)", "2023-10-01", 25.0),
+            invoice("Amy Doe", "Summer Camp", "2023-10-01", 50.0),
+        ];
+        let players = vec![
+            registered("Amy", "Wanderers U12 Reds"),
+            registered("Dan", "Wanderers U12 Reds"),
+            registered("Eve", "Wanderers U9 Whites"),
+        ];
+
+        let forecast = season_forecast(&players, &invoices, &SeasonConfig::new(2023), parse_date("2023-10-15").unwrap());
+
+        assert_eq!(forecast.latest_month, Some((2023, 10)));
+        let lapsed: Vec<&str> = forecast.lapsed.iter().map(|player| player.name.as_str()).collect();
+        assert_eq!(lapsed, vec!["Ben Doe"]);
+        let unbilled: Vec<(&str, &str, f64)> = forecast.unbilled.iter().map(|p| (p.name.as_str(), p.team.as_str(), p.amount)).collect();
+        assert_eq!(unbilled, vec![("Dan Doe", "U12 Reds", 20.0), ("Eve Doe", "Wanderers U9 Whites", 22.5)]);
+
+        let october = month(&forecast, (2023, 10), "U12 Reds");
+        assert_eq!((october.forecast, october.actual), (40.0, Some(20.0)));
+        let november = month(&forecast, (2023, 11), "U12 Reds");
+        assert_eq!((november.forecast, november.actual), (40.0, None));
+        assert_eq!(month(&forecast, (2024, 8), "U14 Blues").forecast, 25.0);
+        assert_eq!(forecast.months.len(), 36);
+        // Sep + Oct actuals (65 + 45) plus ten months at 40 + 25 + 22.5
+        assert_eq!(forecast.projected_total(), 110.0 + 10.0 * 87.5);
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use crate::api::{invoice_json, player_json};
+use crate::audit;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{
+    delete_exception, delete_wholegame, get_all_loveadmin_with_ids, get_all_wholegame, get_exception_records, get_import_batches,
+    update_loveadmin, update_wholegame, AuditEntry, ImportBatch,
+};
+use crate::import::{LOVEADMIN_SOURCE, WHOLEGAME_SOURCE};
+use crate::matching::{full_name, names_match, normalise_name};
+
+// How the person making the request is identified
+pub enum SubjectKey {
+    FanId(String),
+    Name(String),
+    Email(String),
+}
+
+// Why a record is about the subject
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Player,
+    ParentCarer,
+    EmergencyContact,
+    Member,
+    AccountOwner,
+}
+
+pub struct SubjectRecords {
+    pub players: Vec<(Vec<Role>, Wholegame)>,
+    pub invoices: Vec<(Vec<Role>, i64, LoveAdmin)>,
+    // The imports the current data came from; each import replaces the last
+    pub batches: Vec<ImportBatch>,
+    // What the audit log holds about those records, oldest first
+    pub audit: Vec<AuditEntry>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ErasureSummary {
+    pub players_deleted: usize,
+    pub players_updated: usize,
+    pub invoices_pseudonymised: usize,
+    pub exceptions_forgotten: usize,
+}
+
+impl fmt::Display for SubjectKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubjectKey::FanId(fan_id) => write!(f, "FAN ID {}", fan_id),
+            SubjectKey::Name(name) => write!(f, "name '{}'", name),
+            SubjectKey::Email(email) => write!(f, "email {}", email),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Player => write!(f, "player"),
+            Role::ParentCarer => write!(f, "parent/carer"),
+            Role::EmergencyContact => write!(f, "emergency contact"),
+            Role::Member => write!(f, "member"),
+            Role::AccountOwner => write!(f, "account owner"),
+        }
+    }
+}
+
+fn same_email(a: &str, b: &str) -> bool {
+    !a.trim().is_empty() && a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+fn optional_matches(value: &Option<String>, name: &str) -> bool {
+    value.as_deref().is_some_and(|value| names_match(value, name))
+}
+
+fn player_roles(player: &Wholegame, key: &SubjectKey) -> Vec<Role> {
+    let mut roles = Vec::new();
+    match key {
+        SubjectKey::FanId(fan_id) => {
+            if player.get_fan_id().trim() == fan_id.trim() {
+                roles.push(Role::Player);
+            }
+        }
+        SubjectKey::Name(name) => {
+            if names_match(&full_name(player), name) {
+                roles.push(Role::Player);
+            }
+            if optional_matches(player.get_parent_carer_name(), name) {
+                roles.push(Role::ParentCarer);
+            }
+            if optional_matches(player.get_emergency_contact(), name) {
+                roles.push(Role::EmergencyContact);
+            }
+        }
+        SubjectKey::Email(email) => {
+            if same_email(player.get_email_address(), email) {
+                roles.push(Role::Player);
+            }
+            if player.get_parent_carer_email_address().as_deref().is_some_and(|address| same_email(address, email)) {
+                roles.push(Role::ParentCarer);
+            }
+        }
+    }
+    roles
+}
+
+// Finds every record about the subject. Invoices are linked by the member's
+// name to matched players and by the payer's name to matched parents.
+pub fn find_subject(conn: &Connection, key: &SubjectKey) -> rusqlite::Result<SubjectRecords> {
+    let players: Vec<(Vec<Role>, Wholegame)> = get_all_wholegame(conn)?
+        .into_iter()
+        .map(|player| (player_roles(&player, key), player))
+        .filter(|(roles, _)| !roles.is_empty())
+        .collect();
+
+    let mut member_names: HashSet<String> = HashSet::new();
+    let mut payer_names: HashSet<String> = HashSet::new();
+    if let SubjectKey::Name(name) = key {
+        member_names.insert(normalise_name(name));
+        payer_names.insert(normalise_name(name));
+    }
+    for (roles, player) in &players {
+        if roles.contains(&Role::Player) {
+            member_names.insert(normalise_name(&full_name(player)));
+        }
+        if roles.contains(&Role::ParentCarer) {
+            if let Some(parent) = player.get_parent_carer_name() {
+                payer_names.insert(normalise_name(parent));
+            }
+        }
+    }
+    member_names.remove("");
+    payer_names.remove("");
+
+    let invoices = get_all_loveadmin_with_ids(conn)?
+        .into_iter()
+        .filter_map(|(id, invoice)| {
+            let mut roles = Vec::new();
+            if member_names.contains(&normalise_name(invoice.get_name())) {
+                roles.push(Role::Member);
+            }
+            if payer_names.contains(&normalise_name(invoice.get_account_owner())) {
+                roles.push(Role::AccountOwner);
+            }
+            (!roles.is_empty()).then_some((roles, id, invoice))
+        })
+        .collect::<Vec<_>>();
+
+    let mut sources = Vec::new();
+    if !players.is_empty() {
+        sources.push(WHOLEGAME_SOURCE);
+    }
+    if !invoices.is_empty() {
+        sources.push(LOVEADMIN_SOURCE);
+    }
+    let mut batches = Vec::new();
+    for batch in get_import_batches(conn)? {
+        if sources.contains(&batch.source.as_str()) && !batches.iter().any(|seen: &ImportBatch| seen.source == batch.source) {
+            batches.push(batch);
+        }
+    }
+    let audit = audit::history(conn, &entities(&players, &invoices))?;
+    Ok(SubjectRecords { players, invoices, batches, audit })
+}
+
+// The audit log entities of the subject's records
+fn entities(players: &[(Vec<Role>, Wholegame)], invoices: &[(Vec<Role>, i64, LoveAdmin)]) -> Vec<String> {
+    let players = players.iter().map(|(_, player)| audit::player_entity(player.get_fan_id()));
+    players.chain(invoices.iter().map(|(_, id, _)| audit::invoice_entity(*id))).collect()
+}
+
+fn roles_json(roles: &[Role]) -> Value {
+    json!(roles.iter().map(Role::to_string).collect::<Vec<String>>())
+}
+
+fn totals(records: &SubjectRecords) -> (f64, f64, f64) {
+    records.invoices.iter().fold((0.0, 0.0, 0.0), |(invoiced, paid, outstanding), (_, _, invoice)| {
+        (invoiced + invoice.get_invoiced(), paid + invoice.get_paid(), outstanding + invoice.get_outstanding())
+    })
+}
+
+// Reminder dates as LoveAdmin records them: the last one sent per invoice
+fn reminders(records: &SubjectRecords) -> Vec<(i64, &LoveAdmin)> {
+    records.invoices.iter().filter(|(_, _, invoice)| !invoice.get_last_reminder_sent().trim().is_empty()).map(|(_, id, invoice)| (*id, invoice)).collect()
+}
+
+pub fn report_json(key: &SubjectKey, records: &SubjectRecords, generated_at: &str) -> Value {
+    let (invoiced, paid, outstanding) = totals(records);
+    json!({
+        "subject": key.to_string(),
+        "generated_at": generated_at,
+        "wholegame": records.players.iter().map(|(roles, player)| json!({ "roles": roles_json(roles), "record": player_json(player) })).collect::<Vec<Value>>(),
+        "loveadmin": records.invoices.iter().map(|(roles, id, invoice)| json!({ "roles": roles_json(roles), "record": invoice_json(Some(*id), invoice) })).collect::<Vec<Value>>(),
+        "totals": { "invoiced": invoiced, "paid": paid, "outstanding": outstanding },
+        "reminders": reminders(records).iter().map(|(id, invoice)| json!({
+            "invoice_id": id,
+            "product": invoice.get_product(),
+            "invoice_date": invoice.get_date(),
+            "last_reminder_sent": invoice.get_last_reminder_sent(),
+        })).collect::<Vec<Value>>(),
+        "import_provenance": records.batches.iter().map(|batch| json!({
+            "batch": batch.id,
+            "source": batch.source,
+            "file_name": batch.file_name,
+            "imported_at": batch.imported_at,
+            "row_count": batch.row_count,
+        })).collect::<Vec<Value>>(),
+        "audit_log": records.audit.iter().map(|entry| {
+            let parse = |value: &Option<String>| value.as_deref().and_then(|text| serde_json::from_str::<Value>(text).ok());
+            json!({
+                "timestamp": entry.timestamp,
+                "os_user": entry.os_user,
+                "action": entry.action,
+                "entity": entry.entity,
+                "before": parse(&entry.before),
+                "after": parse(&entry.after),
+            })
+        }).collect::<Vec<Value>>(),
+    })
+}
+
+fn optional(value: &Option<String>) -> &str {
+    value.as_deref().filter(|value| !value.trim().is_empty()).unwrap_or("-")
+}
+
+fn roles_text(roles: &[Role]) -> String {
+    roles.iter().map(Role::to_string).collect::<Vec<String>>().join(", ")
+}
+
+// A plain-text summary to send alongside the JSON
+pub fn write_summary<W: Write>(mut writer: W, key: &SubjectKey, records: &SubjectRecords, generated_at: &str) -> io::Result<()> {
+    writeln!(writer, "Subject access report for {} (generated {})", key, generated_at)?;
+    writeln!(writer)?;
+    writeln!(writer, "Wholegame registrations: {}", records.players.len())?;
+    for (roles, player) in &records.players {
+        writeln!(writer, "  {} (FAN ID {}), {} - held as {}", full_name(player), player.get_fan_id(), player.get_team(), roles_text(roles))?;
+        writeln!(writer, "    Date of birth {}, {} {}, registration {} until {}", player.get_date_of_birth(), player.get_age_group(), player.get_gender(), player.get_registration_status(), optional(player.get_registration_expiry()))?;
+        writeln!(writer, "    Email {}, parent/carer {} <{}>", player.get_email_address(), optional(player.get_parent_carer_name()), optional(player.get_parent_carer_email_address()))?;
+        writeln!(writer, "    Emergency contact {} ({}), consent {}, photo uploaded {}", optional(player.get_emergency_contact()), optional(player.get_emergency_contact_phone_number()), if player.is_consent_given() { "given" } else { "not given" }, optional(player.get_photo_uploaded_date()))?;
+    }
+    writeln!(writer)?;
+    let (invoiced, paid, outstanding) = totals(records);
+    writeln!(writer, "LoveAdmin invoices: {} (invoiced £{:.2}, paid £{:.2}, outstanding £{:.2})", records.invoices.len(), invoiced, paid, outstanding)?;
+    for (roles, id, invoice) in &records.invoices {
+        writeln!(
+            writer,
+            "  #{} {} {} for {} paid by {} - £{:.2} invoiced, £{:.2} paid, £{:.2} outstanding - held as {}",
+            id,
+            invoice.get_date(),
+            invoice.get_product(),
+            invoice.get_name(),
+            invoice.get_account_owner(),
+            invoice.get_invoiced(),
+            invoice.get_paid(),
+            invoice.get_outstanding(),
+            roles_text(roles)
+        )?;
+    }
+    writeln!(writer)?;
+    let reminders = reminders(records);
+    writeln!(writer, "Payment reminders: {}", reminders.len())?;
+    for (id, invoice) in reminders {
+        writeln!(writer, "  {} for invoice #{} ({})", invoice.get_last_reminder_sent(), id, invoice.get_product())?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "Where the data came from:")?;
+    if records.batches.is_empty() {
+        writeln!(writer, "  entered before imports were recorded")?;
+    }
+    for batch in &records.batches {
+        writeln!(writer, "  {} export {} imported {} (batch {}, {} rows)", batch.source, batch.file_name, batch.imported_at, batch.id, batch.row_count)?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "Audit log entries: {}", records.audit.len())?;
+    for entry in &records.audit {
+        writeln!(writer, "  {} {} by {} on {}: {}", entry.timestamp, entry.action, entry.os_user, entry.entity, audit::describe(entry))?;
+    }
+    Ok(())
+}
+
+// Random rather than derived from the name, so it can't be reversed
+fn pseudonym(prefix: &str) -> String {
+    format!("{} {}", prefix, &uuid::Uuid::new_v4().to_simple().to_string()[..8])
+}
+
+// Removes the subject while keeping every amount, so accounting totals don't
+// change. Their own Wholegame rows are deleted, their details are cleared from
+// children's rows, and their invoices get a pseudonym in place of the name.
+// The values the audit log holds for all of those records are redacted.
+pub fn erase(conn: &Connection, records: &SubjectRecords, dry_run: bool) -> rusqlite::Result<ErasureSummary> {
+    let mut summary = ErasureSummary::default();
+    let tx = conn.unchecked_transaction()?;
+    let member = pseudonym("Erased member");
+    let payer = pseudonym("Erased payer");
+    let mut forgotten: HashSet<String> = HashSet::new();
+    let mut entities = entities(&records.players, &records.invoices);
+
+    for (roles, player) in &records.players {
+        if roles.contains(&Role::Player) {
+            forgotten.insert(player.get_fan_id().trim().to_string());
+            forgotten.insert(normalise_name(&full_name(player)));
+            summary.players_deleted += 1;
+            if !dry_run {
+                delete_wholegame(&tx, player.get_fan_id())?;
+            }
+            continue;
+        }
+        let mut updated = player.clone();
+        if roles.contains(&Role::ParentCarer) {
+            updated.set_parent_carer_name(None);
+            updated.set_parent_carer_email_address(None);
+        }
+        if roles.contains(&Role::EmergencyContact) {
+            updated.set_emergency_contact(None);
+            updated.set_emergency_contact_phone_number(None);
+        }
+        summary.players_updated += 1;
+        if !dry_run {
+            update_wholegame(&tx, &updated)?;
+        }
+    }
+
+    for (roles, id, invoice) in &records.invoices {
+        let mut updated = invoice.clone();
+        if roles.contains(&Role::Member) {
+            forgotten.insert(normalise_name(invoice.get_name()));
+            updated.set_name(member.clone());
+        }
+        if roles.contains(&Role::AccountOwner) {
+            updated.set_account_owner(payer.clone());
+        }
+        summary.invoices_pseudonymised += 1;
+        if !dry_run {
+            update_loveadmin(&tx, *id, &updated)?;
+        }
+    }
+
+    // Tracked exceptions are keyed by FAN ID or name and hold notes about them
+    for record in get_exception_records(&tx)? {
+        if record.key.split(':').nth(1).is_some_and(|subject| forgotten.contains(subject)) {
+            summary.exceptions_forgotten += 1;
+            entities.push(audit::exception_entity(&record.key));
+            if !dry_run {
+                delete_exception(&tx, &record.key)?;
+            }
+        }
+    }
+
+    if !dry_run {
+        // Only counts, so the entry itself says nothing about who was erased
+        let after = json!({
+            "audit_entries_redacted": audit::redact(&tx, &entities)?,
+            "players_deleted": summary.players_deleted,
+            "players_updated": summary.players_updated,
+            "invoices_pseudonymised": summary.invoices_pseudonymised,
+            "exceptions_forgotten": summary.exceptions_forgotten,
+        });
+        audit::record(&tx, "erase", "subject", None, Some(&after))?;
+    }
+    tx.commit()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod gdpr_tests {
+    use super::*;
+    use crate::database::{create_tables, get_all_loveadmin, insert_loveadmin, insert_wholegame, resolve_exception, setup_database};
+    use crate::fixtures;
+    use crate::import::import_loveadmin;
+
+    fn player(first: &str, fan_id: &str, parent: &str, parent_email: &str) -> Wholegame {
+        let mut player = fixtures::player(first, "U12 Reds");
+        player.set_fan_id(fan_id.to_string());
+        player.set_parent_carer_name(Some(parent.to_string()));
+        player.set_parent_carer_email_address(Some(parent_email.to_string()));
+        player.set_emergency_contact(Some(parent.to_string()));
+        player
+    }
+
+    fn invoice(name: &str, owner: &str, amount: f64, reminder: &str) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, "U12 Reds (*)", "2023-10-01", amount);
+        invoice.set_account_owner(owner.to_string());
+        invoice.set_outstanding(amount);
+        invoice.set_last_reminder_sent(reminder.to_string());
+        invoice
+    }
+
+    fn database() -> Connection {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        insert_wholegame(&conn, &player("Amy", "100", "Jo Doe", "jo@example.com")).unwrap();
+        insert_wholegame(&conn, &player("Ben", "200", "Jo Doe", "JO@example.com")).unwrap();
+        insert_wholegame(&conn, &player("Cat", "300", "Al Poe", "al@example.com")).unwrap();
+        import_loveadmin(&conn, &[invoice("Amy Doe", "Jo Doe", 20.0, "2023-10-10"), invoice("Ben Doe", "Jo Doe", 15.0, ""), invoice("Cat Doe", "Al Poe", 10.0, "")], "october.csv", None).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_subject_access_by_fan_id_and_email() {
+        let conn = database();
+        let key = SubjectKey::FanId("100".to_string());
+        let records = find_subject(&conn, &key).unwrap();
+        assert_eq!(records.players.len(), 1);
+        assert_eq!(records.invoices.len(), 1);
+        assert_eq!(records.batches.len(), 1);
+
+        let report = report_json(&key, &records, "2023-10-15 09:00:00");
+        assert_eq!(report["subject"], "FAN ID 100");
+        assert_eq!(report["wholegame"][0]["roles"], json!(["player"]));
+        assert_eq!(report["reminders"][0]["last_reminder_sent"], "2023-10-10");
+        assert_eq!(report["import_provenance"][0]["file_name"], "october.csv");
+        assert_eq!(report["audit_log"][0]["entity"], "wholegame:100");
+        assert_eq!(report["audit_log"][0]["after"]["first_names"], "Amy");
+
+        let mut summary = Vec::new();
+        write_summary(&mut summary, &key, &records, "2023-10-15 09:00:00").unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        assert!(summary.contains("LoveAdmin invoices: 1 (invoiced £20.00, paid £0.00, outstanding £20.00)"));
+        assert!(summary.contains("  2023-10-10 for invoice #1 (U12 Reds (*))"));
+        assert!(summary.contains("Audit log entries: 1"));
+
+        // A parent identified by email is found on both children and as payer
+        let records = find_subject(&conn, &SubjectKey::Email("jo@example.com".to_string())).unwrap();
+        assert_eq!(records.players.len(), 2);
+        assert!(records.players.iter().all(|(roles, _)| roles == &vec![Role::ParentCarer]));
+        assert_eq!(records.invoices.len(), 2);
+        assert!(records.invoices.iter().all(|(roles, _, _)| roles == &vec![Role::AccountOwner]));
+    }
+
+    #[test]
+    fn test_erasure_keeps_totals() {
+        let conn = database();
+        insert_loveadmin(&conn, &invoice("Amy Doe", "Jo Doe", 5.0, "")).unwrap();
+        resolve_exception(&conn, "not-in-current-month:100:u12 reds", "2023-10-01").unwrap();
+        resolve_exception(&conn, "not-in-loveadmin:300:u12 reds", "2023-10-01").unwrap();
+        let total = |conn: &Connection| get_all_loveadmin(conn).unwrap().iter().fold(0.0, |total, invoice| total + invoice.get_invoiced());
+        let before = total(&conn);
+
+        let records = find_subject(&conn, &SubjectKey::Name("amy doe".to_string())).unwrap();
+        let planned = erase(&conn, &records, true).unwrap();
+        assert_eq!(planned, ErasureSummary { players_deleted: 1, players_updated: 0, invoices_pseudonymised: 2, exceptions_forgotten: 1 });
+        assert_eq!(find_subject(&conn, &SubjectKey::Name("amy doe".to_string())).unwrap().invoices.len(), 2);
+
+        assert_eq!(erase(&conn, &records, false).unwrap(), planned);
+        assert!(find_subject(&conn, &SubjectKey::FanId("100".to_string())).unwrap().players.is_empty());
+        let invoices = get_all_loveadmin(&conn).unwrap();
+        assert!(invoices[0].get_name().starts_with("Erased member "));
+        assert_eq!(invoices[0].get_name(), invoices[3].get_name());
+        assert_eq!(invoices[0].get_account_owner(), "Jo Doe");
+        assert_eq!(total(&conn), before);
+        assert_eq!(get_exception_records(&conn).unwrap().len(), 1);
+
+        // Erasing the parent clears them from the remaining child and pseudonymises the payer
+        let records = find_subject(&conn, &SubjectKey::Name("Jo Doe".to_string())).unwrap();
+        let summary = erase(&conn, &records, false).unwrap();
+        assert_eq!((summary.players_deleted, summary.players_updated, summary.invoices_pseudonymised), (0, 1, 3));
+        let ben = crate::database::get_wholegame_by_fan_id(&conn, "200").unwrap().unwrap();
+        assert!(ben.get_parent_carer_name().is_none() && ben.get_emergency_contact().is_none());
+        assert!(get_all_loveadmin(&conn).unwrap().iter().all(|invoice| invoice.get_account_owner() != "Jo Doe"));
+        assert_eq!(total(&conn), before);
+
+        // Earlier entries are kept but no longer hold either person's details
+        let mentions = |name: &str| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM audit_log WHERE Before LIKE '%' || ?1 || '%' OR After LIKE '%' || ?1 || '%'", [name], |row| row.get(0)).unwrap()
+        };
+        assert_eq!((mentions("Amy"), mentions("Jo Doe"), mentions("jo@example.com")), (0, 0, 0));
+        assert!(mentions("Cat") > 0);
+        let inserts: i64 = conn.query_row("SELECT COUNT(*) FROM audit_log WHERE Action = 'insert' AND Entity = 'wholegame:100'", [], |row| row.get(0)).unwrap();
+        assert_eq!(inserts, 1);
+        let erasures: Vec<String> = conn
+            .prepare("SELECT After FROM audit_log WHERE Action = 'erase' ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(erasures.len(), 2);
+        assert!(erasures[0].contains("\"audit_entries_redacted\":"));
+    }
+}
@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::database::{create_tables, get_all_loveadmin, get_all_wholegame, setup_database};
+use crate::export_schema::ColumnAliases;
+use crate::import::{import_file, LOVEADMIN_SOURCE, WHOLEGAME_SOURCE};
+use crate::reconciliation::{reconcile, Exception};
+use crate::validation::InvalidRows;
+
+// A case is a directory holding either or both exports and the expected
+// results; `generate` writes directories in the same layout
+pub const LOVEADMIN_FILE: &str = "loveadmin.csv";
+pub const WHOLEGAME_FILE: &str = "wholegame.xlsx";
+pub const EXPECTED_FILE: &str = "expected.json";
+
+// Where `cargo test` looks for cases, relative to the repository
+pub const DEFAULT_DIR: &str = "tests/golden";
+
+// Differences between a case's golden file and what reconciliation now finds,
+// matched up by exception key
+#[derive(Debug, Default)]
+pub struct GoldenDiff {
+    pub missing: Vec<Value>,
+    pub unexpected: Vec<Value>,
+    // (expected, found) for the same key
+    pub changed: Vec<(Value, Value)>,
+    // (what, expected, found) for the row counts
+    pub counts: Vec<(String, u64, u64)>,
+}
+
+pub enum CaseOutcome {
+    Passed,
+    Failed(GoldenDiff),
+    Updated,
+}
+
+// What a run over a case found, in the form the golden file records it
+pub struct CaseResult {
+    pub players: usize,
+    pub invoices: usize,
+    pub exceptions: Vec<Exception>,
+}
+
+impl GoldenDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.changed.is_empty() && self.counts.is_empty()
+    }
+}
+
+fn describe(exception: &Value) -> String {
+    format!("{} ({})", exception["key"].as_str().unwrap_or(""), exception["detail"].as_str().unwrap_or(""))
+}
+
+impl fmt::Display for GoldenDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (what, expected, found) in &self.counts {
+            writeln!(f, "  {}: expected {}, found {}", what, expected, found)?;
+        }
+        for exception in &self.missing {
+            writeln!(f, "  - {}", describe(exception))?;
+        }
+        for exception in &self.unexpected {
+            writeln!(f, "  + {}", describe(exception))?;
+        }
+        for (expected, found) in &self.changed {
+            writeln!(f, "  ~ {}", exception_key(expected))?;
+            writeln!(f, "      expected {}", expected)?;
+            writeln!(f, "      found    {}", found)?;
+        }
+        Ok(())
+    }
+}
+
+fn exception_key(exception: &Value) -> &str {
+    exception["key"].as_str().unwrap_or("")
+}
+
+// Imports the case's exports into a scratch database, the same way the
+// import command does, and reconciles them
+pub fn run_reconciliation(dir: &Path) -> Result<CaseResult, Box<dyn Error>> {
+    let conn = setup_database(Some(":memory:"))?;
+    create_tables(&conn)?;
+    for (source, file) in [(LOVEADMIN_SOURCE, LOVEADMIN_FILE), (WHOLEGAME_SOURCE, WHOLEGAME_FILE)] {
+        let path = dir.join(file);
+        if path.exists() {
+            import_file(&conn, source, &std::fs::read(&path)?, file, &ColumnAliases::new(), InvalidRows::Reject).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+    }
+    let players = get_all_wholegame(&conn)?;
+    let invoices = get_all_loveadmin(&conn)?;
+    Ok(CaseResult { players: players.len(), invoices: invoices.len(), exceptions: reconcile(&players, &invoices) })
+}
+
+pub fn compare(expected: &Value, result: &CaseResult) -> GoldenDiff {
+    let mut diff = GoldenDiff::default();
+    for (what, found) in [("players", result.players), ("invoices", result.invoices)] {
+        if let Some(count) = expected[what].as_u64().filter(|count| *count != found as u64) {
+            diff.counts.push((what.to_string(), count, found as u64));
+        }
+    }
+    let expected: Vec<Value> = expected["exceptions"].as_array().cloned().unwrap_or_default();
+    let found: Vec<Value> = result.exceptions.iter().map(Exception::to_json).collect();
+    for exception in &expected {
+        match found.iter().find(|other| exception_key(other) == exception_key(exception)) {
+            None => diff.missing.push(exception.clone()),
+            Some(other) if other != exception => diff.changed.push((exception.clone(), other.clone())),
+            Some(_) => {}
+        }
+    }
+    diff.unexpected = found.into_iter().filter(|exception| !expected.iter().any(|other| exception_key(other) == exception_key(exception))).collect();
+    diff
+}
+
+// Checks one case against its golden file. In update mode the file is
+// rewritten with what was found instead, keeping anything else recorded in it.
+pub fn run_case(dir: &Path, update: bool) -> Result<CaseOutcome, Box<dyn Error>> {
+    let result = run_reconciliation(dir)?;
+    let path = dir.join(EXPECTED_FILE);
+    let expected: Value = match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && update => json!({}),
+        Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+    };
+    let diff = compare(&expected, &result);
+    if diff.is_empty() {
+        return Ok(CaseOutcome::Passed);
+    }
+    if !update {
+        return Ok(CaseOutcome::Failed(diff));
+    }
+    let mut golden = if expected.is_object() { expected } else { json!({}) };
+    golden["players"] = json!(result.players);
+    golden["invoices"] = json!(result.invoices);
+    golden["exceptions"] = json!(result.exceptions.iter().map(Exception::to_json).collect::<Vec<Value>>());
+    std::fs::write(&path, serde_json::to_string_pretty(&golden)? + "\n")?;
+    Ok(CaseOutcome::Updated)
+}
+
+// Every directory under `root` with at least one export in it, in name order
+pub fn case_dirs(root: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = std::fs::read_dir(root)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join(LOVEADMIN_FILE).exists() || path.join(WHOLEGAME_FILE).exists())
+        .collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+#[cfg(test)]
+mod golden_tests {
+    use super::*;
+    use crate::reconciliation::ExceptionKind;
+
+    fn exception(name: &str, detail: &str) -> Exception {
+        Exception { kind: ExceptionKind::UnbilledPlayer, name: name.to_string(), team: "U12 Reds".to_string(), fan_id: None, detail: detail.to_string(), month: None }
+    }
+
+    #[test]
+    fn test_compare_matches_by_key() {
+        let expected = json!({
+            "players": 2,
+            "exceptions": [exception("Amy Doe", "never invoiced in LoveAdmin").to_json(), exception("Ben Doe", "never invoiced in LoveAdmin").to_json()],
+        });
+        let result = CaseResult {
+            players: 3,
+            invoices: 0,
+            exceptions: vec![exception("Amy Doe", "no team subscription in LoveAdmin"), exception("Cal Doe", "never invoiced in LoveAdmin")],
+        };
+        let diff = compare(&expected, &result);
+        assert_eq!(diff.counts, vec![("players".to_string(), 2, 3)]);
+        assert_eq!(diff.missing.len(), 1);
+        assert_eq!(exception_key(&diff.unexpected[0]), "not-in-loveadmin:cal doe:u12 reds");
+        assert_eq!(exception_key(&diff.changed[0].1), "not-in-loveadmin:amy doe:u12 reds");
+        assert!(diff.to_string().contains("  - not-in-loveadmin:ben doe:u12 reds (never invoiced in LoveAdmin)"));
+    }
+
+    // Runs every case under tests/golden. Set UPDATE_GOLDEN=1 to accept the
+    // current results after a deliberate change to the matching rules.
+    #[test]
+    fn test_golden_cases() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_DIR);
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let dirs = case_dirs(&root).unwrap();
+        assert!(!dirs.is_empty(), "no golden cases in {}", root.display());
+        let mut failures = String::new();
+        for dir in dirs {
+            if let CaseOutcome::Failed(diff) = run_case(&dir, update).unwrap() {
+                failures.push_str(&format!("{}:\n{}", dir.display(), diff));
+            }
+        }
+        assert!(failures.is_empty(), "golden files differ (rerun with UPDATE_GOLDEN=1 to accept):\n{}", failures);
+    }
+}
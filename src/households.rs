@@ -0,0 +1,219 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::matching::{full_name, names_match, normalise_name};
+
+// A family as seen from both systems: the children Wholegame lists under a
+// parent/carer and the LoveAdmin account owner(s) paying for them.
+pub struct Household {
+    // Wholegame players only
+    pub fan_ids: Vec<String>,
+    // Also includes anyone invoiced by the family who isn't in Wholegame
+    pub players: Vec<String>,
+    pub parent_carer_names: BTreeSet<String>,
+    pub parent_carer_emails: BTreeSet<String>,
+    pub account_owners: BTreeSet<String>,
+    pub invoiced: f64,
+    pub paid: f64,
+    pub pending: f64,
+    pub outstanding: f64,
+}
+
+impl Household {
+    fn new() -> Self {
+        Household {
+            fan_ids: Vec::new(),
+            players: Vec::new(),
+            parent_carer_names: BTreeSet::new(),
+            parent_carer_emails: BTreeSet::new(),
+            account_owners: BTreeSet::new(),
+            invoiced: 0.0,
+            paid: 0.0,
+            pending: 0.0,
+            outstanding: 0.0,
+        }
+    }
+
+    // Best name to address the family by
+    pub fn label(&self) -> String {
+        self.account_owners
+            .iter()
+            .chain(&self.parent_carer_names)
+            .next()
+            .cloned()
+            .unwrap_or_else(|| self.players.join(", "))
+    }
+
+    fn add_invoice(&mut self, invoice: &LoveAdmin) {
+        self.account_owners.insert(invoice.get_account_owner().trim().to_string());
+        self.invoiced += invoice.get_invoiced();
+        self.paid += invoice.get_paid();
+        self.pending += invoice.get_pending();
+        self.outstanding += invoice.get_outstanding();
+    }
+}
+
+fn find(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[index] = root;
+    root
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = normalise_name(value);
+    if value.is_empty() { None } else { Some(value) }
+}
+
+// Players are in the same household when they share a parent/carer email,
+// a parent/carer name, or are paid for by the same LoveAdmin account owner.
+// Invoices for someone not in Wholegame are attached by account owner.
+pub fn build_households(players: &[Wholegame], invoices: &[LoveAdmin]) -> Vec<Household> {
+    let mut invoices_by_player: HashMap<String, Vec<&LoveAdmin>> = HashMap::new();
+    for invoice in invoices {
+        invoices_by_player.entry(normalise_name(invoice.get_name())).or_default().push(invoice);
+    }
+
+    // Keys that tie players together, each mapped to the first player seen with it
+    let mut parents: Vec<usize> = (0..players.len()).collect();
+    let mut first_with_key: HashMap<String, usize> = HashMap::new();
+    for (index, player) in players.iter().enumerate() {
+        let mut keys = Vec::new();
+        if let Some(email) = player.get_parent_carer_email_address().as_deref().and_then(non_empty) {
+            keys.push(format!("email:{}", email));
+        }
+        let name = player.get_parent_carer_name().as_deref().and_then(non_empty);
+        if let Some(name) = name {
+            keys.push(format!("payer:{}", name));
+        }
+        for invoice in invoices_by_player.get(&normalise_name(&full_name(player))).into_iter().flatten() {
+            if let Some(owner) = non_empty(invoice.get_account_owner()) {
+                keys.push(format!("payer:{}", owner));
+            }
+        }
+        for key in &keys {
+            match first_with_key.get(key) {
+                Some(&other) => {
+                    let (a, b) = (find(&mut parents, index), find(&mut parents, other));
+                    parents[a] = b;
+                }
+                None => {
+                    first_with_key.insert(key.clone(), index);
+                }
+            }
+        }
+    }
+
+    let mut households: Vec<Household> = Vec::new();
+    let mut household_of_root: HashMap<usize, usize> = HashMap::new();
+    for (index, player) in players.iter().enumerate() {
+        let root = find(&mut parents, index);
+        let household_index = *household_of_root.entry(root).or_insert_with(|| {
+            households.push(Household::new());
+            households.len() - 1
+        });
+        let household = &mut households[household_index];
+        household.fan_ids.push(player.get_fan_id().clone());
+        household.players.push(full_name(player));
+        if let Some(name) = player.get_parent_carer_name().as_deref().filter(|name| !name.trim().is_empty()) {
+            household.parent_carer_names.insert(name.trim().to_string());
+        }
+        if let Some(email) = player.get_parent_carer_email_address().as_deref().filter(|email| !email.trim().is_empty()) {
+            household.parent_carer_emails.insert(email.trim().to_lowercase());
+        }
+    }
+
+    let mut player_names: HashMap<String, usize> = HashMap::new();
+    for (index, player) in players.iter().enumerate() {
+        player_names.entry(normalise_name(&full_name(player))).or_insert(index);
+    }
+    // Invoices for people not in Wholegame, keyed by account owner
+    let mut unmatched_households: HashMap<String, usize> = HashMap::new();
+    for invoice in invoices {
+        let owner = non_empty(invoice.get_account_owner()).unwrap_or_default();
+        let by_player = player_names.get(&normalise_name(invoice.get_name())).copied();
+        let by_payer = || first_with_key.get(&format!("payer:{}", owner)).copied();
+        let household = match by_player.or_else(by_payer) {
+            Some(index) => household_of_root[&find(&mut parents, index)],
+            None => *unmatched_households.entry(owner).or_insert_with(|| {
+                households.push(Household::new());
+                households.len() - 1
+            }),
+        };
+        let household = &mut households[household];
+        if by_player.is_none() && !household.players.iter().any(|name| names_match(name, invoice.get_name())) {
+            household.players.push(invoice.get_name().trim().to_string());
+        }
+        household.add_invoice(invoice);
+    }
+
+    households.sort_by_key(|household| household.label().to_lowercase());
+    households
+}
+
+#[cfg(test)]
+mod households_tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn child(first_names: &str, parent: Option<&str>, email: Option<&str>) -> Wholegame {
+        let mut player = fixtures::player(first_names, "");
+        player.set_parent_carer_name(parent.map(String::from));
+        player.set_parent_carer_email_address(email.map(String::from));
+        player
+    }
+
+    fn invoice(name: &str, owner: &str, invoiced: f64, paid: f64) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, "", "", invoiced);
+        invoice.set_account_owner(owner.to_string());
+        invoice.set_paid(paid);
+        invoice.set_outstanding(invoiced - paid);
+        invoice
+    }
+
+    #[test]
+    fn test_siblings_grouped_by_email_name_and_account_owner() {
+        let players = vec![
+            child("Amy", Some("Jo Doe"), Some("jo@example.com")),
+            child("Ben", Some("Joanne Doe"), Some("JO@example.com ")),
+            child("Cal", None, None),
+            child("Dan", Some("Sam Smith"), Some("sam@example.com")),
+        ];
+        let invoices = vec![
+            invoice("Amy Doe", "Joanne Doe", 20.0, 20.0),
+            invoice("Ben Doe", "Joanne Doe", 20.0, 10.0),
+            invoice("Cal Doe", "joanne  doe", 20.0, 0.0),
+            invoice("Dan Doe", "Sam Smith", 25.0, 25.0),
+        ];
+
+        let households = build_households(&players, &invoices);
+
+        assert_eq!(households.len(), 2);
+        let does = &households[0];
+        assert_eq!(does.players, vec!["Amy Doe", "Ben Doe", "Cal Doe"]);
+        assert_eq!(does.parent_carer_emails.len(), 1);
+        assert_eq!((does.invoiced, does.paid, does.outstanding), (60.0, 30.0, 30.0));
+        assert_eq!(households[1].label(), "Sam Smith");
+    }
+
+    #[test]
+    fn test_unmatched_invoices_grouped_by_account_owner() {
+        let players = vec![child("Amy", Some("Jo Doe"), Some("jo@example.com"))];
+        let invoices = vec![
+            invoice("Amy Doe", "Jo Doe", 20.0, 20.0),
+            invoice("Zed Doe", "Jo Doe", 20.0, 0.0),
+            invoice("Kim Lee", "Pat Lee", 20.0, 0.0),
+            invoice("Lou Lee", "Pat Lee", 20.0, 0.0),
+        ];
+
+        let households = build_households(&players, &invoices);
+
+        assert_eq!(households.len(), 2);
+        assert_eq!(households[0].players, vec!["Amy Doe", "Zed Doe"]);
+        assert_eq!(households[0].outstanding, 20.0);
+        assert_eq!(households[1].label(), "Pat Lee");
+        assert_eq!(households[1].invoiced, 40.0);
+    }
+}
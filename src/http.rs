@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+
+// Uploads bigger than this are refused rather than read into memory
+const MAX_BODY_BYTES: u64 = 20 * 1024 * 1024;
+
+// A request as the handlers see it, independent of the server library so
+// they can be tested by calling them directly
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    // Header names are lowercased
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str, body: Vec<u8>) -> Self {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        Request { method: method.to_uppercase(), path: url_decode(path), query: parse_query(query), headers: HashMap::new(), body }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_lowercase(), value.trim().to_string());
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    // Query parameter, treating an empty value as absent
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(|value| value.trim()).filter(|value| !value.is_empty())
+    }
+
+    // Fields of an application/x-www-form-urlencoded body
+    pub fn form(&self) -> HashMap<String, String> {
+        parse_query(&String::from_utf8_lossy(&self.body))
+    }
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Response { status, content_type: content_type.to_string(), headers: Vec::new(), body }
+    }
+
+    pub fn html(body: String) -> Self {
+        Response::new(200, "text/html; charset=utf-8", body.into_bytes())
+    }
+
+    pub fn text(status: u16, message: &str) -> Self {
+        Response::new(status, "text/plain; charset=utf-8", message.as_bytes().to_vec())
+    }
+
+    pub fn not_found() -> Self {
+        Response::text(404, "Not found")
+    }
+
+    // 303 so a browser follows a form POST with a GET
+    pub fn redirect(location: &str) -> Self {
+        let mut response = Response::text(303, "See other");
+        response.headers.push(("Location".to_string(), location.to_string()));
+        response
+    }
+
+    // A file the browser should save rather than show
+    pub fn download(content_type: &str, file_name: &str, body: Vec<u8>) -> Self {
+        let mut response = Response::new(200, content_type, body);
+        response.headers.push(("Content-Disposition".to_string(), format!("attachment; filename=\"{}\"", file_name)));
+        response
+    }
+}
+
+pub fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (url_decode(name), url_decode(value))
+        })
+        .collect()
+}
+
+pub fn escape_html(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Serves requests one at a time until the process is stopped. SQLite access
+// is single threaded anyway and the expected load is a handful of volunteers.
+pub fn serve<F>(address: &str, mut handler: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&Request) -> Response,
+{
+    let server = tiny_http::Server::http(address).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+    println!("Listening on http://{}/ (Ctrl+C to stop)", address);
+    for mut incoming in server.incoming_requests() {
+        let mut body = Vec::new();
+        if incoming.body_length().is_some_and(|length| length as u64 > MAX_BODY_BYTES) {
+            let _ = incoming.respond(tiny_http::Response::from_string("Upload too large").with_status_code(413));
+            continue;
+        }
+        if let Err(e) = incoming.as_reader().take(MAX_BODY_BYTES).read_to_end(&mut body) {
+            eprintln!("Cannot read request body: {}", e);
+            continue;
+        }
+        let mut request = Request::new(&incoming.method().to_string(), incoming.url(), body);
+        for header in incoming.headers() {
+            request = request.with_header(header.field.as_str().as_str(), header.value.as_str());
+        }
+        let response = handler(&request);
+        // The query can hold names and search terms, so it stays out of the log
+        println!("{} {} {}", request.method, request.path, response.status);
+
+        let mut reply = tiny_http::Response::from_data(response.body).with_status_code(response.status);
+        let headers = std::iter::once(("Content-Type".to_string(), response.content_type)).chain(response.headers);
+        for (name, value) in headers {
+            if let Ok(header) = tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+                reply = reply.with_header(header);
+            }
+        }
+        if let Err(e) = incoming.respond(reply) {
+            eprintln!("Cannot send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::*;
+
+    #[test]
+    fn test_request_parses_path_query_and_form() {
+        let request = Request::new("post", "/reconciliation?team=U12+Reds&q=&kind=not-in-wholegame", b"key=a%3Ab%20c&reopen=1".to_vec());
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/reconciliation");
+        assert_eq!(request.param("team"), Some("U12 Reds"));
+        assert_eq!(request.param("q"), None);
+        assert_eq!(request.form()["key"], "a:b c");
+        let request = request.with_header("Authorization", " Bearer secret ");
+        assert_eq!(request.header("authorization"), Some("Bearer secret"));
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_encode("U12 Reds/é"), "U12%20Reds%2F%C3%A9");
+        assert_eq!(url_decode(&url_encode("U12 Reds/é")), "U12 Reds/é");
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{Cursor, Read, Seek};
+
+use calamine::{Data, Reader, Xlsx};
+use rusqlite::Connection;
+use serde_json::json;
+
+use crate::audit;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{insert_import_batch, replace_loveadmin, replace_wholegame};
+use crate::dates::parse_date;
+use crate::export_schema::{check_sheet, ColumnAliases, SchemaCheck};
+use crate::validation::{validate_sheet, InvalidRows, ValidationReport};
+
+// Wholegame exports start with six rows of report metadata above the header
+pub const WHOLEGAME_PREAMBLE_ROWS: usize = 6;
+
+pub const LOVEADMIN_SOURCE: &str = "loveadmin";
+pub const WHOLEGAME_SOURCE: &str = "wholegame";
+
+// An export read into text cells, with the header row kept separately
+pub struct Sheet {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    // The spreadsheet row each of `rows` came from, counting the header
+    pub row_numbers: Vec<usize>,
+}
+
+// Headers are compared ignoring case, spaces and punctuation ("Parent/Carer Name" = "parentcarername")
+pub fn normalise_header(header: &str) -> String {
+    header.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+impl Sheet {
+    fn column(&self, name: &str) -> Option<usize> {
+        let name = normalise_header(name);
+        self.headers.iter().position(|header| normalise_header(header) == name)
+    }
+
+    fn require(&self, names: &[&str]) -> Result<(), String> {
+        let missing: Vec<&str> = names.iter().copied().filter(|name| self.column(name).is_none()).collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Missing column(s): {}", missing.join(", ")))
+        }
+    }
+
+    pub fn has_column(&self, name: &str) -> bool {
+        self.column(name).is_some()
+    }
+
+    pub fn row_number(&self, index: usize) -> usize {
+        self.row_numbers.get(index).copied().unwrap_or(index + 2)
+    }
+
+    // Cell text for a named column, empty when the column or cell is absent
+    pub fn cell<'a>(&self, row: &'a [String], name: &str) -> &'a str {
+        self.column(name).and_then(|index| row.get(index)).map(|value| value.trim()).unwrap_or("")
+    }
+
+    // The sheet without the given spreadsheet rows
+    fn without_rows(self, skipped: &BTreeSet<usize>) -> Sheet {
+        let (rows, row_numbers) = self.rows.into_iter().zip(self.row_numbers).filter(|(_, number)| !skipped.contains(number)).unzip();
+        Sheet { headers: self.headers, rows, row_numbers }
+    }
+}
+
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Empty | Data::Error(_) => String::new(),
+        Data::String(value) | Data::DateTimeIso(value) | Data::DurationIso(value) => value.clone(),
+        // Numeric FAN IDs come through as floats
+        Data::Float(value) if value.fract() == 0.0 => format!("{:.0}", value),
+        Data::Float(value) => value.to_string(),
+        Data::Int(value) => value.to_string(),
+        Data::Bool(value) => value.to_string(),
+        Data::DateTime(value) => match value.as_datetime() {
+            Some(datetime) if datetime.time() == chrono::NaiveTime::MIN => datetime.format("%Y-%m-%d").to_string(),
+            Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => value.to_string(),
+        },
+    }
+}
+
+pub fn read_csv<R: Read>(reader: R) -> Result<Sheet, String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = reader.headers().map_err(|e| format!("Cannot read CSV header: {}", e))?.iter().map(String::from).collect();
+    let mut rows = Vec::new();
+    let mut row_numbers = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("CSV line {}: {}", index + 2, e))?;
+        rows.push(record.iter().map(String::from).collect());
+        row_numbers.push(index + 2);
+    }
+    Ok(Sheet { headers, rows, row_numbers })
+}
+
+// Reads the first worksheet. The header is expected after the metadata
+// preamble, but is looked for in the rows above too in case the preamble is
+// shorter than usual; `header_cell` is a column that must be in the header.
+pub fn read_xlsx<RS: Read + Seek>(reader: RS, header_cell: &str) -> Result<Sheet, String> {
+    let mut workbook: Xlsx<RS> = Xlsx::new(reader).map_err(|e| format!("Cannot open workbook: {}", e))?;
+    let range = workbook
+        .worksheet_range_at(0)
+        .ok_or("Workbook has no worksheets")?
+        .map_err(|e| format!("Cannot read worksheet: {}", e))?;
+    // The range starts at the first used cell, not necessarily A1
+    let first_row = range.start().map(|(row, _)| row as usize).unwrap_or(0);
+    let first_col = range.start().map(|(_, col)| col as usize).unwrap_or(0);
+    let mut rows: Vec<Vec<String>> = range
+        .rows()
+        .map(|row| std::iter::repeat_n(String::new(), first_col).chain(row.iter().map(cell_text)).collect())
+        .collect();
+
+    let expected = WHOLEGAME_PREAMBLE_ROWS.saturating_sub(first_row);
+    let wanted = normalise_header(header_cell);
+    let is_header = |row: &Vec<String>| row.iter().any(|cell| normalise_header(cell) == wanted);
+    let header_index = if rows.get(expected).is_some_and(is_header) {
+        expected
+    } else {
+        rows.iter().take(expected + 1).position(is_header).ok_or_else(|| format!("Cannot find the header row (no '{}' column)", header_cell))?
+    };
+    let headers = rows[header_index].clone();
+    let (rows, row_numbers) = rows
+        .split_off(header_index + 1)
+        .into_iter()
+        .zip(first_row + header_index + 2..)
+        .filter(|(row, _)| row.iter().any(|cell| !cell.trim().is_empty()))
+        .unzip();
+    Ok(Sheet { headers, rows, row_numbers })
+}
+
+// Money columns may carry a pound sign or thousands separators; blank is zero
+pub fn parse_amount(value: &str) -> Option<f64> {
+    let cleaned: String = value.chars().filter(|c| !matches!(c, '£' | ',' | ' ')).collect();
+    if cleaned.is_empty() {
+        return Some(0.0);
+    }
+    cleaned.parse().ok()
+}
+
+pub fn parse_whole_number(value: &str) -> Option<i32> {
+    if value.is_empty() {
+        return Some(0);
+    }
+    value.parse::<f64>().ok().map(|number| number as i32)
+}
+
+fn amount(value: &str, row: usize, column: &str) -> Result<f64, String> {
+    parse_amount(value).ok_or_else(|| format!("Row {}: {} '{}' is not an amount", row, column, value))
+}
+
+fn whole_number(value: &str, row: usize, column: &str) -> Result<i32, String> {
+    parse_whole_number(value).ok_or_else(|| format!("Row {}: {} '{}' is not a number", row, column, value))
+}
+
+fn flag(value: &str) -> bool {
+    matches!(value.to_lowercase().as_str(), "yes" | "y" | "true" | "1")
+}
+
+// Dates are stored as ISO text when they can be read, as found otherwise
+fn date(value: &str) -> String {
+    parse_date(value).map(|date| date.format("%Y-%m-%d").to_string()).unwrap_or_else(|| value.to_string())
+}
+
+fn optional(value: &str) -> Option<String> {
+    if value.is_empty() { None } else { Some(value.to_string()) }
+}
+
+fn optional_date(value: &str) -> Option<String> {
+    optional(value).map(|value| date(&value))
+}
+
+// Row numbers in messages are spreadsheet rows, counting the header
+pub fn loveadmin_rows(sheet: &Sheet) -> Result<Vec<LoveAdmin>, String> {
+    sheet.require(&["Name", "Product", "Date", "Invoiced"])?;
+    let mut invoices = Vec::new();
+    for (index, row) in sheet.rows.iter().enumerate() {
+        let number = sheet.row_number(index);
+        let mut invoice = LoveAdmin::new();
+        invoice.set_name(sheet.cell(row, "Name").to_string());
+        invoice.set_account_owner(sheet.cell(row, "Account Owner").to_string());
+        invoice.set_product(sheet.cell(row, "Product").to_string());
+        invoice.set_date(date(sheet.cell(row, "Date")));
+        invoice.set_invoiced(amount(sheet.cell(row, "Invoiced"), number, "Invoiced")?);
+        invoice.set_paid(amount(sheet.cell(row, "Paid"), number, "Paid")?);
+        invoice.set_pending(amount(sheet.cell(row, "Pending"), number, "Pending")?);
+        invoice.set_outstanding(amount(sheet.cell(row, "Outstanding"), number, "Outstanding")?);
+        invoice.set_failed(whole_number(sheet.cell(row, "Failed"), number, "Failed")?);
+        invoice.set_days_overdue(whole_number(sheet.cell(row, "Days Overdue"), number, "Days Overdue")?);
+        invoice.set_last_reminder_sent(date(sheet.cell(row, "Last Reminder Sent")));
+        invoices.push(invoice);
+    }
+    Ok(invoices)
+}
+
+pub fn wholegame_rows(sheet: &Sheet) -> Result<Vec<Wholegame>, String> {
+    sheet.require(&["First names", "Surname", "FAN ID"])?;
+    let mut players = Vec::new();
+    for row in &sheet.rows {
+        let mut player = Wholegame::new();
+        player.set_first_names(sheet.cell(row, "First names").to_string());
+        player.set_surname(sheet.cell(row, "Surname").to_string());
+        player.set_fan_id(sheet.cell(row, "FAN ID").to_string());
+        player.set_date_of_birth(date(sheet.cell(row, "Date of Birth")));
+        player.set_age_group(sheet.cell(row, "Age Group").to_string());
+        player.set_gender(sheet.cell(row, "Gender").to_string());
+        player.set_suspended(flag(sheet.cell(row, "Suspended")));
+        player.set_team(sheet.cell(row, "Team").to_string());
+        player.set_date_submitted(date(sheet.cell(row, "Date Submitted")));
+        player.set_date_registered(optional_date(sheet.cell(row, "Date Registered")));
+        player.set_registration_expiry(optional_date(sheet.cell(row, "Registration Expiry")));
+        player.set_registration_status(sheet.cell(row, "Registration Status").to_string());
+        player.set_email_address(sheet.cell(row, "Email Address").to_string());
+        player.set_parent_carer_name(optional(sheet.cell(row, "Parent/Carer Name")));
+        player.set_parent_carer_email_address(optional(sheet.cell(row, "Parent/Carer Email Address")));
+        player.set_emergency_contact(optional(sheet.cell(row, "Emergency Contact")));
+        player.set_emergency_contact_phone_number(optional(sheet.cell(row, "Emergency Contact Phone Number")));
+        player.set_other_clubs(optional(sheet.cell(row, "Other Clubs")));
+        player.set_consent_given(flag(sheet.cell(row, "Consent Given")));
+        player.set_contract_status(sheet.cell(row, "Contract Status").to_string());
+        player.set_photo_uploaded_date(optional_date(sheet.cell(row, "Photo Uploaded Date")));
+        players.push(player);
+    }
+    Ok(players)
+}
+
+fn now() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+// Each export is a full snapshot, so an import replaces the table's contents.
+// Everything happens in one transaction and is recorded as an import batch,
+// with the export format version when it was checked, and a single audit entry.
+pub fn import_loveadmin(conn: &Connection, invoices: &[LoveAdmin], file_name: &str, format_version: Option<&str>) -> rusqlite::Result<i64> {
+    let tx = conn.unchecked_transaction()?;
+    let replaced = replace_loveadmin(&tx, invoices)?;
+    let batch = insert_import_batch(&tx, LOVEADMIN_SOURCE, file_name, &now(), invoices.len(), format_version)?;
+    record_import(&tx, LOVEADMIN_SOURCE, batch, file_name, replaced, invoices.len(), format_version)?;
+    tx.commit()?;
+    Ok(batch)
+}
+
+pub fn import_wholegame(conn: &Connection, players: &[Wholegame], file_name: &str, format_version: Option<&str>) -> rusqlite::Result<i64> {
+    let tx = conn.unchecked_transaction()?;
+    let replaced = replace_wholegame(&tx, players)?;
+    let batch = insert_import_batch(&tx, WHOLEGAME_SOURCE, file_name, &now(), players.len(), format_version)?;
+    record_import(&tx, WHOLEGAME_SOURCE, batch, file_name, replaced, players.len(), format_version)?;
+    tx.commit()?;
+    Ok(batch)
+}
+
+fn record_import(
+    conn: &Connection,
+    source: &str,
+    batch: i64,
+    file_name: &str,
+    replaced: usize,
+    rows: usize,
+    format_version: Option<&str>,
+) -> rusqlite::Result<i64> {
+    let after = json!({ "batch": batch, "file_name": file_name, "rows": rows, "format_version": format_version });
+    audit::record(conn, "import", source, Some(&json!({ "rows": replaced })), Some(&after))
+}
+
+// An export we couldn't read is the uploader's problem, a database failure is ours
+#[derive(Debug)]
+pub enum ImportError {
+    Invalid(String),
+    // Rows failed validation and the file was rejected as a whole
+    Rejected(ValidationReport),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Invalid(message) => write!(f, "{}", message),
+            ImportError::Rejected(report) => write!(f, "nothing imported, {}", report),
+            ImportError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<rusqlite::Error> for ImportError {
+    fn from(e: rusqlite::Error) -> Self {
+        ImportError::Database(e)
+    }
+}
+
+// What an import did, including how the file's columns compared with the
+// known export formats and any rows left out
+pub struct ImportSummary {
+    pub rows: usize,
+    pub batch: i64,
+    pub schema: SchemaCheck,
+    pub validation: ValidationReport,
+}
+
+// Reads the file and checks its header row against the known formats for the
+// source before anything is replaced
+fn checked_sheet(source: &str, bytes: &[u8], aliases: &ColumnAliases) -> Result<(Sheet, SchemaCheck), String> {
+    let mut sheet = match source {
+        LOVEADMIN_SOURCE => read_csv(bytes)?,
+        WHOLEGAME_SOURCE => read_xlsx(Cursor::new(bytes), "FAN ID")?,
+        _ => return Err(format!("Unknown import source '{}'", source)),
+    };
+    let schema = check_sheet(source, &mut sheet, aliases);
+    match schema.problem() {
+        Some(problem) => Err(problem),
+        None => Ok((sheet, schema)),
+    }
+}
+
+// Imports a whole export file for `source`. Rows that fail validation either
+// reject the file or are left out, as `invalid_rows` says.
+pub fn import_file(
+    conn: &Connection,
+    source: &str,
+    bytes: &[u8],
+    file_name: &str,
+    aliases: &ColumnAliases,
+    invalid_rows: InvalidRows,
+) -> Result<ImportSummary, ImportError> {
+    let (sheet, schema) = checked_sheet(source, bytes, aliases).map_err(ImportError::Invalid)?;
+    let validation = validate_sheet(source, &sheet);
+    if !validation.is_clean() && invalid_rows == InvalidRows::Reject {
+        return Err(ImportError::Rejected(validation));
+    }
+    let sheet = sheet.without_rows(&validation.invalid_rows());
+    let version = schema.version_label();
+    let (rows, batch) = if source == LOVEADMIN_SOURCE {
+        let invoices = loveadmin_rows(&sheet).map_err(ImportError::Invalid)?;
+        (invoices.len(), import_loveadmin(conn, &invoices, file_name, Some(&version))?)
+    } else {
+        let players = wholegame_rows(&sheet).map_err(ImportError::Invalid)?;
+        (players.len(), import_wholegame(conn, &players, file_name, Some(&version))?)
+    };
+    Ok(ImportSummary { rows, batch, schema, validation })
+}
+
+#[cfg(test)]
+mod import_tests {
+    use super::*;
+    use crate::database::{create_tables, get_all_loveadmin, get_all_wholegame, get_import_batches, setup_database};
+    use rust_xlsxwriter::Workbook;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_loveadmin_csv() {
+        let csv = "Name,Account Owner,Product,Date,Invoiced,Paid,Pending,Outstanding,Failed,Days Overdue,Last Reminder Sent\n\
+                   Amy Doe,Jo Doe,U12 Reds (*),01/10/2023,£20.00,10,0,10,1,5,\n";
+        let invoices = loveadmin_rows(&read_csv(csv.as_bytes()).unwrap()).unwrap();
+        assert_eq!(invoices.len(), 1);
+        assert_eq!(invoices[0].get_date(), "2023-10-01");
+        assert_eq!((invoices[0].get_invoiced(), invoices[0].get_outstanding(), invoices[0].get_failed()), (20.0, 10.0, 1));
+
+        let missing = loveadmin_rows(&read_csv("Name,Date\nAmy Doe,2023-10-01\n".as_bytes()).unwrap());
+        assert_eq!(missing.err(), Some("Missing column(s): Product, Invoiced".to_string()));
+        let bad = loveadmin_rows(&read_csv("Name,Product,Date,Invoiced\nAmy Doe,X,2023-10-01,twenty\n".as_bytes()).unwrap());
+        assert_eq!(bad.err(), Some("Row 2: Invoiced 'twenty' is not an amount".to_string()));
+    }
+
+    #[test]
+    fn test_read_wholegame_xlsx_after_preamble() {
+        let mut workbook = Workbook::new();
+        let sheet = workbook.add_worksheet();
+        sheet.write_string(0, 0, "Player registrations report").unwrap();
+        sheet.write_string(2, 0, "Club: Wanderers").unwrap();
+        for (col, heading) in ["First names", "Surname", "FAN ID", "Date of Birth", "Team", "Suspended", "Consent Given"].iter().enumerate() {
+            sheet.write_string(6, col as u16, *heading).unwrap();
+        }
+        sheet.write_string(7, 0, "Amy").unwrap();
+        sheet.write_string(7, 1, "Doe").unwrap();
+        sheet.write_number(7, 2, 123456789.0).unwrap();
+        sheet.write_string(7, 3, "04/09/2012").unwrap();
+        sheet.write_string(7, 4, "Wanderers U12 Reds").unwrap();
+        sheet.write_string(7, 5, "No").unwrap();
+        sheet.write_string(7, 6, "Yes").unwrap();
+        let bytes = workbook.save_to_buffer().unwrap();
+
+        let sheet = read_xlsx(Cursor::new(bytes), "FAN ID").unwrap();
+        assert_eq!(sheet.row_numbers, vec![8]);
+        let players = wholegame_rows(&sheet).unwrap();
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].get_fan_id(), "123456789");
+        assert_eq!(players[0].get_date_of_birth(), "2012-09-04");
+        assert!(players[0].is_consent_given() && !players[0].is_suspended());
+    }
+
+    #[test]
+    fn test_import_replaces_snapshot_and_records_batch() {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        let sheet = read_csv("Name,Product,Date,Invoiced\nAmy Doe,U12 Reds (*),2023-10-01,20\nBen Doe,U12 Reds (*),2023-10-01,20\n".as_bytes()).unwrap();
+        let invoices = loveadmin_rows(&sheet).unwrap();
+
+        import_loveadmin(&conn, &invoices, "first.csv", None).unwrap();
+        import_loveadmin(&conn, &invoices[..1], "second.csv", None).unwrap();
+        let mut player = Wholegame::new();
+        player.set_fan_id("1".to_string());
+        import_wholegame(&conn, &[player], "players.xlsx", Some("2023")).unwrap();
+
+        assert_eq!(get_all_loveadmin(&conn).unwrap().len(), 1);
+        assert_eq!(get_all_wholegame(&conn).unwrap().len(), 1);
+        let batches: Vec<(String, String, i64)> =
+            get_import_batches(&conn).unwrap().into_iter().map(|batch| (batch.source, batch.file_name, batch.row_count)).collect();
+        assert_eq!(
+            batches,
+            vec![
+                ("wholegame".to_string(), "players.xlsx".to_string(), 1),
+                ("loveadmin".to_string(), "second.csv".to_string(), 1),
+                ("loveadmin".to_string(), "first.csv".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_file_checks_export_format() {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        let renamed = "Name,Product,Date,Amount Invoiced\nAmy Doe,U12 Reds (*),2023-10-01,20\n";
+
+        let error = import_file(&conn, LOVEADMIN_SOURCE, renamed.as_bytes(), "renamed.csv", &ColumnAliases::new(), InvalidRows::Reject).err().unwrap();
+        assert!(error.to_string().contains("'Invoiced' now looks to be 'Amount Invoiced'"), "{}", error);
+        assert!(get_import_batches(&conn).unwrap().is_empty());
+
+        let mut aliases = ColumnAliases::new();
+        aliases.add(LOVEADMIN_SOURCE, "Invoiced", "Amount Invoiced");
+        let summary = import_file(&conn, LOVEADMIN_SOURCE, renamed.as_bytes(), "renamed.csv", &aliases, InvalidRows::Reject).unwrap();
+        assert_eq!(summary.rows, 1);
+        assert_eq!(get_all_loveadmin(&conn).unwrap()[0].get_invoiced(), 20.0);
+        assert_eq!(get_import_batches(&conn).unwrap()[0].format_version.as_deref(), Some("2023 (changed)"));
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::NaiveDate;
+
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::contacts::resolve_contacts;
+use crate::dates::parse_date;
+use crate::matching::{full_name, outstanding_for_player};
+
+// A template is a "Subject: ..." line, a blank line and then the body.
+// Placeholders are written as {field_name}; see `merge_fields` for the list.
+pub struct EmailTemplate {
+    subject: String,
+    body: String,
+}
+
+pub struct MergedEmail {
+    pub player_name: String,
+    pub to_name: String,
+    pub to_address: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub struct MergeResult {
+    pub emails: Vec<MergedEmail>,
+    // Players we could not write to, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub from: String,
+    pub helo_name: String,
+}
+
+impl EmailTemplate {
+    pub fn new(subject: &str, body: &str) -> Self {
+        EmailTemplate { subject: subject.to_string(), body: body.to_string() }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.replace("\r\n", "\n");
+        let (first_line, rest) = text.split_once('\n').unwrap_or((text.as_str(), ""));
+        let subject = first_line
+            .strip_prefix("Subject:")
+            .ok_or_else(|| "Template must start with a 'Subject:' line".to_string())?;
+        Ok(EmailTemplate::new(subject.trim(), rest.trim_start_matches('\n')))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read template {}: {}", path.display(), e))?;
+        EmailTemplate::parse(&text)
+    }
+
+    // Unknown placeholders are left in place so they are easy to spot in the output
+    pub fn render(&self, fields: &HashMap<&str, String>) -> (String, String) {
+        (fill_placeholders(&self.subject, fields), fill_placeholders(&self.body, fields))
+    }
+}
+
+// One pass from left to right, so a value that itself looks like a
+// placeholder is copied as it is rather than filled in again
+fn fill_placeholders(text: &str, fields: &HashMap<&str, String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').and_then(|end| fields.get(&after[..end]).map(|value| (end, value))) {
+            Some((end, value)) => {
+                result.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+pub fn merge_fields(player: &Wholegame, invoices: &[LoveAdmin], recipient_name: &str) -> HashMap<&'static str, String> {
+    let expiry = player
+        .get_registration_expiry()
+        .as_deref()
+        .map(|value| parse_date(value).map(|date| date.format("%d/%m/%Y").to_string()).unwrap_or_else(|| value.to_string()))
+        .unwrap_or_default();
+    let parent_carer_name = player
+        .get_parent_carer_name()
+        .clone()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| recipient_name.to_string());
+
+    let mut fields = HashMap::new();
+    fields.insert("player_name", full_name(player));
+    fields.insert("first_names", player.get_first_names().clone());
+    fields.insert("surname", player.get_surname().clone());
+    fields.insert("fan_id", player.get_fan_id().clone());
+    fields.insert("parent_carer_name", parent_carer_name);
+    fields.insert("recipient_name", recipient_name.to_string());
+    fields.insert("team", player.get_team().clone());
+    fields.insert("amount_outstanding", format!("{:.2}", outstanding_for_player(player, invoices)));
+    fields.insert("registration_expiry", expiry);
+    fields
+}
+
+pub fn merge(template: &EmailTemplate, players: &[&Wholegame], invoices: &[LoveAdmin], reference_date: NaiveDate) -> MergeResult {
+    let mut result = MergeResult { emails: Vec::new(), skipped: Vec::new() };
+
+    for player in players {
+        let contacts = resolve_contacts(player, reference_date);
+        let to_address = match contacts.primary_email() {
+            Some(address) if valid_address(address) => address.trim().to_string(),
+            Some(address) => {
+                result.skipped.push((full_name(player), format!("invalid email address {:?}", address)));
+                continue;
+            }
+            None => {
+                let reason = contacts.issues.first().map_or("no email address".to_string(), |issue| issue.to_string());
+                result.skipped.push((full_name(player), reason));
+                continue;
+            }
+        };
+        let to_name = contacts.primary.map(|contact| contact.name).unwrap_or_default();
+        let to_name = if to_name.trim().is_empty() { full_name(player) } else { to_name };
+        let (subject, body) = template.render(&merge_fields(player, invoices, &to_name));
+        result.emails.push(MergedEmail {
+            player_name: full_name(player),
+            to_name,
+            to_address,
+            subject,
+            body,
+        });
+    }
+    result
+}
+
+// An address we can put between < and > in a header or SMTP command: one @
+// with something either side, and nothing that could end the line or the
+// brackets early
+pub fn valid_address(address: &str) -> bool {
+    let address = address.trim();
+    match address.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !address.chars().any(|c| c.is_control() || c.is_whitespace() || c == '<' || c == '>')
+        }
+        None => false,
+    }
+}
+
+fn invalid_recipient(email: &MergedEmail) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid email address {:?} for {}", email.to_address, email.player_name))
+}
+
+// Checked before anything is written or sent, so a bad address can't leave half a run behind
+fn check_recipients(emails: &[MergedEmail]) -> io::Result<()> {
+    match emails.iter().find(|email| !valid_address(&email.to_address)) {
+        Some(email) => Err(invalid_recipient(email)),
+        None => Ok(()),
+    }
+}
+
+// Control characters become spaces, so a value can't end the header line and
+// start another one
+fn header_text(value: &str) -> String {
+    value.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+// Non-ASCII header text is sent as an RFC 2047 Q-encoded word
+fn encode_header(value: &str) -> String {
+    let value = header_text(value);
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    let mut encoded = String::from("=?utf-8?Q?");
+    for byte in value.bytes() {
+        match byte {
+            b' ' => encoded.push('_'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b',' | b'-' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("={:02X}", byte)),
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
+
+impl MergedEmail {
+    // Full RFC 5322 message with CRLF line endings, as written to .eml files and sent over SMTP
+    pub fn to_message(&self, from: &str) -> String {
+        let mut message = String::new();
+        message.push_str(&format!("From: {}\r\n", header_text(from)));
+        let to_name = header_text(&self.to_name).replace('"', "");
+        let to_name = if to_name.is_ascii() { format!("\"{}\"", to_name) } else { encode_header(&to_name) };
+        message.push_str(&format!("To: {} <{}>\r\n", to_name, header_text(self.to_address.trim())));
+        message.push_str(&format!("Subject: {}\r\n", encode_header(&self.subject)));
+        message.push_str(&format!("Date: {}\r\n", chrono::Local::now().to_rfc2822()));
+        message.push_str(&format!("Message-ID: <{}@loveadmin-tool>\r\n", uuid::Uuid::new_v4()));
+        message.push_str("MIME-Version: 1.0\r\n");
+        message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+        message.push_str("Content-Transfer-Encoding: 8bit\r\n");
+        message.push_str("\r\n");
+        for line in self.body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+}
+
+fn file_stem(email: &MergedEmail) -> String {
+    email
+        .player_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect()
+}
+
+pub fn write_eml_files(dir: &Path, from: &str, emails: &[MergedEmail]) -> io::Result<Vec<PathBuf>> {
+    check_recipients(emails)?;
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for (index, email) in emails.iter().enumerate() {
+        let path = dir.join(format!("{:03}-{}.eml", index + 1, file_stem(email)));
+        fs::write(&path, email.to_message(from))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+pub fn write_mbox(path: &Path, from: &str, emails: &[MergedEmail]) -> io::Result<()> {
+    check_recipients(emails)?;
+    let mut file = fs::File::create(path)?;
+    let timestamp = chrono::Local::now().format("%a %b %e %H:%M:%S %Y");
+    let envelope = sender_address(from);
+    for email in emails {
+        writeln!(file, "From {} {}", envelope, timestamp)?;
+        for line in email.to_message(from).lines() {
+            // mboxrd quoting so body lines can't be mistaken for a message separator
+            if line.trim_start_matches('>').starts_with("From ") {
+                writeln!(file, ">{}", line)?;
+            } else {
+                writeln!(file, "{}", line)?;
+            }
+        }
+        writeln!(file)?;
+    }
+    Ok(())
+}
+
+// "Club Secretary <sec@club.org>" -> "sec@club.org"
+fn sender_address(from: &str) -> &str {
+    match (from.find('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from.trim(),
+    }
+}
+
+fn smtp_reply(reader: &mut impl BufRead, expected: u16) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "SMTP server closed the connection"));
+        }
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+        if code != Some(expected) {
+            return Err(io::Error::other(format!("SMTP server replied: {}", line.trim_end())));
+        }
+        // Multi-line replies use "250-" for every line but the last
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+fn smtp_command(stream: &mut TcpStream, reader: &mut impl BufRead, command: &str, expected: u16) -> io::Result<()> {
+    stream.write_all(command.as_bytes())?;
+    stream.write_all(b"\r\n")?;
+    smtp_reply(reader, expected)
+}
+
+// How long to wait for the server before giving up on the whole run
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn smtp_connect(config: &SmtpConfig) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", config.host));
+    for address in (config.host.as_str(), config.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, SMTP_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(SMTP_TIMEOUT))?;
+                stream.set_write_timeout(Some(SMTP_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+// Plain SMTP without authentication or TLS, which is what a club relay or a
+// local stand-in server offers. Returns the number of messages accepted.
+pub fn send_smtp(config: &SmtpConfig, emails: &[MergedEmail]) -> io::Result<usize> {
+    check_recipients(emails)?;
+    let sender = sender_address(&config.from);
+    if !valid_address(sender) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid sender address {:?}", sender)));
+    }
+    let mut stream = smtp_connect(config)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    smtp_reply(&mut reader, 220)?;
+    smtp_command(&mut stream, &mut reader, &format!("HELO {}", config.helo_name), 250)?;
+
+    let mut sent = 0;
+    for email in emails {
+        smtp_command(&mut stream, &mut reader, &format!("MAIL FROM:<{}>", sender), 250)?;
+        smtp_command(&mut stream, &mut reader, &format!("RCPT TO:<{}>", email.to_address.trim()), 250)?;
+        smtp_command(&mut stream, &mut reader, "DATA", 354)?;
+        let mut data = String::new();
+        for line in email.to_message(&config.from).split("\r\n") {
+            // Dot-stuffing, so a lone "." in the body doesn't end the message
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        stream.write_all(data.trim_end_matches("\r\n").as_bytes())?;
+        smtp_command(&mut stream, &mut reader, "\r\n.", 250)?;
+        sent += 1;
+    }
+    smtp_command(&mut stream, &mut reader, "QUIT", 221)?;
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod mail_merge_tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn junior() -> Wholegame {
+        let mut player = Wholegame::new();
+        player.set_first_names("Jane".to_string());
+        player.set_surname("Doe".to_string());
+        player.set_fan_id("987654321".to_string());
+        player.set_date_of_birth("2012-09-04".to_string());
+        player.set_team("City Juniors U12".to_string());
+        player.set_registration_expiry(Some("2024-09-01".to_string()));
+        player.set_email_address("janedoe@example.com".to_string());
+        player.set_parent_carer_name(Some("John Doe".to_string()));
+        player.set_parent_carer_email_address(Some("johndoe@example.com".to_string()));
+        player
+    }
+
+    fn reference_date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 10, 1).unwrap()
+    }
+
+    fn template() -> EmailTemplate {
+        EmailTemplate::parse("Subject: {player_name} subs\n\nDear {parent_carer_name},\nYou owe {amount_outstanding} for {team}.\nRegistration ends {registration_expiry}.\n").unwrap()
+    }
+
+    #[test]
+    fn test_template_requires_subject_line() {
+        assert!(EmailTemplate::parse("Hello {player_name}").is_err());
+        let mut fields = HashMap::new();
+        fields.insert("player_name", "Jane Doe".to_string());
+        let (subject, body) = template().render(&fields);
+        assert_eq!(subject, "Jane Doe subs");
+        assert!(body.starts_with("Dear {parent_carer_name},"));
+
+        // Values are never filled in a second time, whatever order the fields come in
+        fields.insert("player_name", "{team}".to_string());
+        fields.insert("team", "U12 {Reds}".to_string());
+        let (subject, body) = template().render(&fields);
+        assert_eq!(subject, "{team} subs");
+        assert!(body.contains("for U12 {Reds}."));
+        assert_eq!(fill_placeholders("{{team}} {", &fields), "{U12 {Reds}} {");
+    }
+
+    #[test]
+    fn test_merge_writes_to_parent_for_juniors_and_player_for_adults() {
+        let mut invoice = LoveAdmin::new();
+        invoice.set_name("Jane Doe".to_string());
+        invoice.set_outstanding(12.5);
+        let child = junior();
+        let mut adult = junior();
+        adult.set_first_names("Janet".to_string());
+        adult.set_date_of_birth("1990-01-01".to_string());
+
+        let result = merge(&template(), &[&child, &adult], &[invoice], reference_date());
+
+        assert!(result.skipped.is_empty());
+        assert_eq!(result.emails[0].to_address, "johndoe@example.com");
+        assert_eq!(result.emails[0].subject, "Jane Doe subs");
+        assert_eq!(result.emails[0].body, "Dear John Doe,\nYou owe 12.50 for City Juniors U12.\nRegistration ends 01/09/2024.\n");
+        assert_eq!(result.emails[1].to_address, "janedoe@example.com");
+        assert!(result.emails[1].body.contains("You owe 0.00"));
+    }
+
+    #[test]
+    fn test_merge_skips_juniors_without_parent_email() {
+        let mut player = junior();
+        player.set_parent_carer_email_address(None);
+        let result = merge(&template(), &[&player], &[], reference_date());
+        assert!(result.emails.is_empty());
+        assert_eq!(result.skipped.len(), 1);
+    }
+
+    #[test]
+    fn test_header_injection_is_refused() {
+        // A parent email carrying an extra header is skipped and reported
+        let mut player = junior();
+        player.set_parent_carer_email_address(Some("johndoe@example.com\r\nBcc: all@example.com".to_string()));
+        let result = merge(&template(), &[&player], &[], reference_date());
+        assert!(result.emails.is_empty());
+        assert!(result.skipped[0].1.starts_with("invalid email address"));
+        for address in ["john doe@example.com", "<johndoe@example.com>", "john@doe@example.com", "johndoe.example.com", "@example.com"] {
+            assert!(!valid_address(address), "{}", address);
+        }
+        assert!(valid_address(" johndoe@example.com "));
+
+        // Names and subjects stay on their own header line
+        let mut player = junior();
+        player.set_parent_carer_name(Some("John Doe\r\nBcc: all@example.com".to_string()));
+        player.set_first_names("Jane\r\nBcc: all@example.com\r\n".to_string());
+        let result = merge(&template(), &[&player], &[], reference_date());
+        let message = result.emails[0].to_message("Club <sec@club.example>\r\nBcc: all@example.com");
+        let (headers, _) = message.split_once("\r\n\r\n").unwrap();
+        assert!(!headers.lines().any(|line| line.starts_with("Bcc:")));
+        assert!(headers.contains("To: \"John Doe  Bcc: all@example.com\" <johndoe@example.com>\r\n"));
+
+        // Nothing is written or sent when a recipient was set by hand to a bad address
+        let mut emails = merge(&template(), &[&junior()], &[], reference_date()).emails;
+        emails[0].to_address = "johndoe@example.com>\r\nRCPT TO:<all@example.com".to_string();
+        let path = std::env::temp_dir().join(format!("mail-merge-{}.mbox", uuid::Uuid::new_v4()));
+        assert_eq!(write_mbox(&path, "sec@club.example", &emails).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+        let config = SmtpConfig { host: "127.0.0.1".to_string(), port: 1, from: "sec@club.example".to_string(), helo_name: "localhost".to_string() };
+        assert_eq!(send_smtp(&config, &emails).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_mbox_quotes_from_lines() {
+        let mut result = merge(&template(), &[&junior()], &[], reference_date());
+        result.emails[0].body.push_str("From the committee\n");
+        let path = std::env::temp_dir().join(format!("mail-merge-{}.mbox", uuid::Uuid::new_v4()));
+        write_mbox(&path, "Club <sec@club.example>", &result.emails).unwrap();
+        let mbox = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(mbox.starts_with("From sec@club.example "));
+        assert!(mbox.contains("\n>From the committee\n"));
+        assert!(mbox.contains("To: \"John Doe\" <johndoe@example.com>"));
+    }
+
+    #[test]
+    fn test_send_smtp_to_local_stand_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+
+        // Accepts a single session and hands back everything between DATA and "."
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"220 localhost ready\r\n").unwrap();
+            let mut in_data = false;
+            let mut data = String::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let reply: &[u8] = if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        data.push_str(&line);
+                        b""
+                    }
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.write_all(reply).unwrap();
+                line.clear();
+            }
+            sender.send(data).unwrap();
+        });
+
+        let mut result = merge(&template(), &[&junior()], &[], reference_date());
+        result.emails[0].body.push_str(".\n");
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            from: "sec@club.example".to_string(),
+            helo_name: "localhost".to_string(),
+        };
+
+        assert_eq!(send_smtp(&config, &result.emails).unwrap(), 1);
+        let data = receiver.recv().unwrap();
+        assert!(data.contains("Subject: Jane Doe subs\r\n"));
+        assert!(data.contains("Registration ends 01/09/2024.\r\n..\r\n"));
+    }
+}
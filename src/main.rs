@@ -1,64 +1,68 @@
+mod accounting_export;
+mod age_groups;
+mod api;
+mod audit;
+mod billing;
+mod cli;
+mod commands;
+mod compliance;
+mod contacts;
 mod data_structures;
 mod database;
+mod dates;
+mod eligibility;
+mod encryption;
+mod export_schema;
+mod fees;
+#[cfg(test)]
+mod fixtures;
+mod forecast;
+mod gdpr;
+mod golden;
+mod households;
+mod http;
+mod import;
+mod mail_merge;
+mod matching;
+mod reconciliation;
+mod redact;
+mod registration;
+mod retention;
+mod roster;
+mod synthetic;
+mod tui;
+mod validation;
+mod web;
 
 use rusqlite::Result; 
+use cli::Args;
 use data_structures::{Wholegame, LoveAdmin};
-use database::{setup_database, create_table, insert_loveadmin, insert_wholegame};
+use database::{setup_database, create_table, insert_loveadmin, insert_wholegame, AUDIT_LOG_TABLE_SQL, LOVEADMIN_TABLE_SQL, WHOLEGAME_TABLE_SQL};
 
 
-fn main() -> Result<()> {
+fn main() {
+    let args = Args::parse(std::env::args().skip(1));
+    let result = match args.command() {
+        // With no command we keep the original behaviour of seeding example data
+        None => seed_example_data().map_err(|e| e.into()),
+        Some(_) => commands::run(&args),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn seed_example_data() -> Result<()> {
     let conn = setup_database(None)?;
     
     //let conn = setup_database(Some("test_datbase.db"))?;
 
-    // SQL to create the 'loveAdmin' table
-    let loveadmin_table_sql = "
-        CREATE TABLE IF NOT EXISTS loveadmin (
-            id INTEGER PRIMARY KEY,
-            Name TEXT NOT NULL,
-            AccountOwner TEXT NOT NULL,
-            Product TEXT NOT NULL,
-            Date TEXT NOT NULL,
-            Invoiced REAL NOT NULL,
-            Paid REAL NOT NULL,
-            Pending REAL NOT NULL,
-            Outstanding REAL NOT NULL,
-            Failed INTEGER NOT NULL,
-            DaysOverdue INTEGER NOT NULL,
-            LastReminderSent TEXT NOT NULL
-        )";
-
-    // SQL to create the 'wholegame' table
-    let wholegame_table_sql = "
-        CREATE TABLE IF NOT EXISTS wholegame (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            FirstNames TEXT NOT NULL,
-            Surname TEXT NOT NULL,
-            FAN_ID TEXT UNIQUE NOT NULL,
-            DateOfBirth DATE NOT NULL,
-            AgeGroup TEXT NOT NULL,
-            Gender TEXT NOT NULL,
-            Suspended BOOLEAN NOT NULL,
-            Team TEXT NOT NULL,
-            DateSubmitted DATETIME NOT NULL,
-            DateRegistered DATETIME,
-            RegistrationExpiry DATE,
-            RegistrationStatus TEXT NOT NULL,
-            EmailAddress TEXT NOT NULL,
-            ParentCarerName TEXT,
-            ParentCarerEmailAddress TEXT,
-            EmergencyContact TEXT,
-            EmergencyContactPhoneNumber TEXT,
-            OtherClubs TEXT,
-            ConsentGiven BOOLEAN NOT NULL,
-            ContractStatus TEXT NOT NULL,
-            PhotoUploadedDate DATETIME
-        )";
-         
-
     // Create the 'loveadmin' table
-    create_table(&conn, loveadmin_table_sql)?;
-    create_table(&conn, wholegame_table_sql)?;
+    create_table(&conn, LOVEADMIN_TABLE_SQL)?;
+    create_table(&conn, WHOLEGAME_TABLE_SQL)?;
+    // Every insert is recorded in the audit log
+    create_table(&conn, AUDIT_LOG_TABLE_SQL)?;
 
     // Example data to insert into 'loveadmin'
     // Initialize an empty or default instance of LoveAdmin
@@ -0,0 +1,131 @@
+use chrono::Datelike;
+
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::dates::parse_date;
+
+// Wholegame splits names, LoveAdmin keeps the member's full name in one column
+pub fn full_name(player: &Wholegame) -> String {
+    format!("{} {}", player.get_first_names().trim(), player.get_surname().trim())
+}
+
+// Case and whitespace insensitive form used when comparing names across exports
+pub fn normalise_name(name: &str) -> String {
+    name.split_whitespace()
+        .map(|part| part.to_lowercase())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn names_match(a: &str, b: &str) -> bool {
+    normalise_name(a) == normalise_name(b)
+}
+
+// LoveAdmin products are named "<team> (*)..."; anything else has no team
+pub fn team_from_product(product: &str) -> Option<String> {
+    product.find(" (*)").map(|end| product[..end].trim().to_string())
+}
+
+// Wholegame team names carry the club name ("Wilpshire Wanderers U12 Reds")
+// where LoveAdmin products just use the team ("U12 Reds")
+pub fn teams_match(wholegame_team: &str, loveadmin_team: &str) -> bool {
+    let wholegame_team = normalise_name(wholegame_team);
+    let loveadmin_team = normalise_name(loveadmin_team);
+    !loveadmin_team.is_empty()
+        && (wholegame_team == loveadmin_team || wholegame_team.ends_with(&format!(" {}", loveadmin_team)))
+}
+
+pub fn invoices_for_player<'a>(player: &Wholegame, invoices: &'a [LoveAdmin]) -> Vec<&'a LoveAdmin> {
+    let name = normalise_name(&full_name(player));
+    invoices
+        .iter()
+        .filter(|invoice| normalise_name(invoice.get_name()) == name)
+        .collect()
+}
+
+// Amounts closer than this are the same to the penny; anything smaller is
+// floating point rounding, not money owed or charged
+pub const AMOUNT_TOLERANCE: f64 = 0.005;
+
+pub fn outstanding_for_player(player: &Wholegame, invoices: &[LoveAdmin]) -> f64 {
+    invoices_for_player(player, invoices)
+        .iter()
+        .fold(0.0, |total, invoice| total + invoice.get_outstanding())
+}
+
+// (year, month) an invoice belongs to, if its date is readable
+pub fn invoice_month(invoice: &LoveAdmin) -> Option<(i32, u32)> {
+    parse_date(invoice.get_date()).map(|date| (date.year(), date.month()))
+}
+
+// The most recent month anything was invoiced, which we treat as "current"
+pub fn latest_invoice_month(invoices: &[LoveAdmin]) -> Option<(i32, u32)> {
+    invoices.iter().filter_map(invoice_month).max()
+}
+
+// A player is still paying if they were invoiced in the latest invoice month
+pub fn is_paying(player: &Wholegame, invoices: &[LoveAdmin]) -> bool {
+    let latest = match latest_invoice_month(invoices) {
+        Some(latest) => latest,
+        None => return false,
+    };
+    invoices_for_player(player, invoices)
+        .iter()
+        .any(|invoice| invoice_month(invoice) == Some(latest) && invoice.get_invoiced() > 0.0)
+}
+
+#[cfg(test)]
+mod matching_tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn invoice(name: &str, outstanding: f64) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, "", "", 0.0);
+        invoice.set_outstanding(outstanding);
+        invoice
+    }
+
+    fn dated_invoice(name: &str, date: &str) -> LoveAdmin {
+        let mut invoice = invoice(name, 0.0);
+        invoice.set_date(date.to_string());
+        invoice.set_invoiced(20.0);
+        invoice
+    }
+
+    #[test]
+    fn test_team_from_product() {
+        assert_eq!(team_from_product("U12 Reds (*) Monthly"), Some("U12 Reds".to_string()));
+        assert_eq!(team_from_product("Summer Camp"), None);
+    }
+
+    #[test]
+    fn test_teams_match_ignores_club_prefix() {
+        assert!(teams_match("Wilpshire Wanderers U12 Reds", "U12 Reds"));
+        assert!(teams_match("u12 reds", "U12 Reds"));
+        assert!(!teams_match("Wilpshire Wanderers U12 Reds", "12 Reds"));
+        assert!(!teams_match("U12 Reds", ""));
+    }
+
+    #[test]
+    fn test_invoices_for_player_ignores_case_and_spacing() {
+        let mut player = Wholegame::new();
+        player.set_first_names("Jane ".to_string());
+        player.set_surname("Doe".to_string());
+        let invoices = vec![invoice("jane  doe", 10.0), invoice("John Doe", 5.0), invoice("Jane Doe", 2.5)];
+
+        assert_eq!(invoices_for_player(&player, &invoices).len(), 2);
+        assert_eq!(outstanding_for_player(&player, &invoices), 12.5);
+    }
+
+    #[test]
+    fn test_is_paying_uses_latest_invoice_month() {
+        let mut player = Wholegame::new();
+        player.set_first_names("Jane".to_string());
+        player.set_surname("Doe".to_string());
+        let invoices = vec![dated_invoice("Jane Doe", "2023-08-01"), dated_invoice("John Doe", "2023-09-01")];
+        assert_eq!(latest_invoice_month(&invoices), Some((2023, 9)));
+        assert!(!is_paying(&player, &invoices));
+
+        let invoices = vec![dated_invoice("Jane Doe", "2023-09-15"), dated_invoice("John Doe", "2023-09-01")];
+        assert!(is_paying(&player, &invoices));
+    }
+}
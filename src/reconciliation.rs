@@ -0,0 +1,610 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use crate::audit;
+use crate::compliance::{self, PlayerCompliance};
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{get_all_loveadmin, get_all_wholegame, get_exception_record, get_exception_records, mark_exception_absent, save_found_exception, update_exception_workflow, ExceptionRecord};
+use crate::dates::{parse_date, previous_month};
+use crate::matching::{full_name, invoice_month, invoices_for_player, latest_invoice_month, names_match, normalise_name, team_from_product, teams_match};
+
+// The three sheets the Python analysis produced, plus compliance breaches
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExceptionKind {
+    // Invoiced in LoveAdmin but not registered (to that team) in Wholegame
+    UnregisteredPayer,
+    // Registered in Wholegame without a LoveAdmin subscription for their team
+    UnbilledPlayer,
+    // Invoiced last month but not in the latest invoice month
+    LapsedInvoice,
+    // A player with safeguarding or paperwork issues in the compliance report
+    ComplianceBreach,
+}
+
+pub const EXCEPTION_KINDS: [ExceptionKind; 4] =
+    [ExceptionKind::UnregisteredPayer, ExceptionKind::UnbilledPlayer, ExceptionKind::LapsedInvoice, ExceptionKind::ComplianceBreach];
+
+// Where an exception is in the follow-up. Ignored exceptions come back once
+// the date has passed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionStatus {
+    Open,
+    Investigating,
+    Resolved,
+    IgnoredUntil(NaiveDate),
+}
+
+pub struct Exception {
+    pub kind: ExceptionKind,
+    pub name: String,
+    pub team: String,
+    pub fan_id: Option<String>,
+    pub detail: String,
+    // Month the exception was found in, for kinds that recur monthly
+    pub month: Option<(i32, u32)>,
+}
+
+// An exception as stored between runs, with its workflow state
+pub struct TrackedException {
+    pub id: i64,
+    pub exception: Exception,
+    pub status: ExceptionStatus,
+    pub assignee: Option<String>,
+    pub notes: Option<String>,
+    pub first_seen: String,
+    // First found by this run
+    pub is_new: bool,
+}
+
+// A change made by whoever is following an exception up. An empty assignee
+// clears it; a note is added to the end of the existing notes.
+#[derive(Default)]
+pub struct WorkflowChange {
+    pub status: Option<ExceptionStatus>,
+    pub assignee: Option<String>,
+    pub note: Option<String>,
+}
+
+// Which exceptions to show; None means no filtering on that field
+pub struct ExceptionFilter {
+    pub kind: Option<ExceptionKind>,
+    pub team: Option<String>,
+    pub search: Option<String>,
+    pub assignee: Option<String>,
+    // Show resolved and still-ignored exceptions too
+    pub include_closed: bool,
+}
+
+impl ExceptionKind {
+    pub fn parse(code: &str) -> Option<ExceptionKind> {
+        EXCEPTION_KINDS.iter().copied().find(|kind| kind.code() == code.trim().to_lowercase())
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExceptionKind::UnregisteredPayer => "not-in-wholegame",
+            ExceptionKind::UnbilledPlayer => "not-in-loveadmin",
+            ExceptionKind::LapsedInvoice => "not-in-current-month",
+            ExceptionKind::ComplianceBreach => "compliance-breach",
+        }
+    }
+}
+
+impl ExceptionStatus {
+    // Ignoring needs the date to ignore until
+    pub fn parse(code: &str, until: Option<&str>) -> Result<ExceptionStatus, String> {
+        match code.trim().to_lowercase().as_str() {
+            "open" => Ok(ExceptionStatus::Open),
+            "investigating" => Ok(ExceptionStatus::Investigating),
+            "resolved" => Ok(ExceptionStatus::Resolved),
+            "ignored" => {
+                let until = until.ok_or("Ignoring an exception needs a date to ignore it until")?;
+                parse_date(until).map(ExceptionStatus::IgnoredUntil).ok_or_else(|| format!("Invalid date '{}'", until))
+            }
+            other => Err(format!("Unknown status '{}'; use open, investigating, resolved or ignored", other)),
+        }
+    }
+
+    fn from_record(record: &ExceptionRecord) -> ExceptionStatus {
+        ExceptionStatus::parse(&record.status, record.ignored_until.as_deref()).unwrap_or(ExceptionStatus::Open)
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExceptionStatus::Open => "open",
+            ExceptionStatus::Investigating => "investigating",
+            ExceptionStatus::Resolved => "resolved",
+            ExceptionStatus::IgnoredUntil(_) => "ignored",
+        }
+    }
+
+    pub fn until(&self) -> Option<NaiveDate> {
+        match self {
+            ExceptionStatus::IgnoredUntil(until) => Some(*until),
+            _ => None,
+        }
+    }
+
+    // Still needs someone to look at it on the reference date
+    pub fn is_active(&self, reference_date: NaiveDate) -> bool {
+        match self {
+            ExceptionStatus::Open | ExceptionStatus::Investigating => true,
+            ExceptionStatus::Resolved => false,
+            ExceptionStatus::IgnoredUntil(until) => reference_date > *until,
+        }
+    }
+}
+
+impl fmt::Display for ExceptionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExceptionStatus::IgnoredUntil(until) => write!(f, "ignored until {}", until.format("%d/%m/%Y")),
+            other => write!(f, "{}", other.code()),
+        }
+    }
+}
+
+impl TrackedException {
+    // The status with new exceptions marked, for tables
+    pub fn status_label(&self) -> String {
+        if self.is_new { format!("{} (new)", self.status) } else { self.status.to_string() }
+    }
+}
+
+impl fmt::Display for ExceptionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExceptionKind::UnregisteredPayer => write!(f, "Not in Wholegame"),
+            ExceptionKind::UnbilledPlayer => write!(f, "Not in LoveAdmin"),
+            ExceptionKind::LapsedInvoice => write!(f, "Not in current month"),
+            ExceptionKind::ComplianceBreach => write!(f, "Compliance breach"),
+        }
+    }
+}
+
+impl Exception {
+    // Stays the same between runs over the same data, so a resolved exception
+    // stays resolved. Players sharing a name are told apart by FAN ID.
+    pub fn key(&self) -> String {
+        let who = self.fan_id.clone().unwrap_or_else(|| normalise_name(&self.name));
+        let mut key = format!("{}:{}:{}", self.kind.code(), who, normalise_name(&self.team));
+        if let Some((year, month)) = self.month {
+            key.push_str(&format!(":{}-{:02}", year, month));
+        }
+        key
+    }
+
+    // How known answers and golden files record an exception
+    pub fn to_json(&self) -> Value {
+        json!({
+            "key": self.key(),
+            "kind": self.kind.code(),
+            "team": self.team,
+            "name": self.name,
+            "fan_id": self.fan_id,
+            "detail": self.detail,
+            "month": self.month.map(|(year, month)| format!("{:04}-{:02}", year, month)),
+        })
+    }
+}
+
+impl ExceptionFilter {
+    pub fn matches(&self, tracked: &TrackedException, reference_date: NaiveDate) -> bool {
+        let exception = &tracked.exception;
+        // Exception teams may be Wholegame or LoveAdmin team names
+        let in_team = |team: &str| names_match(team, &exception.team) || teams_match(team, &exception.team) || teams_match(&exception.team, team);
+        let searched = |search: &str| {
+            let search = search.to_lowercase();
+            [&exception.name, &exception.team, &exception.detail].iter().any(|field| field.to_lowercase().contains(&search))
+                || exception.fan_id.as_deref().is_some_and(|fan_id| fan_id.contains(&search))
+        };
+        self.kind.is_none_or(|kind| kind == exception.kind)
+            && self.team.as_deref().is_none_or(in_team)
+            && self.search.as_deref().is_none_or(searched)
+            && self.assignee.as_deref().is_none_or(|assignee| tracked.assignee.as_deref().is_some_and(|current| names_match(current, assignee)))
+            && (self.include_closed || tracked.status.is_active(reference_date))
+    }
+}
+
+// People invoiced in LoveAdmin who either aren't in Wholegame at all or
+// aren't registered to the team they're paying for
+fn unregistered_payers(players: &[Wholegame], invoices: &[LoveAdmin]) -> Vec<Exception> {
+    let mut seen = BTreeSet::new();
+    let mut exceptions = Vec::new();
+    for invoice in invoices {
+        let team = team_from_product(invoice.get_product());
+        if !seen.insert((normalise_name(invoice.get_name()), team.clone())) {
+            continue;
+        }
+        let same_name: Vec<&Wholegame> = players.iter().filter(|player| names_match(&full_name(player), invoice.get_name())).collect();
+        let detail = match (&team, same_name.as_slice()) {
+            (_, []) => "no Wholegame player with this name".to_string(),
+            (Some(team), same_name) if !same_name.iter().any(|player| teams_match(player.get_team(), team)) => {
+                let registered: Vec<&str> = same_name.iter().map(|player| player.get_team().as_str()).collect();
+                format!("invoiced for {} but registered to {}", team, registered.join(", "))
+            }
+            _ => continue,
+        };
+        exceptions.push(Exception {
+            kind: ExceptionKind::UnregisteredPayer,
+            name: invoice.get_name().trim().to_string(),
+            team: team.unwrap_or_default(),
+            fan_id: same_name.first().map(|player| player.get_fan_id().clone()),
+            detail,
+            month: None,
+        });
+    }
+    exceptions
+}
+
+// Wholegame players with no LoveAdmin subscription for their team
+fn unbilled_players(players: &[Wholegame], invoices: &[LoveAdmin]) -> Vec<Exception> {
+    let mut exceptions = Vec::new();
+    for player in players {
+        let invoiced = invoices_for_player(player, invoices);
+        let teams: BTreeSet<String> = invoiced.iter().filter_map(|invoice| team_from_product(invoice.get_product())).collect();
+        let detail = if invoiced.is_empty() {
+            "never invoiced in LoveAdmin".to_string()
+        } else if teams.is_empty() {
+            "no team subscription in LoveAdmin".to_string()
+        } else if !teams.iter().any(|team| teams_match(player.get_team(), team)) {
+            format!("invoiced for {} only", teams.into_iter().collect::<Vec<String>>().join(", "))
+        } else {
+            continue;
+        };
+        exceptions.push(Exception {
+            kind: ExceptionKind::UnbilledPlayer,
+            name: full_name(player),
+            team: player.get_team().clone(),
+            fan_id: Some(player.get_fan_id().clone()),
+            detail,
+            month: None,
+        });
+    }
+    exceptions
+}
+
+// Team subscriptions invoiced the month before the latest invoice month but not in it
+fn lapsed_invoices(players: &[Wholegame], invoices: &[LoveAdmin]) -> Vec<Exception> {
+    let latest = match latest_invoice_month(invoices) {
+        Some(latest) => latest,
+        None => return Vec::new(),
+    };
+    let previous = previous_month(latest);
+    let mut by_month: BTreeMap<(i32, u32), BTreeMap<String, (String, String)>> = BTreeMap::new();
+    for invoice in invoices {
+        if let (Some(month), Some(team)) = (invoice_month(invoice), team_from_product(invoice.get_product())) {
+            if month == latest || month == previous {
+                by_month
+                    .entry(month)
+                    .or_default()
+                    .entry(normalise_name(invoice.get_name()))
+                    .or_insert_with(|| (invoice.get_name().trim().to_string(), team));
+            }
+        }
+    }
+    let current = by_month.get(&latest).cloned().unwrap_or_default();
+    by_month
+        .get(&previous)
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| !current.contains_key(*key))
+        .map(|(_, (name, team))| Exception {
+            kind: ExceptionKind::LapsedInvoice,
+            name: name.clone(),
+            team: team.clone(),
+            fan_id: players.iter().find(|player| names_match(&full_name(player), name)).map(|player| player.get_fan_id().clone()),
+            detail: format!("invoiced {}-{:02} but not {}-{:02}", previous.0, previous.1, latest.0, latest.1),
+            month: Some(latest),
+        })
+        .collect()
+}
+
+// Cross-references the two exports the way the Python analysis did, sorted by
+// kind, team and name
+pub fn reconcile(players: &[Wholegame], invoices: &[LoveAdmin]) -> Vec<Exception> {
+    let mut exceptions = unregistered_payers(players, invoices);
+    exceptions.extend(unbilled_players(players, invoices));
+    exceptions.extend(lapsed_invoices(players, invoices));
+    exceptions.sort_by_key(|exception| (exception.kind, exception.team.to_lowercase(), exception.name.to_lowercase()));
+    exceptions
+}
+
+// One exception per player with compliance issues, whatever the issues are,
+// so a changing list of issues updates the same exception
+pub fn compliance_breaches(results: &[PlayerCompliance]) -> Vec<Exception> {
+    results
+        .iter()
+        .filter(|result| !result.issues.is_empty())
+        .map(|result| Exception {
+            kind: ExceptionKind::ComplianceBreach,
+            name: result.name.clone(),
+            team: result.team.clone(),
+            fan_id: Some(result.fan_id.clone()),
+            detail: result.issues.iter().map(|issue| issue.to_string()).collect::<Vec<String>>().join("; "),
+            month: None,
+        })
+        .collect()
+}
+
+// Everything the exception workflow follows up, in the same order as `reconcile`
+pub fn find_exceptions(players: &[Wholegame], invoices: &[LoveAdmin], reference_date: NaiveDate, photo_max_years: u32) -> Vec<Exception> {
+    let mut exceptions = reconcile(players, invoices);
+    exceptions.extend(compliance_breaches(&compliance::check_all(players, invoices, reference_date, photo_max_years)));
+    exceptions.sort_by_key(|exception| (exception.kind, exception.team.to_lowercase(), exception.name.to_lowercase()));
+    exceptions
+}
+
+fn now() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+// Stores the exceptions found by a run. Ones seen before are updated rather
+// than added again and keep their status, assignee and notes; ones no longer
+// found are marked absent. Returns what was found with its workflow state.
+pub fn track(conn: &Connection, exceptions: Vec<Exception>, seen_at: &str) -> rusqlite::Result<Vec<TrackedException>> {
+    let tx = conn.unchecked_transaction()?;
+    let known: HashMap<String, ExceptionRecord> = get_exception_records(&tx)?.into_iter().map(|record| (record.key.clone(), record)).collect();
+    let mut found = HashSet::new();
+    let mut tracked = Vec::new();
+    for exception in exceptions {
+        let key = exception.key();
+        if !found.insert(key.clone()) {
+            continue;
+        }
+        let seen = ExceptionRecord {
+            key: key.clone(),
+            kind: exception.kind.code().to_string(),
+            name: exception.name.clone(),
+            team: exception.team.clone(),
+            fan_id: exception.fan_id.clone(),
+            detail: exception.detail.clone(),
+            last_seen: seen_at.to_string(),
+            ..ExceptionRecord::default()
+        };
+        let id = save_found_exception(&tx, &seen)?;
+        let mut previous = known.get(&key).cloned();
+        // A resolved exception that went away and has come back needs looking at again
+        if let Some(record) = previous.as_mut().filter(|record| !record.present && record.status == ExceptionStatus::Resolved.code()) {
+            record.status = ExceptionStatus::Open.code().to_string();
+            record.notes = append_note(record.notes.take(), &format!("{} reconciliation: found again after being resolved", seen_at));
+            record.updated_at = Some(seen_at.to_string());
+            update_exception_workflow(&tx, record)?;
+        }
+        let previous = previous.as_ref();
+        tracked.push(TrackedException {
+            id,
+            exception,
+            status: previous.map_or(ExceptionStatus::Open, ExceptionStatus::from_record),
+            assignee: previous.and_then(|record| record.assignee.clone()),
+            notes: previous.and_then(|record| record.notes.clone()),
+            first_seen: previous.map_or_else(|| seen_at.to_string(), |record| record.first_seen.clone()),
+            is_new: previous.is_none(),
+        });
+    }
+    for record in known.values().filter(|record| record.present && !found.contains(&record.key)) {
+        mark_exception_absent(&tx, record.id)?;
+    }
+    tx.commit()?;
+    Ok(tracked)
+}
+
+// Runs reconciliation and the compliance checks over the stored data and tracks the result
+pub fn refresh(conn: &Connection, reference_date: NaiveDate, photo_max_years: u32) -> rusqlite::Result<Vec<TrackedException>> {
+    let players = get_all_wholegame(conn)?;
+    let invoices = get_all_loveadmin(conn)?;
+    track(conn, find_exceptions(&players, &invoices, reference_date, photo_max_years), &now())
+}
+
+// What the latest run found, as stored, without running reconciliation again.
+// An exception is new if the run that last found it was the first to.
+pub fn tracked(conn: &Connection) -> rusqlite::Result<Vec<TrackedException>> {
+    let mut tracked: Vec<TrackedException> = get_exception_records(conn)?
+        .into_iter()
+        .filter(|record| record.present)
+        .filter_map(|record| {
+            let kind = ExceptionKind::parse(&record.kind)?;
+            // Only lapsed invoices are found per month; the month ends their key
+            let month = record
+                .key
+                .rsplit(':')
+                .next()
+                .and_then(|month| month.split_once('-'))
+                .and_then(|(year, month)| Some((year.parse::<i32>().ok()?, month.parse::<u32>().ok()?)))
+                .filter(|_| kind == ExceptionKind::LapsedInvoice);
+            Some(TrackedException {
+                id: record.id,
+                status: ExceptionStatus::from_record(&record),
+                is_new: record.first_seen == record.last_seen,
+                exception: Exception { kind, name: record.name, team: record.team, fan_id: record.fan_id, detail: record.detail, month },
+                assignee: record.assignee,
+                notes: record.notes,
+                first_seen: record.first_seen,
+            })
+        })
+        .collect();
+    tracked.sort_by_key(|tracked| (tracked.exception.kind, tracked.exception.team.to_lowercase(), tracked.exception.name.to_lowercase()));
+    Ok(tracked)
+}
+
+// Notes are kept as one line per entry, oldest first
+fn append_note(notes: Option<String>, line: &str) -> Option<String> {
+    Some(match notes {
+        Some(notes) if !notes.is_empty() => format!("{}\n{}", notes, line),
+        _ => line.to_string(),
+    })
+}
+
+// Applies a change to the stored exception with this id and returns it as saved
+pub fn update_workflow(conn: &Connection, id: i64, change: &WorkflowChange) -> Result<ExceptionRecord, String> {
+    let mut record = get_exception_record(conn, id).map_err(|e| e.to_string())?.ok_or_else(|| format!("No exception with id {}", id))?;
+    let at = now();
+    if let Some(status) = change.status {
+        record.status = status.code().to_string();
+        record.ignored_until = status.until().map(|until| until.format("%Y-%m-%d").to_string());
+    }
+    if let Some(assignee) = &change.assignee {
+        record.assignee = Some(assignee.trim().to_string()).filter(|assignee| !assignee.is_empty());
+    }
+    if let Some(note) = change.note.as_deref().map(str::trim).filter(|note| !note.is_empty()) {
+        record.notes = append_note(record.notes.take(), &format!("{} {}: {}", at, audit::os_user(), note));
+    }
+    record.updated_at = Some(at);
+    update_exception_workflow(conn, &record).map_err(|e| e.to_string())?;
+    Ok(record)
+}
+
+pub fn write_csv<W: Write>(writer: W, exceptions: &[&TrackedException]) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(["ID", "Exception", "Team", "Name", "FAN ID", "Detail", "Status", "Assignee", "First seen", "Notes"]).map_err(io::Error::other)?;
+    for tracked in exceptions {
+        let exception = &tracked.exception;
+        csv.write_record([
+            tracked.id.to_string().as_str(),
+            exception.kind.to_string().as_str(),
+            &exception.team,
+            &exception.name,
+            exception.fan_id.as_deref().unwrap_or(""),
+            &exception.detail,
+            tracked.status_label().as_str(),
+            tracked.assignee.as_deref().unwrap_or(""),
+            &tracked.first_seen,
+            tracked.notes.as_deref().unwrap_or(""),
+        ])
+        .map_err(io::Error::other)?;
+    }
+    csv.flush()
+}
+
+#[cfg(test)]
+mod reconciliation_tests {
+    use super::*;
+    use crate::fixtures::{self, player};
+
+    fn invoice(name: &str, product: &str, date: &str) -> LoveAdmin {
+        fixtures::invoice(name, product, date, 20.0)
+    }
+
+    #[test]
+    fn test_reconcile_finds_each_kind() {
+        let players = vec![
+            player("Amy", "Wanderers U12 Reds"),
+            player("Ben", "Wanderers U12 Reds"),
+            player("Cal", "Wanderers U14 Blues"),
+            player("Dan", "Wanderers U14 Blues"),
+        ];
+        let invoices = vec![
+            invoice("Amy Doe", "U12 Reds (*)", "2023-09-01"),
+            invoice("Amy Doe", "U12 Reds (*)", "2023-10-01"),
+            invoice("Ben Doe", "U12 Reds (*)", "2023-09-01"),
+            invoice("Cal Doe", "U12 Reds (*)", "2023-10-01"),
+            invoice("Zed Doe", "U12 Reds (*)", "2023-10-01"),
+            invoice("Zed Doe", "Summer Camp", "2023-10-01"),
+        ];
+
+        let exceptions = reconcile(&players, &invoices);
+
+        let found: Vec<(ExceptionKind, &str, &str)> =
+            exceptions.iter().map(|exception| (exception.kind, exception.name.as_str(), exception.detail.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (ExceptionKind::UnregisteredPayer, "Zed Doe", "no Wholegame player with this name"),
+                (ExceptionKind::UnregisteredPayer, "Cal Doe", "invoiced for U12 Reds but registered to Wanderers U14 Blues"),
+                (ExceptionKind::UnregisteredPayer, "Zed Doe", "no Wholegame player with this name"),
+                (ExceptionKind::UnbilledPlayer, "Cal Doe", "invoiced for U12 Reds only"),
+                (ExceptionKind::UnbilledPlayer, "Dan Doe", "never invoiced in LoveAdmin"),
+                (ExceptionKind::LapsedInvoice, "Ben Doe", "invoiced 2023-09 but not 2023-10"),
+            ]
+        );
+        assert_eq!(exceptions[5].key(), "not-in-current-month:fan-Ben:u12 reds:2023-10");
+        assert_eq!(exceptions[5].fan_id.as_deref(), Some("fan-Ben"));
+
+        let names: Vec<&str> = exceptions.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["Zed Doe", "Cal Doe", "Zed Doe", "Cal Doe", "Dan Doe", "Ben Doe"]);
+
+        // Stored and read back, every exception keeps its key, month included
+        let conn = crate::database::setup_database(Some(":memory:")).unwrap();
+        crate::database::create_tables(&conn).unwrap();
+        let mut found: Vec<String> = track(&conn, exceptions, "2023-10-15 09:00:00").unwrap().iter().map(|tracked| tracked.exception.key()).collect();
+        let mut stored: Vec<String> = tracked(&conn).unwrap().iter().map(|tracked| tracked.exception.key()).collect();
+        found.sort();
+        stored.sort();
+        assert_eq!(stored, found);
+    }
+
+    #[test]
+    fn test_tracking_keeps_workflow_between_runs() {
+        let conn = crate::database::setup_database(Some(":memory:")).unwrap();
+        crate::database::create_tables(&conn).unwrap();
+        let players = vec![player("Amy", "Wanderers U12 Reds"), player("Dan", "Wanderers U12 Reds")];
+        let invoices = vec![invoice("Amy Doe", "U12 Reds (*)", "2023-10-01"), invoice("Zed Doe", "U12 Reds (*)", "2023-10-01")];
+        let date = parse_date("2023-10-15").unwrap();
+
+        let first = track(&conn, reconcile(&players, &invoices), "2023-10-15 09:00:00").unwrap();
+        assert_eq!(first.len(), 2);
+        assert!(first.iter().all(|tracked| tracked.is_new && tracked.status == ExceptionStatus::Open));
+
+        let dan = first.iter().find(|tracked| tracked.exception.name == "Dan Doe").unwrap().id;
+        let zed = first.iter().find(|tracked| tracked.exception.name == "Zed Doe").unwrap().id;
+        let change = WorkflowChange { status: Some(ExceptionStatus::Investigating), assignee: Some("Sam".to_string()), note: Some("emailed parent".to_string()) };
+        update_workflow(&conn, dan, &change).unwrap();
+        let until = ExceptionStatus::parse("ignored", Some("2023-10-31")).unwrap();
+        update_workflow(&conn, zed, &WorkflowChange { status: Some(until), ..WorkflowChange::default() }).unwrap();
+        let record = update_workflow(&conn, dan, &WorkflowChange { note: Some("chased".to_string()), ..WorkflowChange::default() }).unwrap();
+        assert_eq!(record.notes.unwrap().lines().count(), 2);
+        assert_eq!(record.assignee.as_deref(), Some("Sam"));
+
+        // The same run again updates rather than duplicates
+        let second = track(&conn, reconcile(&players, &invoices), "2023-10-16 09:00:00").unwrap();
+        assert_eq!(second.iter().map(|tracked| tracked.id).collect::<Vec<i64>>(), first.iter().map(|tracked| tracked.id).collect::<Vec<i64>>());
+        assert!(second.iter().all(|tracked| !tracked.is_new && tracked.first_seen == "2023-10-15 09:00:00"));
+        assert_eq!(get_exception_records(&conn).unwrap().len(), 2);
+
+        let filter = ExceptionFilter { kind: None, team: None, search: None, assignee: None, include_closed: false };
+        let shown: Vec<&str> = second.iter().filter(|t| filter.matches(t, date)).map(|t| t.exception.name.as_str()).collect();
+        assert_eq!(shown, vec!["Dan Doe"]);
+        let later = parse_date("2023-11-01").unwrap();
+        assert_eq!(second.iter().filter(|t| filter.matches(t, later)).count(), 2);
+        let filter = ExceptionFilter { assignee: Some("sam".to_string()), include_closed: true, ..filter };
+        assert_eq!(second.iter().filter(|t| filter.matches(t, date)).count(), 1);
+        assert!(ExceptionStatus::parse("ignored", None).is_err());
+
+        // Fixed in the data: kept but marked as no longer found
+        let third = track(&conn, reconcile(&players, &invoices[..1]), "2023-10-17 09:00:00").unwrap();
+        assert_eq!(third.len(), 1);
+        assert!(!get_exception_record(&conn, zed).unwrap().unwrap().present);
+
+        // Reading back what the last run found changes nothing
+        let stored = tracked(&conn).unwrap();
+        assert_eq!(stored.iter().map(|tracked| (tracked.id, tracked.exception.key())).collect::<Vec<_>>(), vec![(dan, third[0].exception.key())]);
+        assert!(!stored[0].is_new && stored[0].assignee.as_deref() == Some("Sam"));
+        assert_eq!(get_exception_record(&conn, dan).unwrap().unwrap().last_seen, "2023-10-17 09:00:00");
+    }
+
+    #[test]
+    fn test_resolved_exception_reopens_when_it_recurs() {
+        let conn = crate::database::setup_database(Some(":memory:")).unwrap();
+        crate::database::create_tables(&conn).unwrap();
+        let players = vec![player("Amy", "Wanderers U12 Reds")];
+        let invoices = vec![invoice("Amy Doe", "U12 Reds (*)", "2023-10-01"), invoice("Zed Doe", "U12 Reds (*)", "2023-10-01")];
+        let zed = track(&conn, reconcile(&players, &invoices), "2023-10-15 09:00:00").unwrap()[0].id;
+        update_workflow(&conn, zed, &WorkflowChange { status: Some(ExceptionStatus::Resolved), ..WorkflowChange::default() }).unwrap();
+
+        // Still found: a manual match stays resolved
+        let again = track(&conn, reconcile(&players, &invoices), "2023-10-16 09:00:00").unwrap();
+        assert_eq!(again[0].status, ExceptionStatus::Resolved);
+
+        track(&conn, reconcile(&players, &invoices[..1]), "2023-10-17 09:00:00").unwrap();
+        let back = track(&conn, reconcile(&players, &invoices), "2023-11-15 09:00:00").unwrap();
+        assert_eq!(back[0].status, ExceptionStatus::Open);
+        assert!(back[0].notes.as_deref().unwrap().ends_with("found again after being resolved"));
+        let record = get_exception_record(&conn, zed).unwrap().unwrap();
+        assert_eq!((record.status.as_str(), record.present), ("open", true));
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Datelike;
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{
+    get_all_loveadmin, get_all_wholegame, get_exception_records, get_import_batches, insert_exception_record, insert_import_batch, replace_loveadmin,
+    replace_wholegame, ExceptionRecord,
+};
+use crate::dates::parse_date;
+use crate::matching::{full_name, normalise_name};
+
+pub const FIRST_NAMES: &[&str] = &[
+    "Oliver", "Amelia", "George", "Isla", "Harry", "Ava", "Noah", "Mia", "Jack", "Ivy", "Leo", "Lily", "Arthur", "Isabella", "Muhammad",
+    "Rosie", "Oscar", "Sophia", "Charlie", "Grace", "Jacob", "Freya", "Thomas", "Evie", "Henry", "Florence", "William", "Poppy", "Alfie",
+    "Ella", "Theo", "Willow", "Archie", "Millie", "Joshua", "Daisy", "Freddie", "Sienna", "Ethan", "Harper",
+];
+
+pub const SURNAMES: &[&str] = &[
+    "Smith", "Jones", "Taylor", "Brown", "Williams", "Wilson", "Johnson", "Davies", "Robinson", "Wright", "Thompson", "Evans", "Walker",
+    "White", "Roberts", "Green", "Hall", "Wood", "Jackson", "Clarke", "Patel", "Khan", "Lewis", "Harris", "Martin", "Cooper", "King",
+    "Baker", "Turner", "Hill", "Ward", "Morris", "Moore", "Clark", "Lee", "Hughes", "Edwards", "Ahmed", "Scott", "Young",
+];
+
+// Fake numbers come from Ofcom's range reserved for drama
+const PHONE_PREFIX: &str = "07700 9";
+const FAN_ID_BASE: u64 = 900_000_000;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RedactionSummary {
+    pub players: usize,
+    pub invoices: usize,
+    pub people: usize,
+    pub exceptions: usize,
+}
+
+// Hands out fake values, always the same one for the same real value, so a
+// person gets one pseudonym across both tables. The seed picks which fake
+// values are used; without it they can't be traced back.
+pub struct Redactor {
+    seed: String,
+    names: HashMap<String, String>,
+    used_names: HashSet<String>,
+    emails: HashMap<String, String>,
+    phones: HashMap<String, String>,
+    fan_ids: HashMap<String, String>,
+}
+
+impl Redactor {
+    pub fn new(seed: &str) -> Self {
+        Redactor {
+            seed: seed.to_string(),
+            names: HashMap::new(),
+            used_names: HashSet::new(),
+            emails: HashMap::new(),
+            phones: HashMap::new(),
+            fan_ids: HashMap::new(),
+        }
+    }
+
+    fn hash(&self, kind: &str, value: &str) -> u64 {
+        let digest = Sha256::new().chain_update(self.seed.as_bytes()).chain_update([0]).chain_update(kind).chain_update([0]).chain_update(value).finalize();
+        u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+    }
+
+    // Names that match after normalising (case, spacing) get the same pseudonym,
+    // and different names never share one, so name matching behaves the same
+    pub fn name(&mut self, real: &str) -> String {
+        let key = normalise_name(real);
+        if key.is_empty() {
+            return real.to_string();
+        }
+        if let Some(fake) = self.names.get(&key) {
+            return fake.clone();
+        }
+        let combinations = (FIRST_NAMES.len() * SURNAMES.len()) as u64;
+        let start = self.hash("name", &key);
+        let fake = (0..combinations)
+            .map(|step| {
+                let index = ((start % combinations + step) % combinations) as usize;
+                format!("{} {}", FIRST_NAMES[index % FIRST_NAMES.len()], SURNAMES[index / FIRST_NAMES.len()])
+            })
+            .find(|candidate| !self.used_names.contains(&normalise_name(candidate)))
+            .unwrap_or_else(|| format!("{} {}-{}", FIRST_NAMES[0], SURNAMES[0], self.used_names.len()));
+        self.used_names.insert(normalise_name(&fake));
+        self.names.insert(key, fake.clone());
+        fake
+    }
+
+    fn optional_name(&mut self, real: &Option<String>) -> Option<String> {
+        real.as_deref().map(|real| self.name(real))
+    }
+
+    pub fn email(&mut self, real: &str) -> String {
+        let key = real.trim().to_lowercase();
+        if key.is_empty() {
+            return real.to_string();
+        }
+        let next = self.emails.len() + 1;
+        let index = self.hash("email", &key) as usize;
+        self.emails
+            .entry(key)
+            .or_insert_with(|| {
+                let first = FIRST_NAMES[index % FIRST_NAMES.len()].to_lowercase();
+                let surname = SURNAMES[(index / FIRST_NAMES.len()) % SURNAMES.len()].to_lowercase();
+                format!("{}.{}{}@example.com", first, surname, next)
+            })
+            .clone()
+    }
+
+    fn optional_email(&mut self, real: &Option<String>) -> Option<String> {
+        real.as_deref().map(|real| self.email(real))
+    }
+
+    pub fn phone(&mut self, real: &str) -> String {
+        let key: String = real.chars().filter(char::is_ascii_digit).collect();
+        if key.is_empty() {
+            return real.to_string();
+        }
+        let next = self.phones.len();
+        self.phones.entry(key).or_insert_with(|| format!("{}{:05}", PHONE_PREFIX, next)).clone()
+    }
+
+    pub fn fan_id(&mut self, real: &str) -> String {
+        let key = real.trim().to_string();
+        if key.is_empty() {
+            return key;
+        }
+        let next = self.fan_ids.len() as u64 + 1;
+        self.fan_ids.entry(key).or_insert_with(|| (FAN_ID_BASE + next).to_string()).clone()
+    }
+
+    // Keeps the year and month, so age groups and eligibility are unchanged
+    pub fn date_of_birth(&self, real: &str) -> String {
+        match parse_date(real) {
+            Some(date) => {
+                let day = (self.hash("dob", real) % 28) as u32 + 1;
+                date.with_day(day).unwrap_or(date).format("%Y-%m-%d").to_string()
+            }
+            None => real.to_string(),
+        }
+    }
+
+    pub fn player(&mut self, player: &Wholegame) -> Wholegame {
+        let fake = self.name(&full_name(player));
+        let (first_names, surname) = fake.split_once(' ').unwrap_or((fake.as_str(), ""));
+        let mut redacted = player.clone();
+        redacted.set_first_names(first_names.to_string());
+        redacted.set_surname(surname.to_string());
+        redacted.set_fan_id(self.fan_id(player.get_fan_id()));
+        redacted.set_date_of_birth(self.date_of_birth(player.get_date_of_birth()));
+        redacted.set_email_address(self.email(player.get_email_address()));
+        redacted.set_parent_carer_name(self.optional_name(player.get_parent_carer_name()));
+        redacted.set_parent_carer_email_address(self.optional_email(player.get_parent_carer_email_address()));
+        redacted.set_emergency_contact(self.optional_name(player.get_emergency_contact()));
+        redacted.set_emergency_contact_phone_number(player.get_emergency_contact_phone_number().as_deref().map(|phone| self.phone(phone)));
+        redacted
+    }
+
+    pub fn invoice(&mut self, invoice: &LoveAdmin) -> LoveAdmin {
+        let mut redacted = invoice.clone();
+        redacted.set_name(self.name(invoice.get_name()));
+        redacted.set_account_owner(self.name(invoice.get_account_owner()));
+        redacted
+    }
+
+    // Reconciliation keys carry a FAN ID or normalised name as their second part
+    pub fn exception_key(&mut self, key: &str) -> String {
+        let mut parts: Vec<String> = key.split(':').map(String::from).collect();
+        if let Some(subject) = parts.get(1).cloned() {
+            parts[1] = match self.fan_ids.get(&subject) {
+                Some(fan_id) => fan_id.clone(),
+                None if subject.is_empty() => subject,
+                None => normalise_name(&self.name(&subject)),
+            };
+        }
+        parts.join(":")
+    }
+
+    pub fn people(&self) -> usize {
+        self.names.len()
+    }
+}
+
+// Copies the club data into `target` with every personal detail replaced.
+// Tracked exceptions keep their status but lose their notes and assignee, and
+// the next reconciliation run fills in their description. Import history comes
+// across too; the audit log and encryption settings don't.
+pub fn redact_database(source: &Connection, target: &Connection, seed: &str) -> rusqlite::Result<RedactionSummary> {
+    let mut redactor = Redactor::new(seed);
+    let players = get_all_wholegame(source)?;
+    let invoices = get_all_loveadmin(source)?;
+    let exceptions = get_exception_records(source)?;
+
+    let tx = target.unchecked_transaction()?;
+    let redacted_players: Vec<Wholegame> = players.iter().map(|player| redactor.player(player)).collect();
+    replace_wholegame(&tx, &redacted_players)?;
+    let redacted_invoices: Vec<LoveAdmin> = invoices.iter().map(|invoice| redactor.invoice(invoice)).collect();
+    replace_loveadmin(&tx, &redacted_invoices)?;
+    for exception in &exceptions {
+        let redacted = ExceptionRecord {
+            key: redactor.exception_key(&exception.key),
+            kind: exception.kind.clone(),
+            status: exception.status.clone(),
+            ignored_until: exception.ignored_until.clone(),
+            first_seen: exception.first_seen.clone(),
+            last_seen: exception.last_seen.clone(),
+            ..ExceptionRecord::default()
+        };
+        insert_exception_record(&tx, &redacted)?;
+    }
+    for batch in get_import_batches(source)?.iter().rev() {
+        insert_import_batch(&tx, &batch.source, &batch.file_name, &batch.imported_at, batch.row_count as usize, batch.format_version.as_deref())?;
+    }
+    tx.commit()?;
+    Ok(RedactionSummary { players: players.len(), invoices: invoices.len(), people: redactor.people(), exceptions: exceptions.len() })
+}
+
+#[cfg(test)]
+mod redact_tests {
+    use super::*;
+    use crate::database::{create_tables, get_exception_record_by_key, insert_loveadmin, insert_wholegame, resolve_exception, setup_database};
+    use crate::fixtures;
+    use crate::reconciliation::reconcile;
+
+    fn player(first: &str, surname: &str, fan_id: &str, dob: &str, parent: &str) -> Wholegame {
+        let mut player = fixtures::player(first, "Wanderers U12 Reds");
+        player.set_surname(surname.to_string());
+        player.set_fan_id(fan_id.to_string());
+        player.set_date_of_birth(dob.to_string());
+        player.set_email_address(format!("{}@example.org", first.to_lowercase()));
+        player.set_parent_carer_name(Some(parent.to_string()));
+        player.set_emergency_contact_phone_number(Some("07123 456789".to_string()));
+        player
+    }
+
+    fn invoice(name: &str, owner: &str, date: &str) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, "U12 Reds (*)", date, 20.0);
+        invoice.set_account_owner(owner.to_string());
+        invoice
+    }
+
+    fn database() -> Connection {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_redacted_copy_reconciles_the_same() {
+        let source = database();
+        insert_wholegame(&source, &player("Amy", "Doe", "12345678", "2012-05-17", "Jo Doe")).unwrap();
+        insert_wholegame(&source, &player("Ben", "Roe", "23456789", "2012-09-03", "Al Roe")).unwrap();
+        for (name, date) in [("amy  DOE", "2023-10-01"), ("Cat Poe", "2023-10-01"), ("Ben Roe", "2023-09-01")] {
+            insert_loveadmin(&source, &invoice(name, "Jo Doe", date)).unwrap();
+        }
+        let exceptions = reconcile(&get_all_wholegame(&source).unwrap(), &get_all_loveadmin(&source).unwrap());
+        assert_eq!(exceptions.len(), 2);
+        for exception in &exceptions {
+            resolve_exception(&source, &exception.key(), "2023-10-02").unwrap();
+        }
+
+        let target = database();
+        let summary = redact_database(&source, &target, "seed").unwrap();
+        assert_eq!(summary, RedactionSummary { players: 2, invoices: 3, people: 5, exceptions: 2 });
+
+        let players = get_all_wholegame(&target).unwrap();
+        let invoices = get_all_loveadmin(&target).unwrap();
+        assert_ne!(players[0].get_first_names(), "Amy");
+        assert_eq!(players[0].get_fan_id(), "900000001");
+        assert_eq!(&players[0].get_date_of_birth()[..7], "2012-05");
+        assert_eq!(players[0].get_email_address().split('@').nth(1), Some("example.com"));
+        assert_eq!(players[0].get_emergency_contact_phone_number(), players[1].get_emergency_contact_phone_number());
+        assert_eq!(invoices[0].get_name(), &full_name(&players[0]));
+        assert_eq!(players[0].get_parent_carer_name().as_ref(), Some(invoices[1].get_account_owner()));
+
+        let shape = |exceptions: &[crate::reconciliation::Exception]| exceptions.iter().map(|exception| (exception.kind, exception.month)).collect::<Vec<_>>();
+        assert_eq!(shape(&reconcile(&players, &invoices)), shape(&exceptions));
+        let status = |key: &str| get_exception_record_by_key(&target, key).unwrap().map(|record| record.status);
+        assert!(reconcile(&players, &invoices).iter().all(|exception| status(&exception.key()).as_deref() == Some("resolved")));
+
+        // The same seed gives the same pseudonyms
+        let again = database();
+        redact_database(&source, &again, "seed").unwrap();
+        assert_eq!(full_name(&get_all_wholegame(&again).unwrap()[1]), full_name(&players[1]));
+    }
+}
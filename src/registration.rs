@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::NaiveDate;
+
+use crate::contacts::resolve_contacts;
+use crate::data_structures::Wholegame;
+use crate::dates::parse_date;
+use crate::matching::full_name;
+
+#[derive(Debug, PartialEq)]
+pub enum ExpiryState {
+    Expired,
+    ExpiresIn(i64),
+}
+
+pub struct ExpiryEntry {
+    pub fan_id: String,
+    pub name: String,
+    pub expiry: Option<NaiveDate>,
+    pub registration_status: String,
+    pub state: ExpiryState,
+    pub contact_name: String,
+    pub contact_email: Option<String>,
+}
+
+impl fmt::Display for ExpiryState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpiryState::Expired => write!(f, "expired"),
+            ExpiryState::ExpiresIn(0) => write!(f, "expires today"),
+            ExpiryState::ExpiresIn(days) => write!(f, "expires in {} days", days),
+        }
+    }
+}
+
+// Wholegame sometimes marks a registration expired without an expiry date
+fn status_says_expired(status: &str) -> bool {
+    let status = status.to_lowercase();
+    status.contains("expired") || status.contains("lapsed")
+}
+
+pub fn expiry_state(player: &Wholegame, reference_date: NaiveDate, window_days: i64) -> Option<ExpiryState> {
+    let expiry = player.get_registration_expiry().as_deref().and_then(parse_date);
+    match expiry {
+        Some(expiry) if expiry < reference_date => Some(ExpiryState::Expired),
+        Some(expiry) if (expiry - reference_date).num_days() <= window_days => {
+            Some(ExpiryState::ExpiresIn((expiry - reference_date).num_days()))
+        }
+        _ if status_says_expired(player.get_registration_status()) => Some(ExpiryState::Expired),
+        _ => None,
+    }
+}
+
+// Registered means Wholegame shows an accepted registration that hasn't expired
+pub fn is_registered(player: &Wholegame, reference_date: NaiveDate) -> bool {
+    let status = player.get_registration_status().to_lowercase();
+    let accepted = ["registered", "active", "approved"].iter().any(|word| status.contains(word));
+    accepted && !status.contains("unregistered") && expiry_state(player, reference_date, 0) != Some(ExpiryState::Expired)
+}
+
+// Players whose registration has expired or expires within the window,
+// grouped by team and soonest first
+pub fn expiry_report(players: &[Wholegame], reference_date: NaiveDate, window_days: i64) -> BTreeMap<String, Vec<ExpiryEntry>> {
+    let mut report: BTreeMap<String, Vec<ExpiryEntry>> = BTreeMap::new();
+    for player in players {
+        let state = match expiry_state(player, reference_date, window_days) {
+            Some(state) => state,
+            None => continue,
+        };
+        let contacts = resolve_contacts(player, reference_date);
+        let contact_email = contacts.primary_email().map(String::from);
+        let contact_name = contacts.primary.map(|contact| contact.name).unwrap_or_default();
+        report.entry(player.get_team().clone()).or_default().push(ExpiryEntry {
+            fan_id: player.get_fan_id().clone(),
+            name: full_name(player),
+            expiry: player.get_registration_expiry().as_deref().and_then(parse_date),
+            registration_status: player.get_registration_status().clone(),
+            state,
+            contact_name,
+            contact_email,
+        });
+    }
+    for entries in report.values_mut() {
+        entries.sort_by(|a, b| a.expiry.cmp(&b.expiry).then_with(|| a.name.cmp(&b.name)));
+    }
+    report
+}
+
+#[cfg(test)]
+mod registration_tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn player(name: &str, team: &str, expiry: Option<&str>, status: &str) -> Wholegame {
+        let mut player = fixtures::player(name, team);
+        player.set_date_of_birth("2012-01-01".to_string());
+        player.set_registration_expiry(expiry.map(String::from));
+        player.set_registration_status(status.to_string());
+        player.set_parent_carer_name(Some("John Doe".to_string()));
+        player.set_parent_carer_email_address(Some("johndoe@example.com".to_string()));
+        player
+    }
+
+    #[test]
+    fn test_expiry_state_uses_window_and_status() {
+        let today = parse_date("2024-08-01").unwrap();
+        assert_eq!(expiry_state(&player("A", "U12", Some("2024-07-31"), "Active"), today, 30), Some(ExpiryState::Expired));
+        assert_eq!(expiry_state(&player("A", "U12", Some("2024-08-31"), "Active"), today, 30), Some(ExpiryState::ExpiresIn(30)));
+        assert_eq!(expiry_state(&player("A", "U12", Some("2024-09-01"), "Active"), today, 30), None);
+        assert_eq!(expiry_state(&player("A", "U12", None, "Expired"), today, 30), Some(ExpiryState::Expired));
+        assert_eq!(expiry_state(&player("A", "U12", None, "Active"), today, 30), None);
+    }
+
+    #[test]
+    fn test_report_groups_by_team_with_contact() {
+        let today = parse_date("2024-08-01").unwrap();
+        let players = vec![
+            player("Jane", "U12 Reds", Some("2024-08-20"), "Active"),
+            player("Jim", "U12 Reds", Some("2024-08-02"), "Active"),
+            player("Joe", "U14 Blues", Some("2025-08-02"), "Active"),
+            player("Jill", "U14 Blues", Some("2024-01-01"), "Active"),
+        ];
+
+        let report = expiry_report(&players, today, 30);
+
+        assert_eq!(report.len(), 2);
+        let reds = &report["U12 Reds"];
+        assert_eq!(reds[0].name, "Jim Doe");
+        assert_eq!(reds[0].state, ExpiryState::ExpiresIn(1));
+        assert_eq!(reds[1].contact_email.as_deref(), Some("johndoe@example.com"));
+        assert_eq!(report["U14 Blues"].len(), 1);
+        assert_eq!(report["U14 Blues"][0].state, ExpiryState::Expired);
+    }
+
+    #[test]
+    fn test_is_registered() {
+        let today = parse_date("2024-08-01").unwrap();
+        assert!(is_registered(&player("A", "U12", Some("2025-07-31"), "Registered"), today));
+        assert!(is_registered(&player("A", "U12", None, "Active"), today));
+        assert!(!is_registered(&player("A", "U12", Some("2024-07-31"), "Registered"), today));
+        assert!(!is_registered(&player("A", "U12", None, "Pending"), today));
+        assert!(!is_registered(&player("A", "U12", None, "Unregistered"), today));
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{Months, NaiveDate};
+use rusqlite::Connection;
+use serde_json::json;
+
+use crate::age_groups::SeasonConfig;
+use crate::audit;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{delete_wholegame, update_loveadmin};
+use crate::dates::parse_date;
+
+pub const ANONYMISED_MEMBER: &str = "Anonymised member";
+pub const ANONYMISED_PAYER: &str = "Anonymised payer";
+
+pub struct RetentionPolicy {
+    // Full seasons to keep a Wholegame record after the season the player left
+    pub wholegame_seasons: i32,
+    // Years to keep names on LoveAdmin invoices; the amounts are kept for good
+    pub loveadmin_years: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy { wholegame_seasons: 2, loveadmin_years: 6 }
+    }
+}
+
+pub struct ExpiredPlayer {
+    pub player: Wholegame,
+    pub left: NaiveDate,
+}
+
+pub struct RetentionPlan {
+    pub players: Vec<ExpiredPlayer>,
+    pub invoices: Vec<(i64, LoveAdmin)>,
+}
+
+// A player has left once their registration has expired. Without an expiry
+// date we can't tell, so they're kept.
+pub fn left_club(player: &Wholegame, reference_date: NaiveDate) -> Option<NaiveDate> {
+    let expiry = player.get_registration_expiry().as_deref().and_then(parse_date)?;
+    (expiry < reference_date).then_some(expiry)
+}
+
+fn already_anonymised(invoice: &LoveAdmin) -> bool {
+    invoice.get_name() == ANONYMISED_MEMBER && invoice.get_account_owner() == ANONYMISED_PAYER
+}
+
+pub fn plan(players: &[Wholegame], invoices: &[(i64, LoveAdmin)], policy: &RetentionPolicy, reference_date: NaiveDate) -> RetentionPlan {
+    let current_season = SeasonConfig::containing(reference_date).start_year;
+    let players = players
+        .iter()
+        .filter_map(|player| {
+            let left = left_club(player, reference_date)?;
+            let seasons_since = current_season - SeasonConfig::containing(left).start_year - 1;
+            (seasons_since >= policy.wholegame_seasons).then(|| ExpiredPlayer { player: player.clone(), left })
+        })
+        .collect();
+
+    let cut_off = policy
+        .loveadmin_years
+        .checked_mul(12)
+        .and_then(|months| reference_date.checked_sub_months(Months::new(months)))
+        .unwrap_or(NaiveDate::MIN);
+    let invoices = invoices
+        .iter()
+        .filter(|(_, invoice)| !already_anonymised(invoice) && parse_date(invoice.get_date()).is_some_and(|date| date < cut_off))
+        .cloned()
+        .collect();
+    RetentionPlan { players, invoices }
+}
+
+// Deletes and anonymises everything in the plan. Each change is audited as
+// usual, then the values the log holds for those records are redacted and
+// one entry records the rules and counts.
+pub fn apply(conn: &Connection, plan: &RetentionPlan, policy: &RetentionPolicy, reference_date: NaiveDate) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for expired in &plan.players {
+        delete_wholegame(&tx, expired.player.get_fan_id())?;
+    }
+    for (id, invoice) in &plan.invoices {
+        let mut anonymised = invoice.clone();
+        anonymised.set_name(ANONYMISED_MEMBER.to_string());
+        anonymised.set_account_owner(ANONYMISED_PAYER.to_string());
+        update_loveadmin(&tx, *id, &anonymised)?;
+    }
+    let players = plan.players.iter().map(|expired| audit::player_entity(expired.player.get_fan_id()));
+    let entities: Vec<String> = players.chain(plan.invoices.iter().map(|(id, _)| audit::invoice_entity(*id))).collect();
+    let after = json!({
+        "reference_date": reference_date.to_string(),
+        "wholegame_seasons": policy.wholegame_seasons,
+        "loveadmin_years": policy.loveadmin_years,
+        "wholegame_deleted": plan.players.len(),
+        "loveadmin_anonymised": plan.invoices.len(),
+        "audit_entries_redacted": audit::redact(&tx, &entities)?,
+    });
+    audit::record(&tx, "retention", "retention", None, Some(&after))?;
+    tx.commit()
+}
+
+#[cfg(test)]
+mod retention_tests {
+    use super::*;
+    use crate::database::{create_tables, get_all_loveadmin, get_all_loveadmin_with_ids, get_all_wholegame, insert_loveadmin, insert_wholegame, setup_database};
+    use crate::fixtures;
+
+    fn player(fan_id: &str, expiry: Option<&str>, registered: &str) -> Wholegame {
+        let mut player = fixtures::player("Amy", "");
+        player.set_fan_id(fan_id.to_string());
+        player.set_registration_expiry(expiry.map(String::from));
+        player.set_date_submitted(registered.to_string());
+        player
+    }
+
+    fn invoice(date: &str) -> LoveAdmin {
+        let mut invoice = fixtures::invoice("Amy Doe", "", date, 20.0);
+        invoice.set_account_owner("Jo Doe".to_string());
+        invoice
+    }
+
+    #[test]
+    fn test_plan_and_apply() {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        // By October 2023 the 2020/21 leaver has had two full seasons since, the
+        // 2021/22 leaver one; the others are registered or have no expiry
+        insert_wholegame(&conn, &player("100", Some("2021-06-30"), "2020-09-01")).unwrap();
+        insert_wholegame(&conn, &player("200", Some("2022-06-30"), "2021-09-01")).unwrap();
+        insert_wholegame(&conn, &player("300", Some("2024-06-30"), "2023-09-01")).unwrap();
+        insert_wholegame(&conn, &player("400", None, "2019-09-01")).unwrap();
+        for date in ["2017-09-01", "2017-11-01", "2023-09-01"] {
+            insert_loveadmin(&conn, &invoice(date)).unwrap();
+        }
+        let reference_date = parse_date("2023-10-15").unwrap();
+        let policy = RetentionPolicy::default();
+
+        let planned = plan(&get_all_wholegame(&conn).unwrap(), &get_all_loveadmin_with_ids(&conn).unwrap(), &policy, reference_date);
+        let fan_ids: Vec<&str> = planned.players.iter().map(|expired| expired.player.get_fan_id().as_str()).collect();
+        assert_eq!(fan_ids, vec!["100"]);
+        assert_eq!(planned.players[0].left, parse_date("2021-06-30").unwrap());
+        assert_eq!(planned.invoices.len(), 1);
+
+        apply(&conn, &planned, &policy, reference_date).unwrap();
+        assert_eq!(get_all_wholegame(&conn).unwrap().len(), 3);
+        let invoices = get_all_loveadmin(&conn).unwrap();
+        assert_eq!((invoices[0].get_name().as_str(), invoices[0].get_account_owner().as_str()), (ANONYMISED_MEMBER, ANONYMISED_PAYER));
+        assert_eq!(invoices[0].get_invoiced(), 20.0);
+        assert_eq!(invoices[1].get_name(), "Amy Doe");
+
+        let after: String = conn.query_row("SELECT After FROM audit_log WHERE Action = 'retention'", [], |row| row.get(0)).unwrap();
+        assert!(after.contains("\"wholegame_deleted\":1"));
+
+        // Nothing left to do on a second run
+        let planned = plan(&get_all_wholegame(&conn).unwrap(), &get_all_loveadmin_with_ids(&conn).unwrap(), &policy, reference_date);
+        assert!(planned.players.is_empty() && planned.invoices.is_empty());
+        let forever = RetentionPolicy { loveadmin_years: u32::MAX, ..policy };
+        assert!(plan(&[], &[(1, invoice("1900-01-01"))], &forever, reference_date).invoices.is_empty());
+    }
+
+    #[test]
+    fn test_apply_redacts_audit_log() {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        let mut leaver = player("100", Some("2021-06-30"), "2020-09-01");
+        leaver.set_first_names("Zoe".to_string());
+        leaver.set_email_address("zoe@example.com".to_string());
+        insert_wholegame(&conn, &leaver).unwrap();
+        insert_wholegame(&conn, &player("300", Some("2024-06-30"), "2023-09-01")).unwrap();
+        let mut old = invoice("2017-09-01");
+        old.set_name("Zoe Doe".to_string());
+        old.set_account_owner("Max Doe".to_string());
+        insert_loveadmin(&conn, &old).unwrap();
+        insert_loveadmin(&conn, &invoice("2023-09-01")).unwrap();
+        let reference_date = parse_date("2023-10-15").unwrap();
+        let policy = RetentionPolicy::default();
+
+        let planned = plan(&get_all_wholegame(&conn).unwrap(), &get_all_loveadmin_with_ids(&conn).unwrap(), &policy, reference_date);
+        apply(&conn, &planned, &policy, reference_date).unwrap();
+
+        // The entries stay but none holds the deleted player's or old invoice's details
+        let mentions = |text: &str| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM audit_log WHERE Before LIKE '%' || ?1 || '%' OR After LIKE '%' || ?1 || '%'", [text], |row| row.get(0)).unwrap()
+        };
+        assert_eq!((mentions("Zoe"), mentions("zoe@example.com"), mentions("Max Doe")), (0, 0, 0));
+        assert!(mentions("Amy") > 0);
+        let entries: i64 = conn.query_row("SELECT COUNT(*) FROM audit_log WHERE Entity IN ('wholegame:100', 'loveadmin:1')", [], |row| row.get(0)).unwrap();
+        assert_eq!(entries, 4);
+        let after: String = conn.query_row("SELECT After FROM audit_log WHERE Action = 'retention'", [], |row| row.get(0)).unwrap();
+        assert!(after.contains("\"audit_entries_redacted\":4"));
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::NaiveDate;
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+
+use crate::compliance::{photo_issue, ComplianceIssue};
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::http::escape_html;
+use crate::matching::{full_name, invoices_for_player, outstanding_for_player, AMOUNT_TOLERANCE};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RosterColumn {
+    Name,
+    FanId,
+    DateOfBirth,
+    AgeGroup,
+    Team,
+    RegistrationStatus,
+    PhotoStatus,
+    EmergencyContact,
+    PaidUp,
+}
+
+pub const DEFAULT_COLUMNS: [RosterColumn; 8] = [
+    RosterColumn::Name,
+    RosterColumn::FanId,
+    RosterColumn::DateOfBirth,
+    RosterColumn::AgeGroup,
+    RosterColumn::RegistrationStatus,
+    RosterColumn::PhotoStatus,
+    RosterColumn::EmergencyContact,
+    RosterColumn::PaidUp,
+];
+
+pub enum RosterFormat {
+    Csv,
+    Xlsx,
+    Html,
+}
+
+pub struct RosterEntry {
+    pub name: String,
+    pub fan_id: String,
+    pub date_of_birth: String,
+    pub age_group: String,
+    pub team: String,
+    pub registration_status: String,
+    pub photo_status: String,
+    pub emergency_contact: String,
+    // None when the player has never been invoiced
+    pub paid_up: Option<bool>,
+}
+
+impl RosterColumn {
+    pub fn parse(value: &str) -> Option<RosterColumn> {
+        match value.trim().to_lowercase().replace(['-', ' '], "_").as_str() {
+            "name" => Some(RosterColumn::Name),
+            "fan_id" => Some(RosterColumn::FanId),
+            "dob" | "date_of_birth" => Some(RosterColumn::DateOfBirth),
+            "age_group" => Some(RosterColumn::AgeGroup),
+            "team" => Some(RosterColumn::Team),
+            "status" | "registration_status" => Some(RosterColumn::RegistrationStatus),
+            "photo" | "photo_status" => Some(RosterColumn::PhotoStatus),
+            "emergency_contact" => Some(RosterColumn::EmergencyContact),
+            "paid_up" => Some(RosterColumn::PaidUp),
+            _ => None,
+        }
+    }
+
+    // Comma separated list of column names, in the order they should appear
+    pub fn parse_list(value: &str) -> Result<Vec<RosterColumn>, String> {
+        value
+            .split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| RosterColumn::parse(name).ok_or_else(|| format!("Unknown roster column '{}'", name.trim())))
+            .collect()
+    }
+
+    pub fn heading(&self) -> &'static str {
+        match self {
+            RosterColumn::Name => "Name",
+            RosterColumn::FanId => "FAN ID",
+            RosterColumn::DateOfBirth => "Date of birth",
+            RosterColumn::AgeGroup => "Age group",
+            RosterColumn::Team => "Team",
+            RosterColumn::RegistrationStatus => "Registration status",
+            RosterColumn::PhotoStatus => "Photo",
+            RosterColumn::EmergencyContact => "Emergency contact",
+            RosterColumn::PaidUp => "Paid up",
+        }
+    }
+}
+
+impl RosterFormat {
+    pub fn parse(value: &str) -> Option<RosterFormat> {
+        match value.to_lowercase().as_str() {
+            "csv" => Some(RosterFormat::Csv),
+            "xlsx" => Some(RosterFormat::Xlsx),
+            "html" => Some(RosterFormat::Html),
+            _ => None,
+        }
+    }
+}
+
+impl RosterEntry {
+    pub fn value(&self, column: RosterColumn) -> String {
+        match column {
+            RosterColumn::Name => self.name.clone(),
+            RosterColumn::FanId => self.fan_id.clone(),
+            RosterColumn::DateOfBirth => self.date_of_birth.clone(),
+            RosterColumn::AgeGroup => self.age_group.clone(),
+            RosterColumn::Team => self.team.clone(),
+            RosterColumn::RegistrationStatus => self.registration_status.clone(),
+            RosterColumn::PhotoStatus => self.photo_status.clone(),
+            RosterColumn::EmergencyContact => self.emergency_contact.clone(),
+            RosterColumn::PaidUp => match self.paid_up {
+                Some(true) => "Yes".to_string(),
+                Some(false) => "No".to_string(),
+                None => "Not invoiced".to_string(),
+            },
+        }
+    }
+}
+
+fn photo_status(player: &Wholegame, reference_date: NaiveDate, photo_max_years: u32) -> String {
+    match photo_issue(player, reference_date, photo_max_years) {
+        Some(ComplianceIssue::NoPhoto) => "Missing".to_string(),
+        Some(ComplianceIssue::PhotoOutOfDate(_)) => "Out of date".to_string(),
+        _ => "OK".to_string(),
+    }
+}
+
+fn emergency_contact(player: &Wholegame) -> String {
+    let name = player.get_emergency_contact().as_deref().unwrap_or("").trim();
+    let phone = player.get_emergency_contact_phone_number().as_deref().unwrap_or("").trim();
+    match (name.is_empty(), phone.is_empty()) {
+        (false, false) => format!("{} ({})", name, phone),
+        (false, true) => name.to_string(),
+        _ => phone.to_string(),
+    }
+}
+
+// Wholegame players grouped by team and sorted by name, with the paid-up
+// flag taken from their LoveAdmin invoices
+pub fn build_rosters(players: &[Wholegame], invoices: &[LoveAdmin], reference_date: NaiveDate, photo_max_years: u32) -> BTreeMap<String, Vec<RosterEntry>> {
+    let mut rosters: BTreeMap<String, Vec<RosterEntry>> = BTreeMap::new();
+    for player in players {
+        let paid_up = if invoices_for_player(player, invoices).is_empty() {
+            None
+        } else {
+            Some(outstanding_for_player(player, invoices) < AMOUNT_TOLERANCE)
+        };
+        rosters.entry(player.get_team().trim().to_string()).or_default().push(RosterEntry {
+            name: full_name(player),
+            fan_id: player.get_fan_id().clone(),
+            date_of_birth: player.get_date_of_birth().clone(),
+            age_group: player.get_age_group().clone(),
+            team: player.get_team().trim().to_string(),
+            registration_status: player.get_registration_status().clone(),
+            photo_status: photo_status(player, reference_date, photo_max_years),
+            emergency_contact: emergency_contact(player),
+            paid_up,
+        });
+    }
+    for entries in rosters.values_mut() {
+        entries.sort_by_key(|entry| entry.name.to_lowercase());
+    }
+    rosters
+}
+
+// All teams in one sheet, team first so the file can be filtered in a spreadsheet
+pub fn write_csv<W: Write>(writer: W, rosters: &BTreeMap<String, Vec<RosterEntry>>, columns: &[RosterColumn]) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    let with_team = !columns.contains(&RosterColumn::Team);
+    let mut headings: Vec<&str> = columns.iter().map(|column| column.heading()).collect();
+    if with_team {
+        headings.insert(0, RosterColumn::Team.heading());
+    }
+    csv.write_record(&headings).map_err(io::Error::other)?;
+    for entry in rosters.values().flatten() {
+        let mut record: Vec<String> = columns.iter().map(|column| entry.value(*column)).collect();
+        if with_team {
+            record.insert(0, entry.team.clone());
+        }
+        csv.write_record(&record).map_err(io::Error::other)?;
+    }
+    csv.flush()
+}
+
+// Excel sheet names can't contain []:*?/\, are limited to 31 characters and
+// must differ ignoring case. Teams that only differ after the cut get " (2)",
+// " (3)" and so on in place of their last characters.
+fn sheet_name(team: &str, used: &mut HashSet<String>) -> String {
+    let name: String = team.chars().filter(|c| !"[]:*?/\\".contains(*c)).collect();
+    let name = if name.trim().is_empty() { "No team".to_string() } else { name };
+    let mut copy = 1;
+    loop {
+        let suffix = if copy == 1 { String::new() } else { format!(" ({})", copy) };
+        let candidate = format!("{}{}", name.chars().take(31 - suffix.len()).collect::<String>(), suffix);
+        if used.insert(candidate.to_lowercase()) {
+            return candidate;
+        }
+        copy += 1;
+    }
+}
+
+// One worksheet per team
+fn workbook(rosters: &BTreeMap<String, Vec<RosterEntry>>, columns: &[RosterColumn]) -> Result<Workbook, XlsxError> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let mut used = HashSet::new();
+    for (team, entries) in rosters {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(sheet_name(team, &mut used))?;
+        for (col, column) in columns.iter().enumerate() {
+            worksheet.write_string_with_format(0, col as u16, column.heading(), &bold)?;
+        }
+        for (row, entry) in entries.iter().enumerate() {
+            for (col, column) in columns.iter().enumerate() {
+                worksheet.write_string(row as u32 + 1, col as u16, entry.value(*column))?;
+            }
+        }
+        worksheet.autofit();
+    }
+    if rosters.is_empty() {
+        workbook.add_worksheet();
+    }
+    Ok(workbook)
+}
+
+pub fn write_xlsx(path: &Path, rosters: &BTreeMap<String, Vec<RosterEntry>>, columns: &[RosterColumn]) -> Result<(), XlsxError> {
+    workbook(rosters, columns)?.save(path)
+}
+
+pub fn xlsx_bytes(rosters: &BTreeMap<String, Vec<RosterEntry>>, columns: &[RosterColumn]) -> Result<Vec<u8>, XlsxError> {
+    workbook(rosters, columns)?.save_to_buffer()
+}
+
+// A page per team, styled to print cleanly from a browser (or to PDF)
+pub fn write_html<W: Write>(mut writer: W, rosters: &BTreeMap<String, Vec<RosterEntry>>, columns: &[RosterColumn], title: &str) -> io::Result<()> {
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html><head><meta charset=\"utf-8\"><title>{}</title>", escape_html(title))?;
+    writeln!(
+        writer,
+        "<style>body{{font-family:sans-serif}}table{{border-collapse:collapse;width:100%}}\
+         th,td{{border:1px solid #999;padding:4px;text-align:left}}section{{page-break-after:always}}\
+         .unpaid{{background:#fdd}}</style>"
+    )?;
+    writeln!(writer, "</head><body>")?;
+    for (team, entries) in rosters {
+        writeln!(writer, "<section>")?;
+        writeln!(writer, "<h1>{}</h1>", escape_html(if team.is_empty() { "No team" } else { team }))?;
+        writeln!(writer, "<p>{} &middot; {} players</p>", escape_html(title), entries.len())?;
+        let headings: Vec<String> = columns.iter().map(|column| format!("<th>{}</th>", column.heading())).collect();
+        writeln!(writer, "<table><tr>{}</tr>", headings.concat())?;
+        for entry in entries {
+            let class = if entry.paid_up == Some(false) { " class=\"unpaid\"" } else { "" };
+            let cells: Vec<String> = columns.iter().map(|column| format!("<td>{}</td>", escape_html(&entry.value(*column)))).collect();
+            writeln!(writer, "<tr{}>{}</tr>", class, cells.concat())?;
+        }
+        writeln!(writer, "</table></section>")?;
+    }
+    writeln!(writer, "</body></html>")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod roster_tests {
+    use super::*;
+    use crate::dates::parse_date;
+    use crate::fixtures;
+
+    fn player(first_names: &str, team: &str) -> Wholegame {
+        let mut player = fixtures::player(first_names, team);
+        player.set_photo_uploaded_date(Some("2023-09-01".to_string()));
+        player.set_emergency_contact(Some("Jo Doe".to_string()));
+        player.set_emergency_contact_phone_number(Some("07700 900000".to_string()));
+        player
+    }
+
+    fn invoice(name: &str, outstanding: f64) -> LoveAdmin {
+        let mut invoice = fixtures::invoice(name, "", "", 20.0);
+        invoice.set_outstanding(outstanding);
+        invoice
+    }
+
+    fn rosters() -> BTreeMap<String, Vec<RosterEntry>> {
+        let mut no_photo = player("Cal", "U12 Reds");
+        no_photo.set_photo_uploaded_date(None);
+        let players = vec![player("Ben", "U12 Reds"), player("Amy", "U12 Reds"), no_photo, player("Dan", "U14 <Blues>")];
+        let invoices = vec![invoice("Amy Doe", 0.0), invoice("Ben Doe", 20.0)];
+        build_rosters(&players, &invoices, parse_date("2023-10-01").unwrap(), 3)
+    }
+
+    #[test]
+    fn test_rosters_grouped_with_paid_up_flag() {
+        let rosters = rosters();
+        let reds: Vec<(String, String, String)> = rosters["U12 Reds"]
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.value(RosterColumn::PaidUp), entry.photo_status.clone()))
+            .collect();
+        assert_eq!(
+            reds,
+            vec![
+                ("Amy Doe".to_string(), "Yes".to_string(), "OK".to_string()),
+                ("Ben Doe".to_string(), "No".to_string(), "OK".to_string()),
+                ("Cal Doe".to_string(), "Not invoiced".to_string(), "Missing".to_string()),
+            ]
+        );
+        assert_eq!(rosters["U12 Reds"][0].emergency_contact, "Jo Doe (07700 900000)");
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        assert_eq!(RosterColumn::parse_list("name, fan-id,Paid Up"), Ok(vec![RosterColumn::Name, RosterColumn::FanId, RosterColumn::PaidUp]));
+        assert!(RosterColumn::parse_list("name,shoe_size").is_err());
+    }
+
+    #[test]
+    fn test_csv_and_html_output() {
+        let rosters = rosters();
+        let columns = [RosterColumn::Name, RosterColumn::PaidUp];
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &rosters, &columns).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("Team,Name,Paid up\nU12 Reds,Amy Doe,Yes\n"));
+        assert_eq!(csv.lines().count(), 5);
+
+        let mut html = Vec::new();
+        write_html(&mut html, &rosters, &columns, "Rosters").unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert_eq!(html.matches("<section>").count(), 2);
+        assert!(html.contains("<h1>U14 &lt;Blues&gt;</h1>"));
+        assert!(html.contains("<tr class=\"unpaid\"><td>Ben Doe</td><td>No</td></tr>"));
+    }
+
+    #[test]
+    fn test_xlsx_output() {
+        let path = std::env::temp_dir().join(format!("roster-{}.xlsx", uuid::Uuid::new_v4()));
+        write_xlsx(&path, &rosters(), &DEFAULT_COLUMNS).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(bytes.starts_with(b"PK"));
+        assert_eq!(sheet_name("U14 <Blues>/Whites: Sunday league squad", &mut HashSet::new()), "U14 <Blues>Whites Sunday league");
+    }
+
+    #[test]
+    fn test_sheet_names_are_unique() {
+        let mut used = HashSet::new();
+        assert_eq!(sheet_name("Wilpshire Wanderers Juniors U12 Blues", &mut used), "Wilpshire Wanderers Juniors U12");
+        assert_eq!(sheet_name("Wilpshire Wanderers Juniors U12 Reds", &mut used), "Wilpshire Wanderers Juniors (2)");
+        assert_eq!(sheet_name("wilpshire wanderers juniors u12 whites", &mut used), "wilpshire wanderers juniors (3)");
+        assert_eq!(sheet_name("", &mut used), "No team");
+        assert_eq!(sheet_name("No Team", &mut used), "No Team (2)");
+
+        let mut rosters = BTreeMap::new();
+        for team in ["Wilpshire Wanderers Juniors U12 Reds", "Wilpshire Wanderers Juniors U12 Blues"] {
+            rosters.insert(team.to_string(), Vec::new());
+        }
+        assert!(xlsx_bytes(&rosters, &DEFAULT_COLUMNS).unwrap().starts_with(b"PK"));
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use chrono::NaiveDate;
+use rust_xlsxwriter::{Workbook, XlsxError};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::age_groups::SeasonConfig;
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::dates::next_month;
+use crate::export_schema::{LOVEADMIN_2023, WHOLEGAME_2023};
+use crate::import::WHOLEGAME_PREAMBLE_ROWS;
+use crate::matching::{full_name, normalise_name};
+use crate::reconciliation::{Exception, ExceptionKind};
+use crate::redact::{FIRST_NAMES, SURNAMES};
+
+const TEAM_COLOURS: &[&str] = &["Reds", "Blues", "Whites", "Blacks", "Greens", "Ambers"];
+const YOUNGEST_AGE_GROUP: u32 = 7;
+const AGE_GROUPS: usize = 10;
+// Team names repeat after this many
+pub const MAX_TEAMS: usize = AGE_GROUPS * TEAM_COLOURS.len();
+const MONTHLY_FEE: f64 = 25.0;
+const CAMP_PRODUCT: &str = "Holiday Camp";
+const CAMP_FEE: f64 = 40.0;
+const FAN_ID_BASE: u64 = 200_000_000;
+
+// What to generate. Rates are the share of players affected, from 0 to 1.
+pub struct SyntheticOptions {
+    pub seed: String,
+    pub club: String,
+    pub teams: usize,
+    pub players_per_team: usize,
+    pub months: u32,
+    // First invoice month
+    pub start: (i32, u32),
+    // Subscriptions that stop before the last month
+    pub lapse_rate: f64,
+    // Players whose LoveAdmin name is misspelt
+    pub typo_rate: f64,
+    pub suspended_rate: f64,
+}
+
+// The exports as generated, with the reconciliation exceptions they should produce
+pub struct SyntheticData {
+    pub players: Vec<Wholegame>,
+    pub invoices: Vec<LoveAdmin>,
+    pub expected: Vec<Exception>,
+    pub suspended: Vec<String>,
+}
+
+impl Default for SyntheticOptions {
+    fn default() -> Self {
+        SyntheticOptions {
+            seed: "synthetic".to_string(),
+            club: "Wanderers".to_string(),
+            teams: 4,
+            players_per_team: 15,
+            months: 6,
+            start: (2023, 9),
+            lapse_rate: 0.1,
+            typo_rate: 0.05,
+            suspended_rate: 0.03,
+        }
+    }
+}
+
+// Splitmix64 seeded from a hash of the seed text, so the same seed always
+// gives the same files without pulling in a random number crate
+struct Random(u64);
+
+impl Random {
+    fn new(seed: &str) -> Self {
+        let digest = Sha256::digest(seed.as_bytes());
+        Random(u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes")))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound.max(1) as u64) as usize
+    }
+
+    fn chance(&mut self, rate: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+
+    fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+        values[self.below(values.len())]
+    }
+}
+
+// What happens to one player's billing; at most one per player so the
+// expected exceptions stay simple to state
+#[derive(Clone, Copy, PartialEq)]
+enum Fault {
+    None,
+    // Last invoiced in this month (an index from the start month)
+    Lapsed(u32),
+    Typo,
+}
+
+fn month_at(start: (i32, u32), offset: u32) -> (i32, u32) {
+    (0..offset).fold(start, |month, _| next_month(month))
+}
+
+fn iso(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+// Wholegame writes dates the UK way
+fn uk(date: NaiveDate) -> String {
+    date.format("%d/%m/%Y").to_string()
+}
+
+fn first_of((year, month): (i32, u32)) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, 1).expect("months are 1-12")
+}
+
+// Swaps two neighbouring letters of the surname, or doubles one if they're
+// the same, so the result never matches the real name
+fn misspell(name: &str, random: &mut Random) -> String {
+    let (first, surname) = name.rsplit_once(' ').unwrap_or(("", name));
+    let mut letters: Vec<char> = surname.chars().collect();
+    let at = 1 + random.below(letters.len().saturating_sub(2));
+    if letters.get(at) != letters.get(at + 1) && at + 1 < letters.len() {
+        letters.swap(at, at + 1);
+    } else {
+        letters.insert(at, letters[at]);
+    }
+    format!("{} {}", first, letters.into_iter().collect::<String>()).trim().to_string()
+}
+
+fn invoice(name: &str, owner: &str, product: &str, date: NaiveDate, amount: f64, random: &mut Random) -> LoveAdmin {
+    let mut invoice = LoveAdmin::new();
+    invoice.set_name(name.to_string());
+    invoice.set_account_owner(owner.to_string());
+    invoice.set_product(product.to_string());
+    invoice.set_date(iso(date));
+    invoice.set_invoiced(amount);
+    // Most pay by direct debit; a few are pending or have failed
+    match random.below(20) {
+        0 => {
+            invoice.set_outstanding(amount);
+            invoice.set_failed(1);
+            invoice.set_days_overdue(1 + random.below(30) as i32);
+            invoice.set_last_reminder_sent(iso(date + chrono::Days::new(7)));
+        }
+        1 => invoice.set_pending(amount),
+        _ => invoice.set_paid(amount),
+    }
+    invoice
+}
+
+pub fn generate(options: &SyntheticOptions) -> SyntheticData {
+    let mut random = Random::new(&options.seed);
+    let season = SeasonConfig::containing(first_of(options.start));
+    let last_month = month_at(options.start, options.months.saturating_sub(1));
+    let mut used_names = HashSet::new();
+    let mut data = SyntheticData { players: Vec::new(), invoices: Vec::new(), expected: Vec::new(), suspended: Vec::new() };
+
+    for team_index in 0..options.teams {
+        let age = YOUNGEST_AGE_GROUP + (team_index % AGE_GROUPS) as u32;
+        let colour = TEAM_COLOURS[team_index / AGE_GROUPS % TEAM_COLOURS.len()];
+        let loveadmin_team = format!("U{} {}", age, colour);
+        let wholegame_team = format!("{} {}", options.club, loveadmin_team);
+        let product = format!("{} (*)", loveadmin_team);
+
+        for _ in 0..options.players_per_team {
+            let surname = random.pick(SURNAMES);
+            // Falls back to a middle name once single first names run short
+            let first_names = (0..)
+                .map(|attempt| {
+                    let first = random.pick(FIRST_NAMES);
+                    if attempt < 20 { first.to_string() } else { format!("{} {}", first, random.pick(FIRST_NAMES)) }
+                })
+                .find(|first| used_names.insert(normalise_name(&format!("{} {}", first, surname))))
+                .expect("names run out eventually");
+            let fan_id = (FAN_ID_BASE + data.players.len() as u64 + 1).to_string();
+            // Old enough for the age group on the cut-off date but no older
+            let earliest = season.cut_off_date() - chrono::Months::new(12 * age) + chrono::Days::new(1);
+            let born = earliest + chrono::Days::new(random.below(365) as u64);
+            let parent = format!("{} {}", random.pick(FIRST_NAMES), surname);
+            let parent_email = format!("{}@example.com", normalise_name(&parent).replace(' ', "."));
+            let registered = first_of(options.start) - chrono::Days::new(random.below(60) as u64 + 1);
+
+            let mut player = Wholegame::new();
+            player.set_first_names(first_names);
+            player.set_surname(surname.to_string());
+            player.set_fan_id(fan_id.clone());
+            player.set_date_of_birth(uk(born));
+            player.set_age_group(format!("U{}", age));
+            player.set_gender(random.pick(&["Male", "Female"]).to_string());
+            player.set_team(wholegame_team.clone());
+            player.set_date_submitted(uk(registered - chrono::Days::new(2)));
+            player.set_date_registered(Some(uk(registered)));
+            player.set_registration_expiry(Some(uk(season.cut_off_date() + chrono::Days::new(366))));
+            player.set_registration_status("Registered".to_string());
+            player.set_email_address(parent_email.clone());
+            player.set_parent_carer_name(Some(parent.clone()));
+            player.set_parent_carer_email_address(Some(parent_email));
+            player.set_emergency_contact(Some(parent.clone()));
+            player.set_emergency_contact_phone_number(Some(format!("07700 9{:05}", random.below(100_000))));
+            player.set_consent_given(true);
+            player.set_contract_status("Non-Contract".to_string());
+            player.set_photo_uploaded_date(Some(uk(registered)));
+            if random.chance(options.suspended_rate) {
+                player.set_suspended(true);
+                data.suspended.push(fan_id.clone());
+            }
+
+            let name = full_name(&player);
+            let fault = if options.months >= 2 && random.chance(options.lapse_rate) {
+                Fault::Lapsed(random.below(options.months as usize - 1) as u32)
+            } else if random.chance(options.typo_rate) {
+                Fault::Typo
+            } else {
+                Fault::None
+            };
+            let (fault, billed_name) = match fault {
+                Fault::Typo => {
+                    // A misspelling that happens to be someone else's name isn't a typo we can predict
+                    let misspelt = misspell(&name, &mut random);
+                    if used_names.insert(normalise_name(&misspelt)) { (fault, misspelt) } else { (Fault::None, name.clone()) }
+                }
+                _ => (fault, name.clone()),
+            };
+            let last_billed = match fault {
+                Fault::Lapsed(month) => month,
+                _ => options.months.saturating_sub(1),
+            };
+            for month in 0..=last_billed.min(options.months.saturating_sub(1)) {
+                let date = first_of(month_at(options.start, month));
+                data.invoices.push(invoice(&billed_name, &parent, &product, date, MONTHLY_FEE, &mut random));
+            }
+            // Non-team products are ignored by reconciliation
+            if fault == Fault::None && options.months > 0 && random.chance(0.1) {
+                let date = first_of(month_at(options.start, random.below(options.months as usize) as u32)) + chrono::Days::new(14);
+                data.invoices.push(invoice(&billed_name, &parent, CAMP_PRODUCT, date, CAMP_FEE, &mut random));
+            }
+
+            match fault {
+                Fault::Typo => {
+                    data.expected.push(Exception {
+                        kind: ExceptionKind::UnregisteredPayer,
+                        name: billed_name,
+                        team: loveadmin_team.clone(),
+                        fan_id: None,
+                        detail: "no Wholegame player with this name".to_string(),
+                        month: None,
+                    });
+                    data.expected.push(Exception {
+                        kind: ExceptionKind::UnbilledPlayer,
+                        name,
+                        team: wholegame_team.clone(),
+                        fan_id: Some(fan_id),
+                        detail: "never invoiced in LoveAdmin".to_string(),
+                        month: None,
+                    });
+                }
+                Fault::Lapsed(month) if month + 2 == options.months => {
+                    let previous = month_at(options.start, month);
+                    data.expected.push(Exception {
+                        kind: ExceptionKind::LapsedInvoice,
+                        name,
+                        team: loveadmin_team.clone(),
+                        fan_id: Some(fan_id),
+                        detail: format!("invoiced {}-{:02} but not {}-{:02}", previous.0, previous.1, last_month.0, last_month.1),
+                        month: Some(last_month),
+                    });
+                }
+                _ => {}
+            }
+            data.players.push(player);
+        }
+    }
+
+    // Exports list invoices by date rather than by player
+    data.invoices.sort_by(|a, b| a.get_date().cmp(b.get_date()));
+    data.expected.sort_by_key(|exception| (exception.kind, exception.team.to_lowercase(), exception.name.to_lowercase()));
+    data
+}
+
+pub fn write_loveadmin_csv<W: Write>(writer: W, invoices: &[LoveAdmin]) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(LOVEADMIN_2023.columns).map_err(io::Error::other)?;
+    let money = |amount: f64| format!("{:.2}", amount);
+    for invoice in invoices {
+        csv.write_record([
+            invoice.get_name().as_str(),
+            invoice.get_account_owner(),
+            invoice.get_product(),
+            invoice.get_date(),
+            &money(invoice.get_invoiced()),
+            &money(invoice.get_paid()),
+            &money(invoice.get_pending()),
+            &money(invoice.get_outstanding()),
+            &invoice.get_failed().to_string(),
+            &invoice.get_days_overdue().to_string(),
+            invoice.get_last_reminder_sent(),
+        ])
+        .map_err(io::Error::other)?;
+    }
+    csv.flush()
+}
+
+// Laid out like a Wholegame download: report metadata, then the header row
+pub fn wholegame_xlsx_bytes(players: &[Wholegame], club: &str, generated: NaiveDate) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.write_string(0, 0, "Player Registrations")?;
+    sheet.write_string(1, 0, format!("Club: {}", club))?;
+    sheet.write_string(2, 0, "Season: All")?;
+    sheet.write_string(3, 0, format!("Report generated: {}", uk(generated)))?;
+    sheet.write_string(4, 0, "Generated test data - not real people")?;
+    let header_row = WHOLEGAME_PREAMBLE_ROWS as u32;
+    for (col, heading) in WHOLEGAME_2023.columns.iter().enumerate() {
+        sheet.write_string(header_row, col as u16, *heading)?;
+    }
+    let yes_no = |value: bool| if value { "Yes" } else { "No" };
+    for (index, player) in players.iter().enumerate() {
+        let row = header_row + 1 + index as u32;
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let cells = [
+            player.get_first_names().clone(),
+            player.get_surname().clone(),
+            String::new(),
+            player.get_date_of_birth().clone(),
+            player.get_age_group().clone(),
+            player.get_gender().clone(),
+            yes_no(player.is_suspended()).to_string(),
+            player.get_team().clone(),
+            player.get_date_submitted().clone(),
+            optional(player.get_date_registered()),
+            optional(player.get_registration_expiry()),
+            player.get_registration_status().clone(),
+            player.get_email_address().clone(),
+            optional(player.get_parent_carer_name()),
+            optional(player.get_parent_carer_email_address()),
+            optional(player.get_emergency_contact()),
+            optional(player.get_emergency_contact_phone_number()),
+            optional(player.get_other_clubs()),
+            yes_no(player.is_consent_given()).to_string(),
+            player.get_contract_status().clone(),
+            optional(player.get_photo_uploaded_date()),
+        ];
+        for (col, value) in cells.iter().enumerate() {
+            if !value.is_empty() {
+                sheet.write_string(row, col as u16, value)?;
+            }
+        }
+        // FAN IDs are numbers in the real export
+        match player.get_fan_id().parse::<f64>() {
+            Ok(number) => sheet.write_number(row, 2, number)?,
+            Err(_) => sheet.write_string(row, 2, player.get_fan_id())?,
+        };
+    }
+    workbook.save_to_buffer()
+}
+
+// The known answers a reconciliation of the generated files should give
+pub fn expected_json(options: &SyntheticOptions, data: &SyntheticData) -> Value {
+    let last_month = month_at(options.start, options.months.saturating_sub(1));
+    json!({
+        "seed": options.seed,
+        "players": data.players.len(),
+        "invoices": data.invoices.len(),
+        "latest_month": format!("{:04}-{:02}", last_month.0, last_month.1),
+        "exceptions": data.expected.iter().map(Exception::to_json).collect::<Vec<Value>>(),
+        "suspended": data.suspended,
+    })
+}
+
+// The reference date the generated data is meant to be checked on: the end
+// of the last invoice month
+pub fn reference_date(options: &SyntheticOptions) -> NaiveDate {
+    first_of(month_at(options.start, options.months)).pred_opt().unwrap_or_else(|| first_of(options.start))
+}
+
+#[cfg(test)]
+mod synthetic_tests {
+    use super::*;
+    use crate::import::{loveadmin_rows, read_csv, read_xlsx, wholegame_rows};
+    use crate::reconciliation::reconcile;
+    use std::io::Cursor;
+
+    fn options(seed: &str) -> SyntheticOptions {
+        SyntheticOptions { seed: seed.to_string(), teams: 6, players_per_team: 20, lapse_rate: 0.2, typo_rate: 0.1, ..SyntheticOptions::default() }
+    }
+
+    #[test]
+    fn test_generated_files_reconcile_to_known_answers() {
+        let options = options("known answers");
+        let data = generate(&options);
+        assert_eq!(data.players.len(), 120);
+        assert!(data.expected.iter().any(|exception| exception.kind == ExceptionKind::LapsedInvoice));
+        assert!(data.expected.iter().any(|exception| exception.kind == ExceptionKind::UnregisteredPayer));
+
+        let mut csv = Vec::new();
+        write_loveadmin_csv(&mut csv, &data.invoices).unwrap();
+        let xlsx = wholegame_xlsx_bytes(&data.players, &options.club, reference_date(&options)).unwrap();
+        let invoices = loveadmin_rows(&read_csv(csv.as_slice()).unwrap()).unwrap();
+        let players = wholegame_rows(&read_xlsx(Cursor::new(xlsx), "FAN ID").unwrap()).unwrap();
+        assert_eq!(players[0].get_fan_id(), data.players[0].get_fan_id());
+        assert_eq!(players.iter().filter(|player| player.is_suspended()).count(), data.suspended.len());
+
+        let found: Vec<Value> = reconcile(&players, &invoices).iter().map(Exception::to_json).collect();
+        let expected: Vec<Value> = data.expected.iter().map(Exception::to_json).collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_same_seed_same_data() {
+        let first = generate(&options("a"));
+        let again = generate(&options("a"));
+        let other = generate(&options("b"));
+        let names = |data: &SyntheticData| data.invoices.iter().map(|invoice| invoice.get_name().clone()).collect::<Vec<String>>();
+        assert_eq!(names(&first), names(&again));
+        assert_ne!(names(&first), names(&other));
+        assert_eq!(misspell("Amy Hall", &mut Random::new("x")), misspell("Amy Hall", &mut Random::new("x")));
+        assert!(!crate::matching::names_match(&misspell("Amy Hall", &mut Random::new("x")), "Amy Hall"));
+    }
+}
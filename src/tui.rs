@@ -0,0 +1,500 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+
+use chrono::NaiveDate;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Tabs, Wrap};
+use ratatui::Frame;
+use rusqlite::Connection;
+
+use crate::compliance::{self, PlayerCompliance};
+use crate::data_structures::{LoveAdmin, Wholegame};
+use crate::database::{get_all_loveadmin, get_all_wholegame};
+use crate::matching::{full_name, invoices_for_player, names_match, team_from_product, teams_match};
+use crate::reconciliation::{self, ExceptionStatus, TrackedException, WorkflowChange};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tab {
+    Players,
+    Invoices,
+    Exceptions,
+    Compliance,
+}
+
+const TABS: [Tab; 4] = [Tab::Players, Tab::Invoices, Tab::Exceptions, Tab::Compliance];
+
+const HELP: &str = "Tab/1-4 switch  ↑↓ move  / search  t team  r resolve/reopen  a show closed  q quit";
+
+impl Tab {
+    fn title(&self) -> &'static str {
+        match self {
+            Tab::Players => "Players",
+            Tab::Invoices => "Invoices",
+            Tab::Exceptions => "Reconciliation exceptions",
+            Tab::Compliance => "Compliance",
+        }
+    }
+
+    fn headings(&self) -> &'static [&'static str] {
+        match self {
+            Tab::Players => &["Name", "FAN ID", "Team", "Age group", "Status"],
+            Tab::Invoices => &["Name", "Product", "Date", "Invoiced", "Paid", "Outstanding"],
+            Tab::Exceptions => &["Exception", "Name", "Team", "Detail", "Status", "Assignee"],
+            Tab::Compliance => &["RAG", "Name", "Team", "Issues"],
+        }
+    }
+}
+
+// Everything the terminal UI shows, loaded once from the database. Filtering
+// and the detail pane work on these in memory; only resolving an exception
+// writes back. Loading runs reconciliation, so new exceptions are tracked.
+pub struct App {
+    players: Vec<Wholegame>,
+    invoices: Vec<LoveAdmin>,
+    exceptions: Vec<TrackedException>,
+    compliance: Vec<PlayerCompliance>,
+    reference_date: NaiveDate,
+    teams: Vec<String>,
+    tab: Tab,
+    selected: usize,
+    search: String,
+    searching: bool,
+    team_filter: Option<usize>,
+    show_closed: bool,
+    status: String,
+}
+
+impl App {
+    pub fn load(conn: &Connection, reference_date: NaiveDate, photo_max_years: u32) -> rusqlite::Result<Self> {
+        let players = get_all_wholegame(conn)?;
+        let invoices = get_all_loveadmin(conn)?;
+        let exceptions = reconciliation::refresh(conn, reference_date, photo_max_years)?;
+        let compliance = compliance::check_all(&players, &invoices, reference_date, photo_max_years)
+            .into_iter()
+            .filter(|result| !result.issues.is_empty())
+            .collect();
+        let teams: BTreeSet<String> = players.iter().map(|player| player.get_team().trim().to_string()).filter(|team| !team.is_empty()).collect();
+        Ok(App {
+            players,
+            invoices,
+            exceptions,
+            compliance,
+            reference_date,
+            teams: teams.into_iter().collect(),
+            tab: Tab::Players,
+            selected: 0,
+            search: String::new(),
+            searching: false,
+            team_filter: None,
+            show_closed: false,
+            status: HELP.to_string(),
+        })
+    }
+
+    fn team(&self) -> Option<&str> {
+        self.team_filter.map(|index| self.teams[index].as_str())
+    }
+
+    // The filter team is a Wholegame team; LoveAdmin teams drop the club name
+    fn in_team(&self, team: &str) -> bool {
+        match self.team() {
+            Some(filter) => names_match(filter, team) || teams_match(filter, team),
+            None => true,
+        }
+    }
+
+    fn row(&self, index: usize) -> Vec<String> {
+        match self.tab {
+            Tab::Players => {
+                let player = &self.players[index];
+                vec![
+                    full_name(player),
+                    player.get_fan_id().clone(),
+                    player.get_team().clone(),
+                    player.get_age_group().clone(),
+                    player.get_registration_status().clone(),
+                ]
+            }
+            Tab::Invoices => {
+                let invoice = &self.invoices[index];
+                vec![
+                    invoice.get_name().clone(),
+                    invoice.get_product().clone(),
+                    invoice.get_date().clone(),
+                    format!("{:.2}", invoice.get_invoiced()),
+                    format!("{:.2}", invoice.get_paid()),
+                    format!("{:.2}", invoice.get_outstanding()),
+                ]
+            }
+            Tab::Exceptions => {
+                let tracked = &self.exceptions[index];
+                let exception = &tracked.exception;
+                vec![
+                    exception.kind.to_string(),
+                    exception.name.clone(),
+                    exception.team.clone(),
+                    exception.detail.clone(),
+                    tracked.status_label(),
+                    tracked.assignee.clone().unwrap_or_default(),
+                ]
+            }
+            Tab::Compliance => {
+                let result = &self.compliance[index];
+                let issues: Vec<String> = result.issues.iter().map(|issue| issue.to_string()).collect();
+                vec![result.rag().to_string(), result.name.clone(), result.team.clone(), issues.join("; ")]
+            }
+        }
+    }
+
+    fn row_team(&self, index: usize) -> String {
+        match self.tab {
+            Tab::Players => self.players[index].get_team().clone(),
+            Tab::Invoices => team_from_product(self.invoices[index].get_product()).unwrap_or_default(),
+            Tab::Exceptions => self.exceptions[index].exception.team.clone(),
+            Tab::Compliance => self.compliance[index].team.clone(),
+        }
+    }
+
+    // Indices of the current tab's rows that pass the team filter and search
+    fn visible(&self) -> Vec<usize> {
+        let count = match self.tab {
+            Tab::Players => self.players.len(),
+            Tab::Invoices => self.invoices.len(),
+            Tab::Exceptions => self.exceptions.len(),
+            Tab::Compliance => self.compliance.len(),
+        };
+        let search = self.search.to_lowercase();
+        (0..count)
+            .filter(|index| self.team().is_none() || self.in_team(&self.row_team(*index)))
+            .filter(|index| self.tab != Tab::Exceptions || self.show_closed || self.exceptions[*index].status.is_active(self.reference_date))
+            .filter(|index| search.is_empty() || self.row(*index).iter().any(|cell| cell.to_lowercase().contains(&search)))
+            .collect()
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        self.visible().get(self.selected).copied()
+    }
+
+    fn player_by_fan_id(&self, fan_id: &str) -> Option<&Wholegame> {
+        self.players.iter().find(|player| player.get_fan_id() == fan_id)
+    }
+
+    fn invoice_lines(&self, player: &Wholegame) -> Vec<String> {
+        let invoices = invoices_for_player(player, &self.invoices);
+        let mut lines = vec![String::new(), format!("Invoices ({})", invoices.len())];
+        for invoice in invoices {
+            lines.push(format!(
+                "  {}  {}  invoiced {:.2}  paid {:.2}  outstanding {:.2}",
+                invoice.get_date(),
+                invoice.get_product(),
+                invoice.get_invoiced(),
+                invoice.get_paid(),
+                invoice.get_outstanding()
+            ));
+        }
+        lines
+    }
+
+    fn player_lines(&self, player: &Wholegame) -> Vec<String> {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let mut lines = vec![
+            full_name(player),
+            format!("FAN ID: {}", player.get_fan_id()),
+            format!("Team: {}", player.get_team()),
+            format!("Date of birth: {}  Age group: {}", player.get_date_of_birth(), player.get_age_group()),
+            format!("Registration: {} (expires {})", player.get_registration_status(), optional(player.get_registration_expiry())),
+            format!("Suspended: {}  Consent: {}", player.is_suspended(), player.is_consent_given()),
+            format!("Email: {}", player.get_email_address()),
+            format!("Parent/carer: {} {}", optional(player.get_parent_carer_name()), optional(player.get_parent_carer_email_address())),
+            format!("Emergency contact: {} {}", optional(player.get_emergency_contact()), optional(player.get_emergency_contact_phone_number())),
+        ];
+        lines.extend(self.invoice_lines(player));
+        lines
+    }
+
+    fn detail(&self) -> Vec<String> {
+        let index = match self.selected_index() {
+            Some(index) => index,
+            None => return vec!["Nothing selected".to_string()],
+        };
+        match self.tab {
+            Tab::Players => self.player_lines(&self.players[index]),
+            Tab::Invoices => {
+                let invoice = &self.invoices[index];
+                let mut lines = vec![
+                    invoice.get_name().clone(),
+                    format!("Account owner: {}", invoice.get_account_owner()),
+                    format!("Product: {}", invoice.get_product()),
+                    format!("Date: {}", invoice.get_date()),
+                    format!("Invoiced {:.2}  Paid {:.2}  Pending {:.2}", invoice.get_invoiced(), invoice.get_paid(), invoice.get_pending()),
+                    format!("Outstanding {:.2}  Failed {}  Days overdue {}", invoice.get_outstanding(), invoice.get_failed(), invoice.get_days_overdue()),
+                    String::new(),
+                ];
+                let matched: Vec<&Wholegame> = self.players.iter().filter(|player| names_match(&full_name(player), invoice.get_name())).collect();
+                if matched.is_empty() {
+                    lines.push("No matching Wholegame player".to_string());
+                }
+                for player in matched {
+                    lines.push(format!("Wholegame: {} ({}, {})", full_name(player), player.get_fan_id(), player.get_team()));
+                }
+                lines
+            }
+            Tab::Exceptions => {
+                let tracked = &self.exceptions[index];
+                let exception = &tracked.exception;
+                let mut lines = vec![
+                    format!("{} (#{})", exception.kind, tracked.id),
+                    format!("Name: {}", exception.name),
+                    format!("Team: {}", exception.team),
+                    format!("Detail: {}", exception.detail),
+                    format!("Status: {}", tracked.status_label()),
+                    format!("Assignee: {}  First seen: {}", tracked.assignee.as_deref().unwrap_or("nobody"), tracked.first_seen),
+                ];
+                lines.extend(tracked.notes.iter().flat_map(|notes| notes.lines()).map(|line| format!("  {}", line)));
+                lines.push(String::new());
+                match exception.fan_id.as_deref().and_then(|fan_id| self.player_by_fan_id(fan_id)) {
+                    Some(player) => lines.extend(self.player_lines(player)),
+                    None => {
+                        let invoices: Vec<&LoveAdmin> = self.invoices.iter().filter(|invoice| names_match(invoice.get_name(), &exception.name)).collect();
+                        lines.push(format!("Invoices ({})", invoices.len()));
+                        for invoice in invoices {
+                            lines.push(format!("  {}  {}  outstanding {:.2}", invoice.get_date(), invoice.get_product(), invoice.get_outstanding()));
+                        }
+                    }
+                }
+                lines
+            }
+            Tab::Compliance => {
+                let result = &self.compliance[index];
+                let mut lines = vec![format!("{} ({})", result.name, result.rag())];
+                lines.extend(result.issues.iter().map(|issue| format!("  {}", issue)));
+                lines.push(String::new());
+                if let Some(player) = self.player_by_fan_id(&result.fan_id) {
+                    lines.extend(self.player_lines(player));
+                }
+                lines
+            }
+        }
+    }
+
+    fn toggle_resolved(&mut self, conn: &Connection) -> rusqlite::Result<()> {
+        if self.tab != Tab::Exceptions {
+            return Ok(());
+        }
+        let tracked = match self.selected_index() {
+            Some(index) => &mut self.exceptions[index],
+            None => return Ok(()),
+        };
+        let (status, verb) = if tracked.status.is_active(self.reference_date) {
+            (ExceptionStatus::Resolved, "Resolved")
+        } else {
+            (ExceptionStatus::Open, "Reopened")
+        };
+        let change = WorkflowChange { status: Some(status), ..WorkflowChange::default() };
+        self.status = match reconciliation::update_workflow(conn, tracked.id, &change) {
+            Ok(_) => {
+                tracked.status = status;
+                format!("{} {}", verb, tracked.exception.key())
+            }
+            Err(e) => e,
+        };
+        self.clamp_selection();
+        Ok(())
+    }
+
+    fn clamp_selection(&mut self) {
+        self.selected = self.selected.min(self.visible().len().saturating_sub(1));
+    }
+
+    fn switch_tab(&mut self, tab: Tab) {
+        self.tab = tab;
+        self.selected = 0;
+    }
+
+    // Returns false once the user has asked to quit
+    pub fn handle_key(&mut self, key: KeyEvent, conn: &Connection) -> rusqlite::Result<bool> {
+        if self.searching {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc => self.searching = false,
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => {}
+            }
+            self.clamp_selection();
+            return Ok(true);
+        }
+        let position = TABS.iter().position(|tab| *tab == self.tab).unwrap_or(0);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+            KeyCode::Tab | KeyCode::Right => self.switch_tab(TABS[(position + 1) % TABS.len()]),
+            KeyCode::BackTab | KeyCode::Left => self.switch_tab(TABS[(position + TABS.len() - 1) % TABS.len()]),
+            KeyCode::Char(c @ '1'..='4') => self.switch_tab(TABS[c as usize - '1' as usize]),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected += 1;
+                self.clamp_selection();
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('/') => {
+                self.searching = true;
+                self.search.clear();
+            }
+            KeyCode::Char('t') => {
+                self.team_filter = match self.team_filter {
+                    None if !self.teams.is_empty() => Some(0),
+                    Some(index) if index + 1 < self.teams.len() => Some(index + 1),
+                    _ => None,
+                };
+                self.clamp_selection();
+            }
+            KeyCode::Char('a') => {
+                self.show_closed = !self.show_closed;
+                self.clamp_selection();
+            }
+            KeyCode::Char('r') => self.toggle_resolved(conn)?,
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [tabs_area, body_area, status_area] = Layout::vertical([Constraint::Length(3), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [table_area, detail_area] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(body_area);
+
+        let position = TABS.iter().position(|tab| *tab == self.tab).unwrap_or(0);
+        let tabs = Tabs::new(TABS.iter().enumerate().map(|(index, tab)| format!("{} {}", index + 1, tab.title())))
+            .select(position)
+            .highlight_style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED))
+            .block(Block::bordered().title("LoveAdmin tool"));
+        frame.render_widget(tabs, tabs_area);
+
+        let visible = self.visible();
+        let mut title = format!("{} ({})", self.tab.title(), visible.len());
+        if let Some(team) = self.team() {
+            title.push_str(&format!("  team: {}", team));
+        }
+        if !self.search.is_empty() || self.searching {
+            title.push_str(&format!("  search: {}{}", self.search, if self.searching { "_" } else { "" }));
+        }
+        let headings = self.tab.headings();
+        let rows: Vec<Row> = visible.iter().map(|index| Row::new(self.row(*index))).collect();
+        let table = Table::new(rows, vec![Constraint::Fill(1); headings.len()])
+            .header(Row::new(headings.to_vec()).style(Style::default().add_modifier(Modifier::BOLD)))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .block(Block::bordered().title(title));
+        let mut state = TableState::default().with_selected(if visible.is_empty() { None } else { Some(self.selected) });
+        frame.render_stateful_widget(table, table_area, &mut state);
+
+        let detail: Vec<Line> = self.detail().into_iter().map(Line::from).collect();
+        frame.render_widget(Paragraph::new(detail).wrap(Wrap { trim: false }).block(Block::bordered().title("Detail")), detail_area);
+        frame.render_widget(Paragraph::new(self.status.as_str()), status_area);
+    }
+}
+
+pub fn run(conn: &Connection, mut app: App) -> Result<(), Box<dyn Error>> {
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        loop {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.handle_key(key, conn)? {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tui_tests {
+    use super::*;
+    use crate::database::{create_tables, get_exception_record_by_key, insert_loveadmin, insert_wholegame, setup_database};
+    use crate::dates::parse_date;
+
+    fn database() -> Connection {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        for (first_names, team) in [("Amy", "Wanderers U12 Reds"), ("Ben", "Wanderers U14 Blues")] {
+            let mut player = Wholegame::new();
+            player.set_first_names(first_names.to_string());
+            player.set_surname("Doe".to_string());
+            player.set_fan_id(format!("fan-{}", first_names));
+            player.set_team(team.to_string());
+            insert_wholegame(&conn, &player).unwrap();
+        }
+        for (name, product) in [("Amy Doe", "U12 Reds (*)"), ("Amy Doe", "Summer Camp"), ("Zed Doe", "U14 Blues (*)")] {
+            let mut invoice = LoveAdmin::new();
+            invoice.set_name(name.to_string());
+            invoice.set_product(product.to_string());
+            invoice.set_date("2023-10-01".to_string());
+            invoice.set_invoiced(20.0);
+            insert_loveadmin(&conn, &invoice).unwrap();
+        }
+        conn
+    }
+
+    fn press(app: &mut App, conn: &Connection, keys: &str) {
+        for c in keys.chars() {
+            let code = if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) };
+            assert!(app.handle_key(KeyEvent::from(code), conn).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_search_team_filter_and_player_detail() {
+        let conn = database();
+        let mut app = App::load(&conn, parse_date("2023-10-15").unwrap(), 3).unwrap();
+        assert_eq!(app.visible().len(), 2);
+
+        press(&mut app, &conn, "/amy\n");
+        assert_eq!(app.visible(), vec![0]);
+        let detail = app.detail();
+        assert_eq!(detail[0], "Amy Doe");
+        assert!(detail.contains(&"Invoices (2)".to_string()));
+
+        press(&mut app, &conn, "/\n2t");
+        assert_eq!(app.team(), Some("Wanderers U12 Reds"));
+        assert_eq!(app.visible(), vec![0]);
+        press(&mut app, &conn, "t");
+        assert_eq!(app.visible(), vec![2]);
+
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("Invoices (1)  team: Wanderers U14 Blues"));
+        assert!(screen.contains("Zed Doe"));
+    }
+
+    #[test]
+    fn test_resolving_exception_hides_it_and_persists() {
+        let conn = database();
+        let mut app = App::load(&conn, parse_date("2023-10-15").unwrap(), 3).unwrap();
+        press(&mut app, &conn, "3");
+        let before = app.visible().len();
+        let key = app.exceptions[app.selected_index().unwrap()].exception.key();
+
+        press(&mut app, &conn, "r");
+        assert_eq!(app.visible().len(), before - 1);
+        let status = |conn: &Connection| get_exception_record_by_key(conn, &key).unwrap().unwrap().status;
+        assert_eq!(status(&conn), "resolved");
+
+        press(&mut app, &conn, "a");
+        assert_eq!(app.visible().len(), before);
+        assert_eq!(app.row(0)[4], "resolved (new)");
+        press(&mut app, &conn, "r");
+        assert_eq!(status(&conn), "open");
+
+        // Loading again finds the same exceptions rather than new ones
+        let again = App::load(&conn, parse_date("2023-10-15").unwrap(), 3).unwrap();
+        assert_eq!(again.exceptions.len(), app.exceptions.len());
+        assert!(again.exceptions.iter().all(|tracked| !tracked.is_new));
+        assert!(!app.handle_key(KeyEvent::from(KeyCode::Char('q')), &conn).unwrap());
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::dates::parse_date;
+use crate::import::{parse_amount, parse_whole_number, Sheet, LOVEADMIN_SOURCE, WHOLEGAME_SOURCE};
+use crate::matching::AMOUNT_TOLERANCE;
+
+// FAN IDs are all digits; older ones are shorter than the current nine
+pub const FAN_ID_DIGITS: RangeInclusive<usize> = 8..=10;
+
+const LOVEADMIN_AMOUNTS: &[&str] = &["Invoiced", "Paid", "Pending", "Outstanding"];
+const LOVEADMIN_DATES: &[&str] = &["Last Reminder Sent"];
+const WHOLEGAME_DATES: &[&str] = &["Date of Birth", "Date Submitted", "Date Registered", "Registration Expiry", "Photo Uploaded Date"];
+const WHOLEGAME_EMAILS: &[&str] = &["Email Address", "Parent/Carer Email Address"];
+
+// What to do with a file that has rows failing validation
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InvalidRows {
+    // Import nothing
+    #[default]
+    Reject,
+    // Import the rows that passed and report the rest
+    Skip,
+}
+
+// One problem with one cell. Row numbers are spreadsheet rows, counting the
+// header (and Wholegame's preamble).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub row: usize,
+    pub column: String,
+    pub value: String,
+    pub problem: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub source: String,
+    pub rows_checked: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl InvalidRows {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "reject" => Some(InvalidRows::Reject),
+            "skip" => Some(InvalidRows::Skip),
+            _ => None,
+        }
+    }
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    // Spreadsheet row numbers with at least one problem
+    pub fn invalid_rows(&self) -> BTreeSet<usize> {
+        self.diagnostics.iter().map(|diagnostic| diagnostic.row).collect()
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{} problem(s) in {} of {} {} rows", self.diagnostics.len(), self.invalid_rows().len(), self.rows_checked, self.source)
+    }
+
+    pub fn table_rows(&self) -> Vec<Vec<String>> {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| vec![diagnostic.row.to_string(), diagnostic.column.clone(), diagnostic.value.clone(), diagnostic.problem.clone()])
+            .collect()
+    }
+}
+
+// One CSV for the problems found in any number of files
+pub fn write_csv<W: Write>(writer: W, reports: &[ValidationReport]) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(["Source", "Row", "Column", "Value", "Problem"]).map_err(io::Error::other)?;
+    for report in reports {
+        for row in report.table_rows() {
+            csv.write_record(std::iter::once(&report.source).chain(&row)).map_err(io::Error::other)?;
+        }
+    }
+    csv.flush()
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.summary())?;
+        for diagnostic in &self.diagnostics {
+            write!(f, "\nRow {}, {} '{}': {}", diagnostic.row, diagnostic.column, diagnostic.value, diagnostic.problem)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn is_valid_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !value.chars().any(|c| c.is_whitespace() || matches!(c, ',' | ';' | '<' | '>'))
+        && !domain.contains('@')
+        && domain.contains('.')
+        && domain.split('.').all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'))
+}
+
+fn fan_id_problem(value: &str) -> Option<String> {
+    if value.is_empty() {
+        Some("missing".to_string())
+    } else if !value.chars().all(|c| c.is_ascii_digit()) {
+        Some("not a number".to_string())
+    } else if !FAN_ID_DIGITS.contains(&value.len()) {
+        Some(format!("{} digits, expected {} to {}", value.len(), FAN_ID_DIGITS.start(), FAN_ID_DIGITS.end()))
+    } else {
+        None
+    }
+}
+
+// Collects the problems for one row
+struct RowCheck<'a> {
+    sheet: &'a Sheet,
+    row: &'a [String],
+    number: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl RowCheck<'_> {
+    fn report(&mut self, column: &str, value: &str, problem: impl Into<String>) {
+        self.diagnostics.push(Diagnostic { row: self.number, column: column.to_string(), value: value.to_string(), problem: problem.into() });
+    }
+
+    fn required(&mut self, column: &str) {
+        if self.sheet.cell(self.row, column).is_empty() {
+            self.report(column, "", "missing");
+        }
+    }
+
+    fn date(&mut self, column: &str) {
+        let value = self.sheet.cell(self.row, column);
+        if !value.is_empty() && parse_date(value).is_none() {
+            self.report(column, value, "not a date");
+        }
+    }
+
+    fn amount(&mut self, column: &str) -> Option<f64> {
+        let value = self.sheet.cell(self.row, column);
+        let amount = parse_amount(value);
+        if amount.is_none() {
+            self.report(column, value, "not an amount");
+        }
+        amount
+    }
+
+    fn loveadmin(&mut self) {
+        self.required("Name");
+        self.required("Product");
+        self.required("Date");
+        self.date("Date");
+        for column in LOVEADMIN_DATES {
+            self.date(column);
+        }
+        let amounts: Vec<Option<f64>> = LOVEADMIN_AMOUNTS.iter().map(|column| self.amount(column)).collect();
+        let failed = self.sheet.cell(self.row, "Failed");
+        if parse_whole_number(failed).is_none() {
+            self.report("Failed", failed, "not a number");
+        }
+        let overdue = self.sheet.cell(self.row, "Days Overdue");
+        match parse_whole_number(overdue) {
+            None => self.report("Days Overdue", overdue, "not a number"),
+            Some(days) if days < 0 => self.report("Days Overdue", overdue, "negative"),
+            Some(_) => {}
+        }
+        // Only checked when the export has the breakdown at all
+        let has_breakdown = LOVEADMIN_AMOUNTS[1..].iter().any(|column| self.sheet.has_column(column));
+        if let [Some(invoiced), Some(paid), Some(pending), Some(outstanding)] = amounts[..] {
+            let total = paid + pending + outstanding;
+            if has_breakdown && (total - invoiced).abs() > AMOUNT_TOLERANCE {
+                let value = self.sheet.cell(self.row, "Invoiced").to_string();
+                self.report("Invoiced", &value, format!("paid + pending + outstanding comes to {:.2}, not {:.2}", total, invoiced));
+            }
+        }
+    }
+
+    fn wholegame(&mut self) {
+        self.required("First names");
+        self.required("Surname");
+        let fan_id = self.sheet.cell(self.row, "FAN ID");
+        if let Some(problem) = fan_id_problem(fan_id) {
+            self.report("FAN ID", fan_id, problem);
+        }
+        for column in WHOLEGAME_DATES {
+            self.date(column);
+        }
+        for column in WHOLEGAME_EMAILS {
+            let value = self.sheet.cell(self.row, column);
+            if !value.is_empty() && !is_valid_email(value) {
+                self.report(column, value, "not an email address");
+            }
+        }
+    }
+}
+
+// Checks every row of an export whose header has already passed the format check
+pub fn validate_sheet(source: &str, sheet: &Sheet) -> ValidationReport {
+    let mut diagnostics = Vec::new();
+    for (index, row) in sheet.rows.iter().enumerate() {
+        let mut check = RowCheck { sheet, row, number: sheet.row_number(index), diagnostics: Vec::new() };
+        match source {
+            LOVEADMIN_SOURCE => check.loveadmin(),
+            WHOLEGAME_SOURCE => check.wholegame(),
+            _ => {}
+        }
+        diagnostics.append(&mut check.diagnostics);
+    }
+    ValidationReport { source: source.to_string(), rows_checked: sheet.rows.len(), diagnostics }
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::*;
+    use crate::import::read_csv;
+
+    #[test]
+    fn test_loveadmin_rows_diagnosed() {
+        let csv = "Name,Product,Date,Invoiced,Paid,Pending,Outstanding,Failed,Days Overdue,Last Reminder Sent\n\
+                   Amy Doe,U12 Reds (*),2023-10-01,£20.00,10,0,10,0,0,\n\
+                   Ben Doe,U12 Reds (*),soon,20,10,0,5,0,-3,\n\
+                   ,U12 Reds (*),2023-10-01,twenty,0,0,0,x,0,2023-10-20\n";
+        let report = validate_sheet(LOVEADMIN_SOURCE, &read_csv(csv.as_bytes()).unwrap());
+        let found: Vec<(usize, &str, &str, &str)> =
+            report.diagnostics.iter().map(|d| (d.row, d.column.as_str(), d.value.as_str(), d.problem.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "Date", "soon", "not a date"),
+                (3, "Days Overdue", "-3", "negative"),
+                (3, "Invoiced", "20", "paid + pending + outstanding comes to 15.00, not 20.00"),
+                (4, "Name", "", "missing"),
+                (4, "Invoiced", "twenty", "not an amount"),
+                (4, "Failed", "x", "not a number"),
+            ]
+        );
+        assert_eq!(report.invalid_rows(), BTreeSet::from([3, 4]));
+        assert_eq!(report.summary(), "6 problem(s) in 2 of 3 loveadmin rows");
+
+        // Without the breakdown columns there is nothing to reconcile the total with
+        let invoiced_only = read_csv("Name,Product,Date,Invoiced\nAmy Doe,U12 Reds (*),2023-10-01,20\n".as_bytes()).unwrap();
+        assert!(validate_sheet(LOVEADMIN_SOURCE, &invoiced_only).is_clean());
+    }
+
+    #[test]
+    fn test_wholegame_rows_diagnosed() {
+        let csv = "First names,Surname,FAN ID,Date of Birth,Email Address,Parent/Carer Email Address\n\
+                   Amy,Doe,123456789,2012-09-04,amy@example.com,\n\
+                   Ben,Doe,12345,31/02/2012,ben@example,jo doe@example.com\n\
+                   Cal,Doe,1234567AB,,,\n";
+        let report = validate_sheet(WHOLEGAME_SOURCE, &read_csv(csv.as_bytes()).unwrap());
+        let found: Vec<(usize, &str, &str)> = report.diagnostics.iter().map(|d| (d.row, d.column.as_str(), d.problem.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "FAN ID", "5 digits, expected 8 to 10"),
+                (3, "Date of Birth", "not a date"),
+                (3, "Email Address", "not an email address"),
+                (3, "Parent/Carer Email Address", "not an email address"),
+                (4, "FAN ID", "not a number"),
+            ]
+        );
+
+        let mut written = Vec::new();
+        write_csv(&mut written, &[report]).unwrap();
+        assert!(String::from_utf8(written).unwrap().starts_with("Source,Row,Column,Value,Problem\nwholegame,3,FAN ID,12345,\"5 digits, expected 8 to 10\"\n"));
+    }
+
+    #[test]
+    fn test_email_syntax() {
+        for valid in ["jo@example.com", "jo.doe+club@mail.example.co.uk"] {
+            assert!(is_valid_email(valid), "{}", valid);
+        }
+        for invalid in ["jo", "jo@", "@example.com", "jo@example", "jo@@example.com", "jo@example..com", "jo doe@example.com", "jo@-example.com"] {
+            assert!(!is_valid_email(invalid), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::NaiveDate;
+use rusqlite::Connection;
+
+use crate::api;
+use crate::compliance;
+use crate::database::{get_all_loveadmin, get_all_wholegame, get_import_batches, reopen_exception, resolve_exception};
+use crate::export_schema::ColumnAliases;
+use crate::http::{escape_html, url_encode, Request, Response};
+use crate::import::{self, ImportError, LOVEADMIN_SOURCE, WHOLEGAME_SOURCE};
+use crate::matching::teams_match;
+use crate::reconciliation::{self, ExceptionFilter, ExceptionKind, TrackedException, EXCEPTION_KINDS};
+use crate::roster::{self, DEFAULT_COLUMNS};
+use crate::validation::InvalidRows;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:1em 2em}nav a{margin-right:1em}\
+    table{border-collapse:collapse;width:100%;margin-top:1em}th,td{border:1px solid #ccc;padding:4px;text-align:left}\
+    th{background:#eee}form.inline{display:inline}fieldset{margin-bottom:1em}.resolved{color:#888}";
+
+// Uploads send the raw file as the request body so no multipart parsing is needed.
+// Reconciliation is re-run after each one, so the pages only ever read it.
+const UPLOAD_SCRIPT: &str = "function upload(source){\
+    const input=document.getElementById(source);if(!input.files.length){return;}const file=input.files[0];\
+    const status=document.getElementById(source+'-status');status.textContent='Uploading...';\
+    const invalid=document.getElementById(source+'-skip').checked?'&invalid=skip':'';\
+    fetch('/upload/'+source+'?file='+encodeURIComponent(file.name)+invalid,{method:'POST',body:file,headers:{'X-Form-Token':formToken}})\
+    .then(r=>r.text().then(t=>{status.textContent=t;if(r.ok){setTimeout(()=>location.reload(),1000);}}));}";
+
+// Settings the pages need that don't come from the request
+pub struct WebOptions {
+    pub reference_date: NaiveDate,
+    pub photo_max_years: u32,
+    // Required on every request when set
+    pub access_token: Option<String>,
+    // Header renames applied to uploaded exports
+    pub aliases: ColumnAliases,
+    // Host headers the server answers to, e.g. "127.0.0.1:8080", so a
+    // rebound DNS name can't reach it
+    pub hosts: Vec<String>,
+    // Random for each run; the page forms and uploads send it back
+    pub form_token: String,
+}
+
+pub fn new_form_token() -> String {
+    uuid::Uuid::new_v4().to_simple().to_string()
+}
+
+fn page(title: &str, body: &str) -> Response {
+    Response::html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title} - LoveAdmin tool</title><style>{STYLE}</style></head>\
+         <body><nav><a href=\"/\">Home</a><a href=\"/reconciliation\">Reconciliation</a></nav><h1>{title}</h1>{body}</body></html>",
+        title = escape_html(title),
+        body = body
+    ))
+}
+
+fn teams(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let teams: BTreeSet<String> = get_all_wholegame(conn)?.iter().map(|player| player.get_team().trim().to_string()).filter(|team| !team.is_empty()).collect();
+    Ok(teams.into_iter().collect())
+}
+
+fn team_options(teams: &[String], selected: Option<&str>) -> String {
+    let mut options = String::from("<option value=\"\">All teams</option>");
+    for team in teams {
+        let chosen = if Some(team.as_str()) == selected { " selected" } else { "" };
+        options.push_str(&format!("<option{}>{}</option>", chosen, escape_html(team)));
+    }
+    options
+}
+
+fn dashboard(conn: &Connection, options: &WebOptions) -> Result<Response, Box<dyn Error>> {
+    let players = get_all_wholegame(conn)?;
+    let invoices = get_all_loveadmin(conn)?;
+    let tracked = reconciliation::tracked(conn)?;
+    let open = tracked.iter().filter(|tracked| tracked.status.is_active(options.reference_date)).count();
+    let batches = get_import_batches(conn)?;
+    let last_import = |source: &str| match batches.iter().find(|batch| batch.source == source) {
+        Some(batch) => format!(
+            "last imported {} from {}, {} rows, batch {}",
+            escape_html(&batch.imported_at),
+            escape_html(&batch.file_name),
+            batch.row_count,
+            batch.id
+        ),
+        None => "never imported".to_string(),
+    };
+
+    let mut body = format!(
+        "<p>{} Wholegame players ({}), {} LoveAdmin invoices ({}), <a href=\"/reconciliation\">{} open exceptions</a>.</p>",
+        players.len(),
+        last_import(WHOLEGAME_SOURCE),
+        invoices.len(),
+        last_import(LOVEADMIN_SOURCE),
+        open
+    );
+    body.push_str("<h2>Upload exports</h2><p>Uploading a file replaces everything previously imported from that system.</p>");
+    for (source, label, accept) in [(LOVEADMIN_SOURCE, "LoveAdmin CSV", ".csv"), (WHOLEGAME_SOURCE, "Wholegame XLSX", ".xlsx")] {
+        body.push_str(&format!(
+            "<fieldset><legend>{label}</legend><input type=\"file\" id=\"{source}\" accept=\"{accept}\"> \
+             <label><input type=\"checkbox\" id=\"{source}-skip\"> Import valid rows only</label> \
+             <button onclick=\"upload('{source}')\">Upload</button> <span id=\"{source}-status\" style=\"white-space: pre-line\"></span></fieldset>"
+        ));
+    }
+    body.push_str(&format!("<script>const formToken=\"{}\";{}</script>", options.form_token, UPLOAD_SCRIPT));
+
+    let options = team_options(&teams(conn)?, None);
+    body.push_str(&format!(
+        "<h2>Reports</h2><ul><li><a href=\"/reports/reconciliation.csv\">Reconciliation exceptions (CSV)</a></li>\
+         <li><a href=\"/reports/compliance.csv\">Safeguarding compliance (CSV)</a></li></ul>\
+         <form action=\"/reports/roster.xlsx\">Team rosters: <select name=\"team\">{options}</select> \
+         <button>XLSX</button> <button formaction=\"/reports/roster.html\">Printable</button></form>"
+    ));
+    Ok(page("LoveAdmin tool", &body))
+}
+
+fn upload(conn: &Connection, request: &Request, source: &str, options: &WebOptions) -> Result<Response, Box<dyn Error>> {
+    let file_name = request.param("file").unwrap_or("upload");
+    let invalid_rows = request.param("invalid").and_then(InvalidRows::parse).unwrap_or_default();
+    match import::import_file(conn, source, &request.body, file_name, &options.aliases, invalid_rows) {
+        Ok(summary) => {
+            reconciliation::refresh(conn, options.reference_date, options.photo_max_years)?;
+            let mut text = format!("Imported {} rows from {} (batch {}, {})", summary.rows, file_name, summary.batch, summary.schema);
+            for warning in summary.schema.warnings() {
+                text.push_str(&format!("\nWarning: {}", warning));
+            }
+            if !summary.validation.is_clean() {
+                text.push_str(&format!("\nSkipped: {}", summary.validation));
+            }
+            Ok(Response::text(200, &text))
+        }
+        Err(e @ (ImportError::Invalid(_) | ImportError::Rejected(_))) => Ok(Response::text(400, &format!("Cannot import {}: {}", file_name, e))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn exception_filter(request: &Request) -> ExceptionFilter {
+    ExceptionFilter {
+        kind: request.param("kind").and_then(ExceptionKind::parse),
+        team: request.param("team").map(String::from),
+        search: request.param("q").map(String::from),
+        assignee: request.param("assignee").map(String::from),
+        include_closed: request.param("all").is_some(),
+    }
+}
+
+fn filtered_exceptions(conn: &Connection, request: &Request, options: &WebOptions) -> Result<Vec<TrackedException>, Box<dyn Error>> {
+    let filter = exception_filter(request);
+    let mut tracked = reconciliation::tracked(conn)?;
+    tracked.retain(|tracked| filter.matches(tracked, options.reference_date));
+    Ok(tracked)
+}
+
+// The page's own query string, so downloads and resolve buttons keep the filters
+fn query_string(request: &Request) -> String {
+    let mut pairs: Vec<String> = ["kind", "team", "q", "assignee", "all"]
+        .iter()
+        .filter_map(|name| request.param(name).map(|value| format!("{}={}", name, url_encode(value))))
+        .collect();
+    pairs.sort();
+    if pairs.is_empty() { String::new() } else { format!("?{}", pairs.join("&")) }
+}
+
+fn reconciliation_page(conn: &Connection, request: &Request, options: &WebOptions) -> Result<Response, Box<dyn Error>> {
+    let exceptions = filtered_exceptions(conn, request, options)?;
+    let filter = exception_filter(request);
+    let query = query_string(request);
+
+    let mut kinds = String::from("<option value=\"\">All exceptions</option>");
+    for kind in EXCEPTION_KINDS {
+        let chosen = if filter.kind == Some(kind) { " selected" } else { "" };
+        kinds.push_str(&format!("<option value=\"{}\"{}>{}</option>", kind.code(), chosen, kind));
+    }
+    let mut body = format!(
+        "<form><select name=\"kind\">{}</select> <select name=\"team\">{}</select> \
+         <input name=\"q\" placeholder=\"Search\" value=\"{}\"> <label><input type=\"checkbox\" name=\"all\" value=\"1\"{}> include closed</label> \
+         <button>Filter</button></form><p>{} exceptions. <a href=\"/reports/reconciliation.csv{}\">Download CSV</a> \
+         <form class=\"inline\" method=\"post\" action=\"/reconciliation/run\"><input type=\"hidden\" name=\"back\" value=\"/reconciliation{}\">\
+         <input type=\"hidden\" name=\"token\" value=\"{}\"><button>Re-run reconciliation</button></form></p>",
+        kinds,
+        team_options(&teams(conn)?, filter.team.as_deref()),
+        escape_html(filter.search.as_deref().unwrap_or("")),
+        if filter.include_closed { " checked" } else { "" },
+        exceptions.len(),
+        escape_html(&query),
+        escape_html(&query),
+        options.form_token
+    );
+    body.push_str("<table><tr><th>Exception</th><th>Team</th><th>Name</th><th>FAN ID</th><th>Detail</th><th>Status</th><th>Assignee</th><th></th></tr>");
+    for tracked in &exceptions {
+        let exception = &tracked.exception;
+        let key = exception.key();
+        let is_resolved = !tracked.status.is_active(options.reference_date);
+        body.push_str(&format!(
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>\
+             <form class=\"inline\" method=\"post\" action=\"/reconciliation/resolve\">\
+             <input type=\"hidden\" name=\"key\" value=\"{}\"><input type=\"hidden\" name=\"action\" value=\"{}\">\
+             <input type=\"hidden\" name=\"back\" value=\"/reconciliation{}\"><input type=\"hidden\" name=\"token\" value=\"{}\">\
+             <button>{}</button></form></td></tr>",
+            if is_resolved { " class=\"resolved\"" } else { "" },
+            exception.kind,
+            escape_html(&exception.team),
+            escape_html(&exception.name),
+            escape_html(exception.fan_id.as_deref().unwrap_or("")),
+            escape_html(&exception.detail),
+            escape_html(&tracked.status_label()),
+            escape_html(tracked.assignee.as_deref().unwrap_or("")),
+            escape_html(&key),
+            if is_resolved { "reopen" } else { "resolve" },
+            escape_html(&query),
+            options.form_token,
+            if is_resolved { "Reopen" } else { "Resolve" },
+        ));
+    }
+    body.push_str("</table>");
+    Ok(page("Reconciliation", &body))
+}
+
+fn resolve(conn: &Connection, request: &Request) -> Result<Response, Box<dyn Error>> {
+    let form = request.form();
+    let key = match form.get("key") {
+        Some(key) if !key.is_empty() => key,
+        _ => return Ok(Response::text(400, "Missing exception key")),
+    };
+    if form.get("action").map(String::as_str) == Some("reopen") {
+        reopen_exception(conn, key)?;
+    } else {
+        resolve_exception(conn, key, &chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string())?;
+    }
+    Ok(back_to_reconciliation(&form))
+}
+
+// Only ever send the browser back to the reconciliation page
+fn back_to_reconciliation(form: &HashMap<String, String>) -> Response {
+    let back = form.get("back").filter(|back| back.starts_with("/reconciliation")).map(String::as_str).unwrap_or("/reconciliation");
+    Response::redirect(back)
+}
+
+// Picks up changes made since the last upload, e.g. from the command line
+fn rerun(conn: &Connection, request: &Request, options: &WebOptions) -> Result<Response, Box<dyn Error>> {
+    reconciliation::refresh(conn, options.reference_date, options.photo_max_years)?;
+    Ok(back_to_reconciliation(&request.form()))
+}
+
+fn reconciliation_csv(conn: &Connection, request: &Request, options: &WebOptions) -> Result<Response, Box<dyn Error>> {
+    let exceptions = filtered_exceptions(conn, request, options)?;
+    let mut csv = Vec::new();
+    reconciliation::write_csv(&mut csv, &exceptions.iter().collect::<Vec<&TrackedException>>())?;
+    Ok(Response::download("text/csv", "reconciliation.csv", csv))
+}
+
+fn compliance_csv(conn: &Connection, options: &WebOptions) -> Result<Response, Box<dyn Error>> {
+    let results = compliance::check_all(&get_all_wholegame(conn)?, &get_all_loveadmin(conn)?, options.reference_date, options.photo_max_years);
+    let mut csv = Vec::new();
+    compliance::write_csv(&mut csv, &results)?;
+    Ok(Response::download("text/csv", "compliance.csv", csv))
+}
+
+fn roster_report(conn: &Connection, request: &Request, options: &WebOptions, printable: bool) -> Result<Response, Box<dyn Error>> {
+    let mut rosters = roster::build_rosters(&get_all_wholegame(conn)?, &get_all_loveadmin(conn)?, options.reference_date, options.photo_max_years);
+    if let Some(team) = request.param("team") {
+        rosters.retain(|name, _| teams_match(name, team));
+    }
+    if printable {
+        let mut html = Vec::new();
+        let title = format!("Team roster at {}", options.reference_date.format("%d/%m/%Y"));
+        roster::write_html(&mut html, &rosters, &DEFAULT_COLUMNS, &title)?;
+        return Ok(Response::new(200, "text/html; charset=utf-8", html));
+    }
+    let xlsx = roster::xlsx_bytes(&rosters, &DEFAULT_COLUMNS)?;
+    Ok(Response::download("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", "rosters.xlsx", xlsx))
+}
+
+// "club.example" and "club.example:80" are the same host
+fn with_port(host: &str) -> String {
+    let host = host.trim().to_lowercase();
+    let has_port = host.rsplit_once(':').is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit()));
+    if has_port { host } else { format!("{}:80", host) }
+}
+
+fn known_host(host: &str, options: &WebOptions) -> bool {
+    options.hosts.iter().any(|allowed| with_port(allowed) == with_port(host))
+}
+
+// Browsers say where a request comes from; scripts usually don't
+fn same_origin(request: &Request, options: &WebOptions) -> bool {
+    match request.header("origin") {
+        Some(origin) => origin.strip_prefix("http://").is_some_and(|host| known_host(host, options)),
+        None => true,
+    }
+}
+
+// Uploads send the token as a header, forms as a field
+fn has_form_token(request: &Request, options: &WebOptions) -> bool {
+    match request.header("x-form-token") {
+        Some(token) => token == options.form_token,
+        None => request.form().get("token") == Some(&options.form_token),
+    }
+}
+
+fn is_api(request: &Request) -> bool {
+    request.path == "/api" || request.path.starts_with("/api/")
+}
+
+// Scripts send "Authorization: Bearer <token>"; browsers log in with any
+// user name and the token as the password
+fn authorised(request: &Request, options: &WebOptions) -> bool {
+    let Some(token) = &options.access_token else { return true };
+    let Some(value) = request.header("authorization") else { return false };
+    if let Some(bearer) = value.strip_prefix("Bearer ") {
+        return bearer == token;
+    }
+    value
+        .strip_prefix("Basic ")
+        .and_then(|encoded| BASE64.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .is_some_and(|credentials| credentials.split_once(':').is_some_and(|(_, password)| password == token))
+}
+
+fn unauthorised(request: &Request) -> Response {
+    if is_api(request) {
+        return api::unauthorised();
+    }
+    let mut response = Response::text(401, "Log in with any user name and the access token as the password");
+    response.headers.push(("WWW-Authenticate".to_string(), "Basic realm=\"LoveAdmin tool\"".to_string()));
+    response
+}
+
+fn route(conn: &Connection, request: &Request, options: &WebOptions) -> Result<Response, Box<dyn Error>> {
+    if !request.header("host").is_some_and(|host| known_host(host, options)) {
+        return Ok(Response::text(421, "Unknown host"));
+    }
+    if request.method != "GET" && !same_origin(request, options) {
+        return Ok(Response::text(403, "Cross-site request refused"));
+    }
+    if !authorised(request, options) {
+        return Ok(unauthorised(request));
+    }
+    if is_api(request) {
+        return Ok(api::handle(conn, request, options));
+    }
+    if request.method == "POST" && !has_form_token(request, options) {
+        return Ok(Response::text(403, "Missing or out of date form token; reload the page and try again"));
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => dashboard(conn, options),
+        ("POST", "/upload/loveadmin") => upload(conn, request, LOVEADMIN_SOURCE, options),
+        ("POST", "/upload/wholegame") => upload(conn, request, WHOLEGAME_SOURCE, options),
+        ("GET", "/reconciliation") => reconciliation_page(conn, request, options),
+        ("POST", "/reconciliation/resolve") => resolve(conn, request),
+        ("POST", "/reconciliation/run") => rerun(conn, request, options),
+        ("GET", "/reports/reconciliation.csv") => reconciliation_csv(conn, request, options),
+        ("GET", "/reports/compliance.csv") => compliance_csv(conn, options),
+        ("GET", "/reports/roster.xlsx") => roster_report(conn, request, options, false),
+        ("GET", "/reports/roster.html") => roster_report(conn, request, options, true),
+        _ => Ok(Response::not_found()),
+    }
+}
+
+pub fn handle(conn: &Connection, request: &Request, options: &WebOptions) -> Response {
+    route(conn, request, options).unwrap_or_else(|e| Response::text(500, &format!("Error: {}", e)))
+}
+
+#[cfg(test)]
+mod web_tests {
+    use super::*;
+    use crate::database::{create_tables, setup_database};
+    use crate::dates::parse_date;
+
+    fn database() -> Connection {
+        let conn = setup_database(Some(":memory:")).unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn options() -> WebOptions {
+        WebOptions {
+            reference_date: parse_date("2023-10-15").unwrap(),
+            photo_max_years: 3,
+            access_token: None,
+            aliases: ColumnAliases::new(),
+            hosts: vec!["127.0.0.1:8080".to_string(), "localhost:8080".to_string()],
+            form_token: "t0ken".to_string(),
+        }
+    }
+
+    fn request(method: &str, url: &str, body: Vec<u8>) -> Request {
+        Request::new(method, url, body).with_header("Host", "127.0.0.1:8080")
+    }
+
+    fn upload(url: &str, body: &[u8]) -> Request {
+        request("POST", url, body.to_vec()).with_header("X-Form-Token", "t0ken")
+    }
+
+    fn get(conn: &Connection, url: &str) -> Response {
+        handle(conn, &request("GET", url, Vec::new()), &options())
+    }
+
+    fn text(response: &Response) -> String {
+        String::from_utf8(response.body.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_upload_reconcile_and_resolve() {
+        let conn = database();
+        let csv = "Name,Account Owner,Product,Date,Invoiced\nAmy Doe,Jo Doe,U12 Reds (*),2023-10-01,20\nZed Doe,Jo Doe,U14 Blues (*),2023-10-01,20\n";
+        let uploaded = handle(&conn, &upload("/upload/loveadmin?file=invoices.csv", csv.as_bytes()), &options());
+        assert_eq!(uploaded.status, 200, "{}", text(&uploaded));
+        assert!(text(&uploaded).starts_with("Imported 2 rows from invoices.csv"));
+
+        let bad = handle(&conn, &upload("/upload/wholegame?file=players.xlsx", b"not a workbook"), &options());
+        assert_eq!(bad.status, 400);
+
+        let home = get(&conn, "/");
+        assert!(text(&home).contains("2 LoveAdmin invoices (last imported"));
+        assert!(text(&home).contains("2 open exceptions"));
+
+        let page = get(&conn, "/reconciliation?q=zed");
+        assert!(text(&page).contains("1 exceptions."));
+        assert!(text(&page).contains("href=\"/reports/reconciliation.csv?q=zed\""));
+
+        let resolved = handle(
+            &conn,
+            &request("POST", "/reconciliation/resolve", b"key=not-in-wholegame%3Azed+doe%3Au14+blues&back=%2Freconciliation%3Fq%3Dzed&token=t0ken".to_vec()),
+            &options(),
+        );
+        assert_eq!(resolved.status, 303);
+        assert_eq!(resolved.headers, vec![("Location".to_string(), "/reconciliation?q=zed".to_string())]);
+        assert!(text(&get(&conn, "/reconciliation?q=zed")).contains("0 exceptions."));
+        assert!(text(&get(&conn, "/reconciliation?q=zed&all=1")).contains(">Reopen<"));
+
+        let csv = get(&conn, "/reports/reconciliation.csv?all=1");
+        assert_eq!(csv.headers[0].1, "attachment; filename=\"reconciliation.csv\"");
+        assert_eq!(text(&csv).lines().count(), 3);
+
+        // Pages only read; a change made elsewhere shows once reconciliation is re-run
+        let audit_entries = || -> i64 { conn.query_row("SELECT COUNT(*) FROM audit_log", [], |row| row.get(0)).unwrap() };
+        let before = audit_entries();
+        crate::database::insert_loveadmin(&conn, &crate::fixtures::invoice("Yan Doe", "U14 Blues (*)", "2023-10-01", 20.0)).unwrap();
+        for url in ["/", "/reconciliation", "/reports/reconciliation.csv"] {
+            assert_eq!(get(&conn, url).status, 200);
+        }
+        assert_eq!(audit_entries(), before + 1);
+        assert!(text(&get(&conn, "/")).contains("1 open exceptions"));
+        assert!(text(&get(&conn, "/reconciliation")).contains("action=\"/reconciliation/run\""));
+        let rerun = handle(&conn, &request("POST", "/reconciliation/run", b"back=%2Freconciliation&token=t0ken".to_vec()), &options());
+        assert_eq!(rerun.status, 303);
+        assert!(text(&get(&conn, "/")).contains("2 open exceptions"));
+    }
+
+    #[test]
+    fn test_reports_and_unknown_paths() {
+        let conn = database();
+        let roster = get(&conn, "/reports/roster.xlsx");
+        assert_eq!(roster.status, 200);
+        assert!(roster.body.starts_with(b"PK"));
+        assert!(text(&get(&conn, "/reports/roster.html")).starts_with("<!DOCTYPE html>"));
+        assert!(text(&get(&conn, "/reports/compliance.csv")).starts_with("Team,RAG,FAN ID,Player,Issues"));
+        assert_eq!(get(&conn, "/nowhere").status, 404);
+        assert_eq!(handle(&conn, &request("GET", "/upload/loveadmin", Vec::new()), &options()).status, 404);
+    }
+
+    #[test]
+    fn test_access_token_guards_every_route() {
+        let conn = database();
+        let secured = WebOptions { access_token: Some("s3cret".to_string()), ..options() };
+        let post = || upload("/upload/loveadmin?file=invoices.csv", b"Name\n");
+        for unauthenticated in [request("GET", "/reports/compliance.csv", Vec::new()), request("GET", "/", Vec::new()), post()] {
+            let response = handle(&conn, &unauthenticated, &secured);
+            assert_eq!(response.status, 401);
+            assert_eq!(response.headers[0].0, "WWW-Authenticate");
+        }
+        let api = handle(&conn, &request("GET", "/api/players", Vec::new()), &secured);
+        assert_eq!((api.status, api.content_type.as_str()), (401, "application/json"));
+        let wrong = request("GET", "/", Vec::new()).with_header("Authorization", "Bearer guess");
+        assert_eq!(handle(&conn, &wrong, &secured).status, 401);
+
+        let bearer = request("GET", "/api/players", Vec::new()).with_header("Authorization", "Bearer s3cret");
+        assert_eq!(handle(&conn, &bearer, &secured).status, 200);
+        let basic = format!("Basic {}", BASE64.encode("anyone:s3cret"));
+        assert_eq!(handle(&conn, &request("GET", "/reports/compliance.csv", Vec::new()).with_header("Authorization", &basic), &secured).status, 200);
+        assert_ne!(handle(&conn, &post().with_header("Authorization", &basic), &secured).status, 401);
+    }
+
+    #[test]
+    fn test_unknown_hosts_and_cross_site_posts_are_refused() {
+        let conn = database();
+        let rebound = Request::new("GET", "/reports/compliance.csv", Vec::new()).with_header("Host", "attacker.example:8080");
+        assert_eq!(handle(&conn, &rebound, &options()).status, 421);
+        assert_eq!(handle(&conn, &Request::new("GET", "/", Vec::new()), &options()).status, 421);
+        assert_eq!(handle(&conn, &request("GET", "/", Vec::new()).with_header("Host", "LOCALHOST:8080"), &options()).status, 200);
+
+        let page = text(&get(&conn, "/"));
+        assert!(page.contains("const formToken=\"t0ken\""));
+        let resolve = |body: &str| request("POST", "/reconciliation/resolve", body.as_bytes().to_vec());
+        assert_eq!(handle(&conn, &resolve("key=a"), &options()).status, 403);
+        assert_eq!(handle(&conn, &resolve("key=a&token=guess"), &options()).status, 403);
+        assert_eq!(handle(&conn, &resolve("key=a&token=t0ken"), &options()).status, 303);
+        let stale = request("POST", "/upload/loveadmin?file=a.csv", b"Name\n".to_vec()).with_header("X-Form-Token", "old");
+        assert_eq!(handle(&conn, &stale, &options()).status, 403);
+
+        let cross_site = upload("/upload/loveadmin?file=a.csv", b"Name\n").with_header("Origin", "http://attacker.example");
+        assert_eq!(handle(&conn, &cross_site, &options()).status, 403);
+        let api = request("POST", "/api/imports/loveadmin?file=a.csv", b"Name\n".to_vec()).with_header("Origin", "http://attacker.example");
+        assert_eq!(handle(&conn, &api, &options()).status, 403);
+        let same_site = upload("/upload/loveadmin?file=a.csv", b"Name\n").with_header("Origin", "http://127.0.0.1:8080");
+        assert_ne!(handle(&conn, &same_site, &options()).status, 403);
+    }
+}